@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xm5_thing::protocols::frame::{Frame, FrameDecoder};
+
+// whatever comes off the air: no panics, and anything that parses encodes back the same
+fuzz_target!(|data: &[u8]| {
+    let _ = Frame::try_from(data);
+
+    let mut decoder = FrameDecoder::new();
+    for frame in decoder.push(data).into_iter().flatten() {
+        let bytes: Vec<u8> = frame.clone().into();
+        let again: Vec<_> = FrameDecoder::new().push(&bytes);
+        assert_eq!(again, vec![Ok(frame)]);
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xm5_thing::protocols::frame::{Frame, FrameDataType, FrameDecoder, MAX_FRAME_SIZE};
+
+// Frame -> Vec<u8> -> Frame for every data type, whatever the content and however it's split up
+fuzz_target!(|data: &[u8]| {
+    let [data_type, sequence_number, split, content @ ..] = data else {
+        return;
+    };
+    let Ok(data_type) = FrameDataType::try_from(*data_type) else {
+        return;
+    };
+    // start, header, checksum and end on top of the content
+    let content = &content[..content.len().min(MAX_FRAME_SIZE - 8)];
+
+    let frame = Frame::new(data_type, *sequence_number, content);
+    let bytes: Vec<u8> = frame.clone().into();
+
+    let split = *split as usize % bytes.len();
+    let mut decoder = FrameDecoder::new();
+    let mut frames = decoder.push(&bytes[..split]);
+    frames.extend(decoder.push(&bytes[split..]));
+    assert_eq!(frames, vec![Ok(frame)]);
+});
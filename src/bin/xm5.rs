@@ -0,0 +1,865 @@
+use std::{process::ExitCode, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use xm5_thing::{
+    constant::SONY_SOME_SERVICE_UUID,
+    debug,
+    platforms::{self, MacAddress, PlatformDeviceCommunication},
+    protocols::{
+        adaptive::{default_profiles_path, ActivityProfile, AdaptiveProfiles},
+        battery::{
+            default_history_path, BatteryEvent, BatteryHistory, BatteryMonitor,
+            BatteryMonitorConfig,
+        },
+        connection::{HeadphoneAppCommand, HeadphoneConnection},
+        mdr::{
+            AssignableKey, AssignablePreset, AutoPowerOff, ConnectionMode, DetectedActivity,
+            EqPreset, NcAsmMode, NcAsmParam, SmartTalkingConfig, SmartTalkingSensitivity,
+            SmartTalkingTimeout, EQ_CUSTOM_BAND_COUNT, MAX_AMBIENT_LEVEL,
+        },
+        properties::{BatteryLevel, HeadphoneProperties},
+        wearing::{wear_events, WearEvent},
+    },
+};
+
+const USAGE: &str = "\
+usage: xm5 [--json] [--verbose] <command>
+
+commands:
+  status                        everything we know about the headphone
+  battery                       levels and time left
+  watch                         print low battery warnings and wear changes as they happen
+  devices                       paired sources
+  buttons                       what the NC/AMB button and touch panel do
+  button <key> <function>       nc-button|touch, ambient|google|alexa|playback|volume|voice|off
+  nc on|off|wind
+  nc ambient [0-20] [--voice]
+  eq preset <name>              e.g. off, bright, bass-boost, custom
+  eq bands <5 levels in dB>
+  eq clear-bass <level>
+  switch <mac>                  play from another connected source
+  connect|disconnect|unpair <mac>
+  pin on|off
+  pause-when-removed on|off
+  speak-to-chat on|off
+  speak-to-chat config <sensitivity> <timeout> [--voice]
+                                auto|high|low, short|standard|long|off
+  dsee on|off                   upscaling
+  quality sound|stable          what the bluetooth link prioritises
+  auto-off <when>               5m|30m|1h|3h|removed|never
+  power-off
+  adaptive on|off               Adaptive Sound Control
+  adaptive profiles             noise control used for each activity
+  adaptive <activity> on|off|wind|ambient [0-20] [--voice]
+                                staying|walking|running|transport
+
+Goes through `xm5-thing daemon` if it's running, connects on its own otherwise";
+
+// for scripts
+const EXIT_FAILED: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+/// No daemon and no headphone either
+const EXIT_UNAVAILABLE: u8 = 3;
+
+const ACTIVITIES: &[(&str, DetectedActivity)] = &[
+    ("staying", DetectedActivity::Staying),
+    ("walking", DetectedActivity::Walking),
+    ("running", DetectedActivity::Running),
+    ("transport", DetectedActivity::Transport),
+];
+
+const AUTO_POWER_OFF: &[(&str, AutoPowerOff)] = &[
+    ("5m", AutoPowerOff::After5Minutes),
+    ("30m", AutoPowerOff::After30Minutes),
+    ("1h", AutoPowerOff::After1Hour),
+    ("3h", AutoPowerOff::After3Hours),
+    ("removed", AutoPowerOff::WhenRemoved),
+    ("never", AutoPowerOff::Off),
+];
+
+const BUTTON_KEYS: &[(&str, AssignableKey)] = &[
+    ("nc-button", AssignableKey::NcAmbButton),
+    ("touch", AssignableKey::TouchPanel),
+];
+
+const BUTTON_PRESETS: &[(&str, AssignablePreset)] = &[
+    ("ambient", AssignablePreset::AmbientSoundControl),
+    ("google", AssignablePreset::GoogleAssistant),
+    ("alexa", AssignablePreset::AmazonAlexa),
+    ("playback", AssignablePreset::PlaybackControl),
+    ("volume", AssignablePreset::VolumeControl),
+    ("voice", AssignablePreset::VoiceRecognition),
+    ("off", AssignablePreset::NoFunction),
+];
+
+const EQ_PRESETS: &[(&str, EqPreset)] = &[
+    ("off", EqPreset::Off),
+    ("rock", EqPreset::Rock),
+    ("pop", EqPreset::Pop),
+    ("jazz", EqPreset::Jazz),
+    ("dance", EqPreset::Dance),
+    ("edm", EqPreset::Edm),
+    ("rnb-hip-hop", EqPreset::RnbHipHop),
+    ("acoustic", EqPreset::Acoustic),
+    ("bright", EqPreset::Bright),
+    ("excited", EqPreset::Excited),
+    ("mellow", EqPreset::Mellow),
+    ("relaxed", EqPreset::Relaxed),
+    ("vocal", EqPreset::Vocal),
+    ("treble-boost", EqPreset::TrebleBoost),
+    ("bass-boost", EqPreset::BassBoost),
+    ("speech", EqPreset::Speech),
+    ("custom", EqPreset::Manual),
+    ("custom1", EqPreset::Custom1),
+    ("custom2", EqPreset::Custom2),
+];
+
+enum View {
+    Status,
+    Battery,
+    Devices,
+    Buttons,
+}
+
+/// What `watch` prints
+enum WatchEvent {
+    Battery(BatteryEvent),
+    Wear(WearEvent),
+}
+
+enum Action {
+    Show(View),
+    Command(HeadphoneAppCommand),
+    Watch,
+    /// Ours, the headphone isn't involved. `None` shows them
+    Profiles(Option<(DetectedActivity, ActivityProfile)>),
+}
+
+struct Options {
+    json: bool,
+    verbose: bool,
+    action: Action,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Option<Options>> {
+    let mut json = false;
+    let mut verbose = false;
+    let mut voice = false;
+    let mut words = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--verbose" | "-v" => verbose = true,
+            "--voice" => voice = true,
+            "--help" | "-h" => return Ok(None),
+            _ => words.push(arg),
+        }
+    }
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+
+    let nc = |mode, level| HeadphoneAppCommand::SetNcAsm(NcAsmParam::new(mode, level, voice));
+    let mac = |s: &str| s.parse::<MacAddress>();
+
+    let action = match words.as_slice() {
+        ["status"] => Action::Show(View::Status),
+        ["battery"] => Action::Show(View::Battery),
+        ["devices"] => Action::Show(View::Devices),
+        ["buttons"] => Action::Show(View::Buttons),
+        ["button", key, function] => {
+            let Some((_, key)) = BUTTON_KEYS.iter().find(|(n, _)| n == key) else {
+                let names: Vec<_> = BUTTON_KEYS.iter().map(|(n, _)| *n).collect();
+                bail!("Unknown button {key}, try one of {}", names.join(", "));
+            };
+            let Some((_, preset)) = BUTTON_PRESETS.iter().find(|(n, _)| n == function) else {
+                let names: Vec<_> = BUTTON_PRESETS.iter().map(|(n, _)| *n).collect();
+                bail!(
+                    "Unknown button function {function}, try one of {}",
+                    names.join(", ")
+                );
+            };
+            Action::Command(HeadphoneAppCommand::SetAssignment(*key, *preset))
+        }
+        ["watch"] => Action::Watch,
+        ["nc", nc_words @ ..] => {
+            let (mode, level) = parse_nc(nc_words)?;
+            Action::Command(nc(mode, level))
+        }
+        ["eq", "preset", name] => {
+            let name = name.to_lowercase();
+            let Some((_, preset)) = EQ_PRESETS.iter().find(|(n, _)| *n == name) else {
+                let names: Vec<_> = EQ_PRESETS.iter().map(|(n, _)| *n).collect();
+                bail!("Unknown preset {name}, try one of {}", names.join(", "));
+            };
+            Action::Command(HeadphoneAppCommand::SetEqPreset(*preset))
+        }
+        ["eq", "bands", levels @ ..] => {
+            let levels = levels
+                .iter()
+                .map(|level| level.parse::<i8>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let levels: [i8; EQ_CUSTOM_BAND_COUNT] = levels
+                .try_into()
+                .map_err(|_| anyhow!("Expected {EQ_CUSTOM_BAND_COUNT} levels"))?;
+            Action::Command(HeadphoneAppCommand::SetEqBands(levels))
+        }
+        ["eq", "clear-bass", level] => {
+            Action::Command(HeadphoneAppCommand::SetClearBass(level.parse()?))
+        }
+        ["switch", address] => Action::Command(HeadphoneAppCommand::SwitchDevice(mac(address)?)),
+        ["connect", address] => Action::Command(HeadphoneAppCommand::ConnectDevice(mac(address)?)),
+        ["disconnect", address] => {
+            Action::Command(HeadphoneAppCommand::DisconnectDevice(mac(address)?))
+        }
+        ["unpair", address] => Action::Command(HeadphoneAppCommand::UnpairDevice(mac(address)?)),
+        ["pin", "on"] => Action::Command(HeadphoneAppCommand::EnablePinning(true)),
+        ["pin", "off"] => Action::Command(HeadphoneAppCommand::EnablePinning(false)),
+        ["speak-to-chat", "on"] => Action::Command(HeadphoneAppCommand::EnableSpeakToChat(true)),
+        ["speak-to-chat", "off"] => Action::Command(HeadphoneAppCommand::EnableSpeakToChat(false)),
+        ["speak-to-chat", "config", sensitivity, timeout] => {
+            let sensitivity = match sensitivity.to_lowercase().as_str() {
+                "auto" => SmartTalkingSensitivity::Auto,
+                "high" => SmartTalkingSensitivity::High,
+                "low" => SmartTalkingSensitivity::Low,
+                _ => bail!("Unknown sensitivity {sensitivity}, try auto, high or low"),
+            };
+            let timeout = match timeout.to_lowercase().as_str() {
+                "short" => SmartTalkingTimeout::Short,
+                "standard" => SmartTalkingTimeout::Standard,
+                "long" => SmartTalkingTimeout::Long,
+                "off" => SmartTalkingTimeout::Off,
+                _ => bail!("Unknown timeout {timeout}, try short, standard, long or off"),
+            };
+            Action::Command(HeadphoneAppCommand::SetSpeakToChat(SmartTalkingConfig {
+                sensitivity,
+                voice_passthrough: voice,
+                timeout,
+            }))
+        }
+        ["dsee", "on"] => Action::Command(HeadphoneAppCommand::EnableUpscaling(true)),
+        ["dsee", "off"] => Action::Command(HeadphoneAppCommand::EnableUpscaling(false)),
+        ["quality", "sound"] => Action::Command(HeadphoneAppCommand::SetConnectionMode(
+            ConnectionMode::SoundQuality,
+        )),
+        ["quality", "stable"] => Action::Command(HeadphoneAppCommand::SetConnectionMode(
+            ConnectionMode::StableConnection,
+        )),
+        ["auto-off", when] => {
+            let Some((_, setting)) = AUTO_POWER_OFF.iter().find(|(n, _)| n == when) else {
+                let names: Vec<_> = AUTO_POWER_OFF.iter().map(|(n, _)| *n).collect();
+                bail!(
+                    "Unknown auto power off {when}, try one of {}",
+                    names.join(", ")
+                );
+            };
+            Action::Command(HeadphoneAppCommand::SetAutoPowerOff(*setting))
+        }
+        ["pause-when-removed", "on"] => {
+            Action::Command(HeadphoneAppCommand::EnableControlByWearing(true))
+        }
+        ["pause-when-removed", "off"] => {
+            Action::Command(HeadphoneAppCommand::EnableControlByWearing(false))
+        }
+        ["power-off"] => Action::Command(HeadphoneAppCommand::PowerOff),
+        ["adaptive", "on"] => Action::Command(HeadphoneAppCommand::EnableAutoNcAsm(true)),
+        ["adaptive", "off"] => Action::Command(HeadphoneAppCommand::EnableAutoNcAsm(false)),
+        ["adaptive", "profiles"] => Action::Profiles(None),
+        ["adaptive", activity, nc_words @ ..] => {
+            let Some((_, activity)) = ACTIVITIES.iter().find(|(n, _)| n == activity) else {
+                let names: Vec<_> = ACTIVITIES.iter().map(|(n, _)| *n).collect();
+                bail!(
+                    "Unknown activity {activity}, try one of {}",
+                    names.join(", ")
+                );
+            };
+            let (mode, level) = parse_nc(nc_words)?;
+            Action::Profiles(Some((*activity, ActivityProfile::new(mode, level, voice))))
+        }
+        [] => bail!("Missing command"),
+        _ => bail!("Unknown command {}", words.join(" ")),
+    };
+
+    Ok(Some(Options {
+        json,
+        verbose,
+        action,
+    }))
+}
+
+/// What comes after `nc`, without `--voice`
+fn parse_nc(words: &[&str]) -> Result<(NcAsmMode, u8)> {
+    let mode = match words {
+        ["on"] => (NcAsmMode::NoiseCancelling, 0),
+        ["off"] => (NcAsmMode::Off, 0),
+        ["wind"] => (NcAsmMode::WindNoiseReduction, 0),
+        ["ambient"] => (NcAsmMode::AmbientSound, MAX_AMBIENT_LEVEL),
+        ["ambient", level] => {
+            let level: u8 = level.parse()?;
+            if level > MAX_AMBIENT_LEVEL {
+                bail!("Ambient level goes up to {MAX_AMBIENT_LEVEL}");
+            }
+            (NcAsmMode::AmbientSound, level)
+        }
+        _ => bail!(
+            "Unknown noise control {}, try on, off, wind or ambient",
+            words.join(" ")
+        ),
+    };
+    Ok(mode)
+}
+
+#[cfg(unix)]
+mod daemon_client {
+    use anyhow::{bail, Result};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+        net::{
+            unix::{OwnedReadHalf, OwnedWriteHalf},
+            UnixStream,
+        },
+    };
+    use xm5_thing::daemon::protocol::{socket_path, DaemonRequest, DaemonResponse};
+
+    use super::WatchEvent;
+
+    pub struct DaemonClient {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl DaemonClient {
+        /// None if no daemon is running
+        pub async fn connect() -> Option<DaemonClient> {
+            let stream = UnixStream::connect(socket_path()).await.ok()?;
+            let (reader, writer) = stream.into_split();
+            Some(DaemonClient {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            })
+        }
+
+        async fn send(&mut self, request: DaemonRequest) -> Result<()> {
+            let mut line = serde_json::to_vec(&request)?;
+            line.push(b'\n');
+            self.writer.write_all(&line).await?;
+            Ok(())
+        }
+
+        pub async fn request(&mut self, request: DaemonRequest) -> Result<DaemonResponse> {
+            self.send(request).await?;
+
+            while let Some(line) = self.lines.next_line().await? {
+                match serde_json::from_str(&line)? {
+                    // only there when subscribed
+                    DaemonResponse::Changed { .. }
+                    | DaemonResponse::BatteryEvent { .. }
+                    | DaemonResponse::WearEvent { .. } => continue,
+                    DaemonResponse::Error { message } => bail!(message),
+                    response => return Ok(response),
+                }
+            }
+            bail!("Daemon hung up")
+        }
+
+        /// Until the daemon goes away
+        pub async fn watch(&mut self, mut on_event: impl FnMut(WatchEvent)) -> Result<()> {
+            self.send(DaemonRequest::Subscribe).await?;
+            while let Some(line) = self.lines.next_line().await? {
+                match serde_json::from_str(&line)? {
+                    DaemonResponse::BatteryEvent { event } => on_event(WatchEvent::Battery(event)),
+                    DaemonResponse::WearEvent { event } => on_event(WatchEvent::Wear(event)),
+                    _ => {}
+                }
+            }
+            bail!("Daemon hung up")
+        }
+    }
+}
+
+enum Client {
+    #[cfg(unix)]
+    Daemon(daemon_client::DaemonClient),
+    Direct(HeadphoneConnection<PlatformDeviceCommunication>),
+}
+
+impl Client {
+    async fn open() -> Result<Client> {
+        #[cfg(unix)]
+        if let Some(client) = daemon_client::DaemonClient::connect().await {
+            return Ok(Client::Daemon(client));
+        }
+
+        let communication = platforms::connect(SONY_SOME_SERVICE_UUID).await?;
+        let connection = HeadphoneConnection::new(communication).await;
+        // commands clamp against what the headphone reports, so this goes first
+        connection.refresh().await;
+        Ok(Client::Direct(connection))
+    }
+
+    async fn properties(&mut self) -> Result<HeadphoneProperties> {
+        match self {
+            #[cfg(unix)]
+            Client::Daemon(client) => {
+                use xm5_thing::daemon::protocol::{DaemonRequest, DaemonResponse};
+
+                match client.request(DaemonRequest::Properties).await? {
+                    DaemonResponse::Properties { properties } => Ok(properties),
+                    response => bail!("Unexpected response {response:?}"),
+                }
+            }
+            Client::Direct(connection) => Ok(connection.properties()),
+        }
+    }
+
+    async fn battery_remaining(&mut self) -> Result<Option<Duration>> {
+        match self {
+            #[cfg(unix)]
+            Client::Daemon(client) => {
+                use xm5_thing::daemon::protocol::{DaemonRequest, DaemonResponse};
+
+                match client.request(DaemonRequest::Battery).await? {
+                    DaemonResponse::Battery { remaining_secs, .. } => {
+                        Ok(remaining_secs.map(Duration::from_secs))
+                    }
+                    response => bail!("Unexpected response {response:?}"),
+                }
+            }
+            // nothing watched the battery here, but the daemon might have not long ago
+            Client::Direct(_) => {
+                let history = BatteryHistory::load(&default_history_path())?;
+                Ok(history.estimate_remaining(Utc::now()))
+            }
+        }
+    }
+
+    async fn watch(&mut self, mut on_event: impl FnMut(WatchEvent)) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Client::Daemon(client) => client.watch(on_event).await,
+            Client::Direct(connection) => {
+                let monitor =
+                    BatteryMonitor::start(connection.clone(), BatteryMonitorConfig::from_env());
+                let mut battery_rx = monitor.subscribe();
+                let mut wear_rx = wear_events(connection);
+                loop {
+                    tokio::select! {
+                        event = battery_rx.recv() => match event {
+                            Ok(event) => on_event(WatchEvent::Battery(event)),
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => bail!("Stopped watching the battery"),
+                        },
+                        Some(event) = wear_rx.recv() => on_event(WatchEvent::Wear(event)),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, command: HeadphoneAppCommand) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            Client::Daemon(client) => {
+                use xm5_thing::daemon::protocol::DaemonRequest;
+
+                client.request(DaemonRequest::Command { command }).await?;
+                Ok(())
+            }
+            Client::Direct(connection) => Ok(connection.send(command).await?),
+        }
+    }
+}
+
+fn format_battery(battery: Option<BatteryLevel>) -> String {
+    match battery {
+        Some(BatteryLevel {
+            level,
+            is_charging: true,
+        }) => format!("{level}% (charging)"),
+        Some(BatteryLevel { level, .. }) => format!("{level}%"),
+        None => "unknown".to_owned(),
+    }
+}
+
+fn print_status(properties: &HeadphoneProperties) {
+    let unknown = || "unknown".to_owned();
+    println!(
+        "{} (firmware {})",
+        properties.model_name.clone().unwrap_or_else(unknown),
+        properties.fw_version.clone().unwrap_or_else(unknown)
+    );
+    println!("Battery: {}", format_battery(properties.battery));
+    if let Some(volume) = properties.volume {
+        println!("Volume: {volume}");
+    }
+    match properties.nc_asm {
+        Some(NcAsmParam {
+            mode: NcAsmMode::AmbientSound,
+            ambient_level,
+            voice_passthrough,
+            ..
+        }) => println!(
+            "Noise control: AmbientSound {ambient_level}{}",
+            if voice_passthrough { " (voice)" } else { "" }
+        ),
+        Some(param) => println!("Noise control: {:?}", param.mode),
+        None => println!("Noise control: unknown"),
+    }
+    match &properties.eq {
+        Some(eq) => println!("Equalizer: {:?} {:?}", eq.preset, eq.levels),
+        None => println!("Equalizer: unknown"),
+    }
+    if let Some(level) = properties.clear_bass {
+        println!("Clear Bass: {level}");
+    }
+    if let Some(enabled) = properties.speak_to_chat {
+        let talking = properties.talking == Some(true);
+        println!(
+            "Speak-to-Chat: {}{}",
+            if enabled { "on" } else { "off" },
+            if talking { " (talking)" } else { "" }
+        );
+    }
+    if let Some(codec) = properties.codec {
+        println!("Codec: {codec:?}");
+    }
+    if let Some(enabled) = properties.upscaling {
+        let active = properties.upscaling_indicator.is_some_and(|i| i.active);
+        println!(
+            "DSEE: {}{}",
+            if enabled { "on" } else { "off" },
+            if active { " (active)" } else { "" }
+        );
+    }
+    if let Some(mode) = properties.connection_mode {
+        println!("Connection quality: {mode:?}");
+    }
+    if let Some(enabled) = properties.control_by_wearing {
+        let wearing = match properties.wearing {
+            Some(true) => " (wearing)",
+            Some(false) => " (not wearing)",
+            None => "",
+        };
+        println!(
+            "Pause when removed: {}{wearing}",
+            if enabled { "on" } else { "off" }
+        );
+    }
+    if let Some(setting) = properties.auto_power_off {
+        println!("Auto power off: {setting:?}");
+    }
+    if let Some(enabled) = properties.auto_nc_asm {
+        let activity = properties
+            .activity
+            .filter(|_| enabled)
+            .map(|activity| format!(" ({activity:?})"))
+            .unwrap_or_default();
+        println!(
+            "Adaptive Sound Control: {}{activity}",
+            if enabled { "on" } else { "off" }
+        );
+    }
+    if let Some(devices) = &properties.devices {
+        let connected = devices.iter().filter(|d| d.flags.connected).count();
+        println!("Devices: {connected} connected, {} paired", devices.len());
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match minutes / 60 {
+        0 => format!("{minutes}m"),
+        hours => format!("{hours}h {}m", minutes % 60),
+    }
+}
+
+fn print_battery(properties: &HeadphoneProperties, remaining: Option<Duration>) {
+    println!("{}", format_battery(properties.battery));
+    if let Some(remaining) = remaining {
+        println!("About {} left", format_duration(remaining));
+    }
+    if let Some(battery) = properties.left_right_battery {
+        println!("Left: {}", format_battery(Some(battery.left)));
+        println!("Right: {}", format_battery(Some(battery.right)));
+    }
+    if let Some(battery) = properties.cradle_battery {
+        println!("Case: {}", format_battery(Some(battery)));
+    }
+}
+
+fn print_devices(properties: &HeadphoneProperties) {
+    for device in properties.devices.iter().flatten() {
+        let mut state = vec![];
+        if device.flags.connected {
+            state.push("connected");
+        }
+        if device.flags.active {
+            state.push("active");
+        }
+        if device.flags.pinned {
+            state.push("pinned");
+        }
+        let class = device
+            .flags
+            .device_class()
+            .map(|class| format!(" [{class:?}]"))
+            .unwrap_or_default();
+        println!(
+            "{} {} {}{class} {}",
+            if device.flags.active { "*" } else { " " },
+            device.mac_address,
+            device.name,
+            state.join(", ")
+        );
+    }
+}
+
+fn button_name<T: PartialEq + std::fmt::Debug>(names: &[(&str, T)], value: &T) -> String {
+    names
+        .iter()
+        .find(|(_, v)| v == value)
+        .map(|(n, _)| (*n).to_owned())
+        .unwrap_or_else(|| format!("{value:?}"))
+}
+
+fn print_buttons(properties: &HeadphoneProperties) {
+    for info in properties.assignable_capability.iter().flatten() {
+        let assigned = properties
+            .assignments
+            .iter()
+            .flatten()
+            .find(|a| a.key == info.key)
+            .map(|a| button_name(BUTTON_PRESETS, &a.preset))
+            .unwrap_or_else(|| "unknown".to_owned());
+        let options: Vec<_> = info
+            .presets
+            .iter()
+            .map(|preset| button_name(BUTTON_PRESETS, preset))
+            .collect();
+        println!(
+            "{}: {assigned} (can be {})",
+            button_name(BUTTON_KEYS, &info.key),
+            options.join(", ")
+        );
+    }
+}
+
+fn format_profile(profile: &ActivityProfile) -> String {
+    match profile.mode {
+        NcAsmMode::AmbientSound => format!(
+            "AmbientSound {}{}",
+            profile.ambient_level,
+            if profile.voice_passthrough {
+                " (voice)"
+            } else {
+                ""
+            }
+        ),
+        mode => format!("{mode:?}"),
+    }
+}
+
+fn profiles(options: &Options, change: Option<(DetectedActivity, ActivityProfile)>) -> Result<()> {
+    let path = default_profiles_path();
+    let mut profiles = AdaptiveProfiles::load(&path)?;
+    if let Some((activity, profile)) = change {
+        profiles.set(activity, profile);
+        return Ok(profiles.save(&path)?);
+    }
+
+    if options.json {
+        println!("{}", serde_json::to_string(&profiles)?);
+        return Ok(());
+    }
+    for (name, activity) in ACTIVITIES {
+        println!("{name}: {}", format_profile(&profiles.get(*activity)));
+    }
+    Ok(())
+}
+
+async fn execute(client: &mut Client, options: &Options) -> Result<()> {
+    match &options.action {
+        Action::Command(command) => client.send(*command).await,
+        Action::Profiles(change) => profiles(options, *change),
+        Action::Watch => {
+            let json = options.json;
+            client
+                .watch(|event| match (event, json) {
+                    (WatchEvent::Battery(event), true) => println!("{}", json!(event)),
+                    (WatchEvent::Wear(event), true) => println!("{}", json!(event)),
+                    (WatchEvent::Battery(BatteryEvent::Low { level, .. }), false) => {
+                        println!("Battery low: {level}%")
+                    }
+                    (WatchEvent::Wear(WearEvent::PutOn), false) => println!("Put on"),
+                    (WatchEvent::Wear(WearEvent::TakenOff), false) => println!("Taken off"),
+                })
+                .await
+        }
+        Action::Show(view) => {
+            let properties = client.properties().await?;
+            match (view, options.json) {
+                (View::Status, true) => println!("{}", serde_json::to_string(&properties)?),
+                (View::Status, false) => print_status(&properties),
+                (View::Battery, true) => println!(
+                    "{}",
+                    json!({
+                        "battery": properties.battery,
+                        "left_right_battery": properties.left_right_battery,
+                        "cradle_battery": properties.cradle_battery,
+                        "remaining_secs": client.battery_remaining().await?.map(|d| d.as_secs()),
+                    })
+                ),
+                (View::Battery, false) => {
+                    print_battery(&properties, client.battery_remaining().await?)
+                }
+                (View::Devices, true) => {
+                    println!("{}", serde_json::to_string(&properties.devices)?)
+                }
+                (View::Devices, false) => print_devices(&properties),
+                (View::Buttons, true) => println!(
+                    "{}",
+                    json!({
+                        "capability": properties.assignable_capability,
+                        "assignments": properties.assignments,
+                    })
+                ),
+                (View::Buttons, false) => print_buttons(&properties),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\nSee `xm5 --help`");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    debug::set_enabled(options.verbose);
+
+    // no need to reach the headphone for these
+    if let Action::Profiles(change) = options.action {
+        return match profiles(&options, change) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e:#}");
+                ExitCode::from(EXIT_FAILED)
+            }
+        };
+    }
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let mut client = match Client::open().await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("Can't reach the headphone: {e:#}");
+                return ExitCode::from(EXIT_UNAVAILABLE);
+            }
+        };
+
+        let result = execute(&mut client, &options).await;
+        if let Client::Direct(connection) = &client {
+            connection.close();
+        }
+
+        match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e:#}");
+                ExitCode::from(EXIT_FAILED)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Action> {
+        let args = line.split_whitespace().map(str::to_owned).collect();
+        Ok(parse_args(args)?.expect("not --help").action)
+    }
+
+    fn parse_nc_param(line: &str) -> NcAsmParam {
+        match parse(line).unwrap() {
+            Action::Command(HeadphoneAppCommand::SetNcAsm(param)) => param,
+            _ => panic!("{line} isn't a noise control command"),
+        }
+    }
+
+    #[test]
+    fn ambient_level_is_capped() {
+        assert!(parse("nc ambient 21").is_err());
+        assert!(parse("adaptive walking ambient 21").is_err());
+        assert_eq!(parse_nc_param("nc ambient 20").ambient_level, 20);
+        assert_eq!(
+            parse_nc_param("nc ambient").ambient_level,
+            MAX_AMBIENT_LEVEL
+        );
+    }
+
+    #[test]
+    fn eq_bands_needs_every_band() {
+        assert!(parse("eq bands 1 2 3 4").is_err());
+        assert!(parse("eq bands 1 2 3 4 5 6").is_err());
+        assert!(parse("eq bands 1 2 x 4 5").is_err());
+        assert!(matches!(
+            parse("eq bands -10 -5 0 5 10"),
+            Ok(Action::Command(HeadphoneAppCommand::SetEqBands([
+                -10, -5, 0, 5, 10
+            ])))
+        ));
+    }
+
+    #[test]
+    fn voice_goes_anywhere() {
+        for line in [
+            "--voice nc ambient 5",
+            "nc --voice ambient 5",
+            "nc ambient 5 --voice",
+        ] {
+            let param = parse_nc_param(line);
+            assert_eq!(param, NcAsmParam::new(NcAsmMode::AmbientSound, 5, true));
+        }
+        assert!(!parse_nc_param("nc ambient 5").voice_passthrough);
+
+        assert!(matches!(
+            parse("speak-to-chat config high long --voice"),
+            Ok(Action::Command(HeadphoneAppCommand::SetSpeakToChat(
+                SmartTalkingConfig {
+                    sensitivity: SmartTalkingSensitivity::High,
+                    voice_passthrough: true,
+                    timeout: SmartTalkingTimeout::Long,
+                }
+            )))
+        ));
+        assert!(matches!(
+            parse("adaptive --voice running ambient 3"),
+            Ok(Action::Profiles(Some((DetectedActivity::Running, profile))))
+                if profile == ActivityProfile::new(NcAsmMode::AmbientSound, 3, true)
+        ));
+    }
+
+    #[test]
+    fn unknown_words_are_usage_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("nc loud").is_err());
+        assert!(parse("button touch nothing").is_err());
+        assert!(parse("auto-off 2h").is_err());
+        assert!(parse("switch not-a-mac").is_err());
+        assert!(matches!(
+            parse_args(vec!["status".into(), "-h".into()]),
+            Ok(None)
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    future::{pending, Future},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{
+        mpsc::{channel, Sender},
+        watch,
+    },
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    constant::SONY_SOME_SERVICE_UUID,
+    platforms::{self, traits::DeviceCommunication},
+    protocols::{
+        adaptive::{default_profiles_path, AdaptiveSound},
+        battery::{tracked_battery, BatteryMonitor, BatteryMonitorConfig},
+        connection::{Disconnect, HeadphoneConnection},
+        wearing::{wear_events, WearEvent},
+    },
+};
+use protocol::{socket_path, DaemonRequest, DaemonResponse};
+
+pub mod protocol;
+
+// owns the connection so scripts and bars can talk to the headphone without the ui
+// see `protocol` for what goes over the socket
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+pub fn start() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    if let Err(e) = runtime.block_on(run()) {
+        eprintln!("{e:#}");
+        std::process::exit(1);
+    }
+}
+
+pub async fn run() -> Result<()> {
+    let path = socket_path();
+    // bind first so a second daemon bails before touching bluetooth
+    let listener = bind(&path).await?;
+    debug_println!("Listening on {}", path.display());
+
+    let (sessions_tx, sessions_rx) = watch::channel(None);
+    let connect = || platforms::connect(SONY_SOME_SERVICE_UUID);
+    let result = tokio::select! {
+        result = serve(listener, sessions_rx) => result,
+        result = keep_connected(connect, BatteryMonitorConfig::from_env(), sessions_tx) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+async fn bind(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            bail!("A daemon is already listening on {}", path.display());
+        }
+        // left behind by a daemon that didn't exit cleanly
+        std::fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+/// One connection to the headphone and what runs on it, a reconnect makes a new one
+#[derive(Clone)]
+pub struct Session<D: DeviceCommunication> {
+    pub connection: HeadphoneConnection<D>,
+    pub battery: BatteryMonitor,
+}
+
+/// The current session, `None` while there's no headphone
+pub type Sessions<D> = watch::Receiver<Option<Session<D>>>;
+
+/// Connects, and connects again whenever the headphone goes away. Never stops on its own,
+/// a headphone that was powered off is waited for like a lost one
+pub async fn keep_connected<D, F, Fut>(
+    mut connect: F,
+    battery_config: BatteryMonitorConfig,
+    sessions: watch::Sender<Option<Session<D>>>,
+) -> Result<()>
+where
+    D: DeviceCommunication + Clone + Send + Sync + 'static,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<D>>,
+{
+    let mut backoff = Duration::ZERO;
+    loop {
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).clamp(RECONNECT_MIN, RECONNECT_MAX);
+
+        let communication = match connect().await {
+            Ok(communication) => communication,
+            Err(e) => {
+                debug_println!("Can't connect: {e:#}, trying again in {backoff:?}");
+                continue;
+            }
+        };
+        let connected_at = Instant::now();
+        let connection = HeadphoneConnection::new(communication).await;
+
+        let c = connection.clone();
+        tokio::spawn(async move { c.refresh().await });
+
+        let battery = BatteryMonitor::start(connection.clone(), battery_config.clone());
+        let _adaptive = AdaptiveSound::start(connection.clone(), default_profiles_path());
+        spawn_wear_hooks(&connection);
+        sessions.send_replace(Some(Session {
+            connection: connection.clone(),
+            battery,
+        }));
+
+        let disconnect = connection.disconnected().await;
+        sessions.send_replace(None);
+        if disconnect == Disconnect::Expected {
+            // not its fault, give it a moment to actually go off before looking again
+            backoff = RECONNECT_MIN;
+            debug_println!("Powered off, waiting for it to come back");
+            continue;
+        }
+        // one that stayed up for a while is worth trying again right away
+        if connected_at.elapsed() > RECONNECT_MAX {
+            backoff = Duration::ZERO;
+        }
+        debug_println!("Lost the headphone, reconnecting in {backoff:?}");
+    }
+}
+
+/// `$XM5_ON_PUT_ON` and `$XM5_ON_TAKEN_OFF` go through `sh -c`, e.g. to lock the screen
+fn spawn_wear_hooks<D>(connection: &HeadphoneConnection<D>)
+where
+    D: DeviceCommunication,
+{
+    let mut events = wear_events(connection);
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let var = match event {
+                WearEvent::PutOn => "XM5_ON_PUT_ON",
+                WearEvent::TakenOff => "XM5_ON_TAKEN_OFF",
+            };
+            let Ok(hook) = std::env::var(var) else {
+                continue;
+            };
+            // not waited on, a slow hook shouldn't hold up the next event
+            if let Err(e) = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(&hook)
+                .spawn()
+            {
+                debug_println!("Can't run ${var}: {e}");
+            }
+        }
+    });
+}
+
+/// Accept clients until the listener fails, they talk to whatever session is current
+pub async fn serve<D>(listener: UnixListener, sessions: Sessions<D>) -> Result<()>
+where
+    D: DeviceCommunication + Clone + Send + Sync + 'static,
+{
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, sessions).await {
+                debug_println!("Client error: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_client<D>(stream: UnixStream, sessions: Sessions<D>) -> Result<()>
+where
+    D: DeviceCommunication + Clone + Send + Sync + 'static,
+{
+    let (reader, mut writer) = stream.into_split();
+
+    // replies and subscriptions share the socket
+    let (responses_tx, mut responses_rx) = channel::<DaemonResponse>(24);
+    let writer_task = tokio::spawn(async move {
+        while let Some(response) = responses_rx.recv().await {
+            let mut line = serde_json::to_vec(&response)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+        }
+        anyhow::Ok(())
+    });
+
+    let mut subscription: Option<JoinHandle<()>> = None;
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<DaemonRequest>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let message = e.to_string();
+                let _ = responses_tx.send(DaemonResponse::Error { message }).await;
+                continue;
+            }
+        };
+
+        let session = sessions.borrow().clone();
+        let response = match (request, session) {
+            // it keeps following the session, so once is enough
+            (DaemonRequest::Subscribe, _) => {
+                if subscription.is_none() {
+                    subscription = Some(subscribe(sessions.clone(), responses_tx.clone()));
+                }
+                continue;
+            }
+            (_, None) => DaemonResponse::Error {
+                message: "Not connected to the headphone".to_owned(),
+            },
+            (DaemonRequest::Command { command }, Some(Session { connection, .. })) => {
+                match connection.send(command).await {
+                    Ok(()) => DaemonResponse::Ok,
+                    Err(e) => DaemonResponse::Error {
+                        message: e.to_string(),
+                    },
+                }
+            }
+            (DaemonRequest::Properties, Some(Session { connection, .. })) => {
+                DaemonResponse::Properties {
+                    properties: connection.properties(),
+                }
+            }
+            (DaemonRequest::Refresh, Some(Session { connection, .. })) => {
+                connection.refresh().await;
+                DaemonResponse::Ok
+            }
+            (
+                DaemonRequest::Battery,
+                Some(Session {
+                    connection,
+                    battery,
+                }),
+            ) => DaemonResponse::Battery {
+                battery: tracked_battery(&connection.properties()),
+                remaining_secs: battery.estimate_remaining().map(|d| d.as_secs()),
+            },
+        };
+        if responses_tx.send(response).await.is_err() {
+            break;
+        }
+    }
+
+    // client hung up
+    if let Some(subscription) = subscription {
+        subscription.abort();
+    }
+    drop(responses_tx);
+    writer_task.await?
+}
+
+fn subscribe<D>(mut sessions: Sessions<D>, responses_tx: Sender<DaemonResponse>) -> JoinHandle<()>
+where
+    D: DeviceCommunication + Clone + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let session = sessions.borrow_and_update().clone();
+            tokio::select! {
+                _ = forward_events(session, &responses_tx) => break,
+                changed = sessions.changed() => if changed.is_err() {
+                    break;
+                },
+            }
+        }
+    })
+}
+
+/// Returns once the client is gone, a new session starts over with a fresh `Properties`
+async fn forward_events<D>(session: Option<Session<D>>, responses_tx: &Sender<DaemonResponse>)
+where
+    D: DeviceCommunication + Clone,
+{
+    let Some(session) = session else {
+        return pending().await;
+    };
+
+    // subscribe before the snapshot so nothing falls in between
+    let mut changes_rx = session.connection.properties_rx();
+    let mut battery_rx = session.battery.subscribe();
+    let mut wear_rx = wear_events(&session.connection);
+    let properties = session.connection.properties();
+    if responses_tx
+        .send(DaemonResponse::Properties { properties })
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        let response = tokio::select! {
+            Some(change) = changes_rx.recv() => DaemonResponse::Changed {
+                changed: change.changed,
+                properties: change.properties,
+            },
+            // lagging only skips that one
+            Ok(event) = battery_rx.recv() => DaemonResponse::BatteryEvent { event },
+            Some(event) = wear_rx.recv() => DaemonResponse::WearEvent { event },
+            // it's over, wait for the next session
+            else => return pending().await,
+        };
+        if responses_tx.send(response).await.is_err() {
+            return;
+        }
+    }
+}
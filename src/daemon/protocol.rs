@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocols::{
+    battery::BatteryEvent,
+    connection::HeadphoneAppCommand,
+    properties::{BatteryLevel, HeadphoneProperties, HeadphoneProperty},
+    wearing::WearEvent,
+};
+
+// one json object per line, both ways
+// {"type":"command","command":{"SetEqPreset":"Bright"}}
+// {"type":"subscribe"}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    Command {
+        command: HeadphoneAppCommand,
+    },
+    Properties,
+    Refresh,
+    Battery,
+    /// Get the current properties, then a `Changed` line every time they change
+    /// and a `BatteryEvent` line for low battery warnings,
+    /// a `WearEvent` line when it goes on or comes off
+    Subscribe,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonResponse {
+    Ok,
+    Error {
+        message: String,
+    },
+    Properties {
+        properties: HeadphoneProperties,
+    },
+    Changed {
+        changed: Vec<HeadphoneProperty>,
+        properties: HeadphoneProperties,
+    },
+    Battery {
+        battery: Option<BatteryLevel>,
+        /// Estimated from the discharge so far, `None` while charging or not known yet
+        remaining_secs: Option<u64>,
+    },
+    BatteryEvent {
+        event: BatteryEvent,
+    },
+    WearEvent {
+        event: WearEvent,
+    },
+}
+
+/// `$XM5_SOCKET`, or `xm5-thing.sock` in the runtime dir
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("XM5_SOCKET") {
+        return PathBuf::from(path);
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("xm5-thing.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("xm5-thing-{user}.sock"))
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// protocol chatter goes to stderr so stdout stays clean for the cli
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+macro_rules! debug_println {
+    ($($arg:tt)*) => {
+        if $crate::debug::enabled() {
+            eprintln!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,12 @@
+// `mdr_packets!` munches one packet per step
+#![recursion_limit = "256"]
+
+#[macro_use]
+pub mod debug;
+
+pub mod constant;
+#[cfg(unix)]
+pub mod daemon;
+pub mod platforms;
+pub mod protocols;
+pub mod ui;
\ No newline at end of file
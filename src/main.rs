@@ -3,16 +3,15 @@
 //     windows_subsystem = "windows"
 // )]
 
-use crate::ui::start;
-
-mod platforms;
-mod constant;
-mod protocols;
-mod ui;
+use xm5_thing::ui::start;
 
 // #[tokio::main]
 fn main() {
-    start();
+    match std::env::args().nth(1).as_deref() {
+        #[cfg(unix)]
+        Some("daemon") => xm5_thing::daemon::start(),
+        _ => start(),
+    }
 }
 
 /* 
@@ -0,0 +1,613 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+
+use crate::{
+    platforms::{traits::DeviceCommunication, MacAddress},
+    protocols::{
+        dialect::MdrDialect,
+        frame::{Frame, FrameDataType, FrameDecoder},
+        mdr::{
+            AssignableKey, AssignableKeyInfo, AssignablePreset, AudioCodec, AudioInquiredType,
+            AutoPowerOff, BatteryInquiredType, CommonRetBatteryLevel, ConnectRetDeviceInfo,
+            ConnectedDevice, ConnectedDeviceFlags, ConnectionMode, DeviceInfoInquiredType,
+            EbbCapability, EqCapability, EqEbbCapability, EqEbbInquiredType, EqEbbParam, EqParam,
+            EqPreset, EqPresetInfo, FunctionType, KeyAssignment, MDRPacket, ModelColor,
+            ModelSeries, MultipointAction, NcAsmMode, NcAsmParam, OnOffSetting, SmartTalkingConfig,
+            SmartTalkingSensitivity, SmartTalkingTimeout, SupportedFunctions, SystemInquiredType,
+            UpscalingIndicator, UpscalingType,
+        },
+    },
+};
+
+/// What the emulated headset reports when asked
+#[derive(Debug, Clone)]
+pub struct EmulatedHeadphone {
+    pub protocol_version: u16,
+    pub model_name: String,
+    pub fw_version: String,
+    pub series: ModelSeries,
+    pub color: ModelColor,
+    pub supported_functions: SupportedFunctions,
+    pub battery: (u8, bool),
+    pub left_right_battery: Option<((u8, bool), (u8, bool))>,
+    pub cradle_battery: Option<(u8, bool)>,
+    pub connected_count: u8,
+    pub devices: Vec<ConnectedDevice>,
+    pub volume: u8,
+    pub nc_asm: NcAsmParam,
+    pub eq_capability: EqCapability,
+    pub eq: EqParam,
+    pub clear_bass: i8,
+    pub speak_to_chat: bool,
+    pub speak_to_chat_config: SmartTalkingConfig,
+    pub auto_nc_asm: bool,
+    pub codec: AudioCodec,
+    pub upscaling: bool,
+    pub connection_mode: ConnectionMode,
+    pub auto_power_off: AutoPowerOff,
+    pub control_by_wearing: bool,
+    /// Changes are up to whoever drives the emulator, see `notify`
+    pub wearing: bool,
+    pub assignable_capability: Vec<AssignableKeyInfo>,
+    pub assignments: Vec<KeyAssignment>,
+}
+
+impl Default for EmulatedHeadphone {
+    fn default() -> Self {
+        Self {
+            protocol_version: 0x0100,
+            model_name: "WH-1000XM5".to_owned(),
+            fw_version: "2.0.1".to_owned(),
+            series: ModelSeries::Premium,
+            color: ModelColor::Black,
+            supported_functions: [
+                FunctionType::BatteryLevel,
+                FunctionType::UpscalingIndicator,
+                FunctionType::CodecIndicator,
+                FunctionType::PowerOff,
+                FunctionType::PairingDeviceManagementClassicBt,
+                FunctionType::VoiceGuidance,
+                FunctionType::PresetEq,
+                FunctionType::Ebb,
+                FunctionType::NoiseCancellingAndAmbientSoundMode,
+                FunctionType::AutoNcAsm,
+                FunctionType::PlaybackController,
+                FunctionType::ConnectionMode,
+                FunctionType::Upscaling,
+                FunctionType::ControlByWearing,
+                FunctionType::AutoPowerOff,
+                FunctionType::SmartTalkingMode,
+                FunctionType::AssignableSettings,
+            ]
+            .into_iter()
+            .collect(),
+            battery: (70, false),
+            left_right_battery: None,
+            cradle_battery: None,
+            connected_count: 1,
+            devices: vec![ConnectedDevice {
+                mac_address: MacAddress::new(&[0xC5, 0xE8, 0x11, 0x0A, 0x80, 0xAC]),
+                flags: ConnectedDeviceFlags {
+                    connected: true,
+                    active: true,
+                    // laptop
+                    class_of_device: 0x00010C,
+                    ..Default::default()
+                },
+                name: "Emulated laptop".to_owned(),
+            }],
+            volume: 15,
+            nc_asm: NcAsmParam::new(NcAsmMode::NoiseCancelling, 0, false),
+            eq_capability: EqCapability {
+                customizable: true,
+                band_count: 5,
+                level_steps: 20,
+                presets: [
+                    (EqPreset::Off, "Off"),
+                    (EqPreset::Bright, "Bright"),
+                    (EqPreset::Mellow, "Mellow"),
+                    (EqPreset::Manual, "Custom"),
+                ]
+                .into_iter()
+                .map(|(preset, name)| EqPresetInfo {
+                    preset,
+                    name: name.to_owned(),
+                })
+                .collect(),
+            },
+            eq: EqParam {
+                preset: EqPreset::Off,
+                levels: vec![0; 5],
+            },
+            clear_bass: 0,
+            speak_to_chat: false,
+            speak_to_chat_config: SmartTalkingConfig {
+                sensitivity: SmartTalkingSensitivity::Auto,
+                voice_passthrough: true,
+                timeout: SmartTalkingTimeout::Standard,
+            },
+            auto_nc_asm: false,
+            codec: AudioCodec::Ldac,
+            upscaling: false,
+            connection_mode: ConnectionMode::SoundQuality,
+            auto_power_off: AutoPowerOff::Off,
+            control_by_wearing: true,
+            wearing: true,
+            assignable_capability: vec![
+                AssignableKeyInfo {
+                    key: AssignableKey::NcAmbButton,
+                    presets: vec![
+                        AssignablePreset::AmbientSoundControl,
+                        AssignablePreset::GoogleAssistant,
+                        AssignablePreset::AmazonAlexa,
+                        AssignablePreset::NoFunction,
+                    ],
+                },
+                AssignableKeyInfo {
+                    key: AssignableKey::TouchPanel,
+                    presets: vec![AssignablePreset::PlaybackControl],
+                },
+            ],
+            assignments: vec![
+                KeyAssignment {
+                    key: AssignableKey::NcAmbButton,
+                    preset: AssignablePreset::AmbientSoundControl,
+                },
+                KeyAssignment {
+                    key: AssignableKey::TouchPanel,
+                    preset: AssignablePreset::PlaybackControl,
+                },
+            ],
+        }
+    }
+}
+
+/// Misbehaviour to inject. Counters are decremented as they are used up
+#[derive(Debug, Clone, Default)]
+pub struct EmulatorFaults {
+    /// Handle the next n data frames without acking them
+    pub drop_acks: usize,
+    /// Act like the next n data frames never arrived
+    pub lose_frames: usize,
+    /// Send the next n frames with a wrong checksum
+    pub bad_checksums: usize,
+    /// Wait this long before answering a request
+    pub reply_delay: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Emulator {
+    headphone: EmulatedHeadphone,
+    faults: EmulatorFaults,
+    sequence_number: u8,
+    last_received: Option<u8>,
+    received_acks: usize,
+    listeners: Vec<Sender<Vec<u8>>>,
+    /// Hangs up once the replies are out
+    powered_off: bool,
+}
+
+impl Emulator {
+    fn encode(&mut self, frame: Frame) -> Vec<u8> {
+        if self.faults.bad_checksums > 0 {
+            self.faults.bad_checksums -= 1;
+            return frame.encode_with_checksum(frame.checksum().wrapping_add(1));
+        }
+        frame.into()
+    }
+
+    fn dialect(&self) -> MdrDialect {
+        MdrDialect::from_protocol_version(self.headphone.protocol_version)
+    }
+
+    fn next_frame(&mut self, packet: &MDRPacket) -> Vec<u8> {
+        let (data_type, bytes) = packet
+            .encode(self.dialect())
+            .expect("the emulator only replies with what its dialect has");
+        let frame = Frame::new(data_type, self.sequence_number, &bytes);
+        self.sequence_number ^= 1;
+        self.encode(frame)
+    }
+
+    fn reply(&mut self, packet: &MDRPacket) -> Vec<MDRPacket> {
+        let headphone = &mut self.headphone;
+        // DSEE does nothing to LDAC
+        let upscaling_indicator = |headphone: &EmulatedHeadphone| UpscalingIndicator {
+            upscaling_type: UpscalingType::DseeExtreme,
+            active: headphone.upscaling && headphone.codec != AudioCodec::Ldac,
+        };
+        let battery = |(level, is_charging): (u8, bool)| CommonRetBatteryLevel::Battery {
+            level,
+            is_charging,
+        };
+
+        match packet {
+            MDRPacket::ConnectGetProtocolInfo => vec![MDRPacket::ConnectRetProtocolInfo {
+                protocol_version: headphone.protocol_version,
+            }],
+            MDRPacket::ConnectGetSupportFunction => vec![MDRPacket::ConnectRetSupportFunction(
+                headphone.supported_functions.clone(),
+            )],
+            MDRPacket::ConnectGetDeviceInfo { inquired_type } => {
+                let info = match inquired_type {
+                    DeviceInfoInquiredType::ModelName => {
+                        ConnectRetDeviceInfo::ModelName(headphone.model_name.clone())
+                    }
+                    DeviceInfoInquiredType::FwVersion => {
+                        ConnectRetDeviceInfo::FwVersion(headphone.fw_version.clone())
+                    }
+                    DeviceInfoInquiredType::SeriesAndColorInfo => {
+                        ConnectRetDeviceInfo::SeriesAndColorInfo(headphone.series, headphone.color)
+                    }
+                    DeviceInfoInquiredType::InstructionGuide => {
+                        ConnectRetDeviceInfo::InstructionGuide(vec![])
+                    }
+                };
+                vec![MDRPacket::ConnectRetDeviceInfo(info)]
+            }
+            MDRPacket::CommonGetBatteryLevel { inquired_type } => {
+                let info = match inquired_type {
+                    BatteryInquiredType::Battery => Some(battery(headphone.battery)),
+                    BatteryInquiredType::LeftRightBattery => headphone.left_right_battery.map(
+                        |((left_level, left_charging), (right_level, right_charging))| {
+                            CommonRetBatteryLevel::LeftRightBattery {
+                                left_level,
+                                left_charging,
+                                right_level,
+                                right_charging,
+                            }
+                        },
+                    ),
+                    BatteryInquiredType::CradleBattery => {
+                        headphone.cradle_battery.map(|(level, is_charging)| {
+                            CommonRetBatteryLevel::CradleBattery { level, is_charging }
+                        })
+                    }
+                };
+                info.map(MDRPacket::CommonRetBatteryLevel)
+                    .into_iter()
+                    .collect()
+            }
+            MDRPacket::ConnectedDeviecesGet { .. } => vec![MDRPacket::ConnectedDeviecesRet {
+                connected_count: headphone.connected_count,
+                devices: headphone.devices.clone(),
+            }],
+            MDRPacket::EqEbbGetCapability { inquired_type } => {
+                let capability = match inquired_type {
+                    EqEbbInquiredType::Ebb => EqEbbCapability::Ebb(EbbCapability {
+                        min_level: -10,
+                        max_level: 10,
+                    }),
+                    _ => EqEbbCapability::Eq(headphone.eq_capability.clone()),
+                };
+                vec![MDRPacket::EqEbbRetCapability(capability)]
+            }
+            MDRPacket::EqEbbGetParam { inquired_type } => {
+                let param = match inquired_type {
+                    EqEbbInquiredType::Ebb => EqEbbParam::Ebb(headphone.clear_bass),
+                    _ => EqEbbParam::Eq(headphone.eq.clone()),
+                };
+                vec![MDRPacket::EqEbbRetParam(param)]
+            }
+            MDRPacket::EqEbbSetParam(param) => {
+                match param {
+                    // picking a preset doesn't come with levels
+                    EqEbbParam::Eq(eq) if eq.levels.is_empty() => headphone.eq.preset = eq.preset,
+                    EqEbbParam::Eq(eq) => headphone.eq = eq.clone(),
+                    EqEbbParam::Ebb(level) => headphone.clear_bass = *level,
+                }
+                let param = match param {
+                    EqEbbParam::Eq(_) => EqEbbParam::Eq(headphone.eq.clone()),
+                    EqEbbParam::Ebb(_) => EqEbbParam::Ebb(headphone.clear_bass),
+                };
+                vec![MDRPacket::EqEbbNtfyParam(param)]
+            }
+            MDRPacket::MultipointActiveDeviceSet { mac_address } => {
+                let is_connected = headphone
+                    .devices
+                    .iter()
+                    .any(|d| d.mac_address == *mac_address && d.flags.connected);
+                if is_connected {
+                    for device in headphone.devices.iter_mut() {
+                        device.flags.active = device.mac_address == *mac_address;
+                    }
+                }
+                vec![]
+            }
+            MDRPacket::MultipointPinningSet { enabled } => {
+                for device in headphone.devices.iter_mut() {
+                    device.flags.pinned = *enabled && device.flags.active;
+                }
+                vec![]
+            }
+            MDRPacket::MultipointDeviceAction {
+                action,
+                mac_address,
+            } => {
+                match action {
+                    MultipointAction::Unpair => {
+                        headphone.devices.retain(|d| d.mac_address != *mac_address)
+                    }
+                    MultipointAction::Connect | MultipointAction::Disconnect => {
+                        for device in headphone.devices.iter_mut() {
+                            if device.mac_address == *mac_address {
+                                device.flags.connected = *action == MultipointAction::Connect;
+                                device.flags.active &= device.flags.connected;
+                            }
+                        }
+                    }
+                }
+                headphone.connected_count = headphone
+                    .devices
+                    .iter()
+                    .filter(|d| d.flags.connected)
+                    .count() as u8;
+                vec![]
+            }
+            MDRPacket::NcAsmGetParam { inquired_type } => {
+                vec![MDRPacket::NcAsmRetParam(NcAsmParam {
+                    inquired_type: *inquired_type,
+                    ..headphone.nc_asm
+                })]
+            }
+            MDRPacket::NcAsmSetParam(param) => {
+                headphone.nc_asm = *param;
+                vec![MDRPacket::NcAsmNtfyParam(*param)]
+            }
+            MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::SmartTalkingMode,
+            } => {
+                vec![MDRPacket::SmartTalkingRetParam {
+                    enabled: headphone.speak_to_chat.into(),
+                    preview_mode: OnOffSetting::Off,
+                }]
+            }
+            MDRPacket::SystemGetExtParam {
+                inquired_type: SystemInquiredType::SmartTalkingMode,
+            } => {
+                vec![MDRPacket::SmartTalkingRetExtParam(
+                    headphone.speak_to_chat_config,
+                )]
+            }
+            MDRPacket::SmartTalkingSetParam {
+                enabled,
+                preview_mode,
+            } => {
+                headphone.speak_to_chat = enabled.is_on();
+                vec![MDRPacket::SmartTalkingNtfyParam {
+                    enabled: *enabled,
+                    preview_mode: *preview_mode,
+                }]
+            }
+            MDRPacket::SmartTalkingSetExtParam(config) => {
+                headphone.speak_to_chat_config = *config;
+                vec![MDRPacket::SmartTalkingNtfyExtParam(*config)]
+            }
+            MDRPacket::CommonGetAudioCodec => vec![MDRPacket::CommonRetAudioCodec {
+                codec: headphone.codec,
+            }],
+            MDRPacket::CommonGetUpscalingEffect => {
+                vec![MDRPacket::CommonRetUpscalingEffect(upscaling_indicator(
+                    headphone,
+                ))]
+            }
+            MDRPacket::AudioGetParam {
+                inquired_type: AudioInquiredType::Upscaling,
+            } => vec![MDRPacket::UpscalingRetParam {
+                enabled: headphone.upscaling,
+            }],
+            MDRPacket::AudioGetParam {
+                inquired_type: AudioInquiredType::ConnectionMode,
+            } => vec![MDRPacket::ConnectionModeRetParam {
+                mode: headphone.connection_mode,
+            }],
+            MDRPacket::UpscalingSetParam { enabled } => {
+                headphone.upscaling = *enabled;
+                vec![
+                    MDRPacket::UpscalingNtfyParam { enabled: *enabled },
+                    MDRPacket::CommonNtfyUpscalingEffect(upscaling_indicator(headphone)),
+                ]
+            }
+            // the source picks the codec again, pretend it's a phone that has everything
+            MDRPacket::ConnectionModeSetParam { mode } => {
+                headphone.connection_mode = *mode;
+                headphone.codec = match mode {
+                    ConnectionMode::SoundQuality => AudioCodec::Ldac,
+                    ConnectionMode::StableConnection => AudioCodec::Aac,
+                };
+                vec![
+                    MDRPacket::ConnectionModeNtfyParam { mode: *mode },
+                    MDRPacket::CommonNtfyAudioCodec {
+                        codec: headphone.codec,
+                    },
+                    MDRPacket::CommonNtfyUpscalingEffect(upscaling_indicator(headphone)),
+                ]
+            }
+            MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::ControlByWearing,
+            } => vec![MDRPacket::ControlByWearingRetParam {
+                enabled: headphone.control_by_wearing.into(),
+            }],
+            MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::WearingStatus,
+            } => vec![MDRPacket::WearingRetStatus {
+                wearing: headphone.wearing,
+            }],
+            MDRPacket::ControlByWearingSetParam { enabled } => {
+                headphone.control_by_wearing = enabled.is_on();
+                vec![MDRPacket::ControlByWearingNtfyParam { enabled: *enabled }]
+            }
+            MDRPacket::SystemGetCapability {
+                inquired_type: SystemInquiredType::AssignableSettings,
+            } => vec![MDRPacket::AssignableRetCapability {
+                keys: headphone.assignable_capability.clone(),
+            }],
+            MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::AssignableSettings,
+            } => vec![MDRPacket::AssignableRetParam {
+                assignments: headphone.assignments.clone(),
+            }],
+            MDRPacket::AssignableSetParam { assignments } => {
+                headphone.assignments = assignments.clone();
+                vec![MDRPacket::AssignableNtfyParam {
+                    assignments: assignments.clone(),
+                }]
+            }
+            MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::AutoPowerOff,
+            } => vec![MDRPacket::AutoPowerOffRetParam(headphone.auto_power_off)],
+            MDRPacket::AutoPowerOffSetParam(setting) => {
+                headphone.auto_power_off = *setting;
+                vec![MDRPacket::AutoPowerOffNtfyParam(*setting)]
+            }
+            MDRPacket::CommonSetPowerOff => {
+                self.powered_off = true;
+                vec![]
+            }
+            MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::AutoNcAsm,
+            } => {
+                vec![MDRPacket::AutoNcAsmRetParam {
+                    enabled: headphone.auto_nc_asm.into(),
+                }]
+            }
+            // activity notifications are up to whoever drives the emulator, see `notify`
+            MDRPacket::AutoNcAsmSetParam { enabled } => {
+                headphone.auto_nc_asm = enabled.is_on();
+                vec![MDRPacket::AutoNcAsmNtfyParam { enabled: *enabled }]
+            }
+            _ => vec![],
+        }
+    }
+
+    // a full listener loses the bytes, just like a real link would
+    fn send(&mut self, bytes: Vec<u8>) {
+        self.listeners.retain(|listener| {
+            !matches!(
+                listener.try_send(bytes.clone()),
+                Err(TrySendError::Closed(_))
+            )
+        });
+    }
+}
+
+/// A fake headset living in the same process, for testing without hardware
+#[derive(Debug, Clone)]
+pub struct EmulatedDeviceCommunication {
+    emulator: Arc<Mutex<Emulator>>,
+    inbound: Sender<Vec<u8>>,
+}
+
+impl EmulatedDeviceCommunication {
+    pub fn new(headphone: EmulatedHeadphone) -> Self {
+        let emulator = Arc::new(Mutex::new(Emulator {
+            headphone,
+            ..Default::default()
+        }));
+        let (inbound, mut inbound_rx) = channel::<Vec<u8>>(24);
+
+        let e = emulator.clone();
+        tokio::spawn(async move {
+            let emulator = e;
+            let mut decoder = FrameDecoder::new();
+            while let Some(bytes) = inbound_rx.recv().await {
+                // broken frames get no ack, same as the real thing
+                for frame in decoder.push(&bytes).into_iter().flatten() {
+                    if frame.data_type == FrameDataType::Ack {
+                        emulator.lock().unwrap().received_acks += 1;
+                        continue;
+                    }
+
+                    let (replies, delay) = {
+                        let mut e = emulator.lock().unwrap();
+                        if e.faults.lose_frames > 0 {
+                            e.faults.lose_frames -= 1;
+                            continue;
+                        }
+                        if e.faults.drop_acks > 0 {
+                            e.faults.drop_acks -= 1;
+                        } else {
+                            let ack = e.encode(Frame::new_ack(frame.sequence_number));
+                            e.send(ack);
+                        }
+
+                        // retransmission of something we already answered
+                        if e.last_received == Some(frame.sequence_number) {
+                            continue;
+                        }
+                        e.last_received = Some(frame.sequence_number);
+
+                        // a packet it doesn't get is ignored, same as the real thing
+                        let dialect = e.dialect();
+                        let replies: Vec<_> = MDRPacket::from_frame(frame, dialect)
+                            .unwrap_or_default()
+                            .packets
+                            .iter()
+                            .flat_map(|packet| e.reply(packet))
+                            .collect();
+                        (replies, e.faults.reply_delay)
+                    };
+
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    let mut e = emulator.lock().unwrap();
+                    for reply in replies {
+                        let bytes = e.next_frame(&reply);
+                        e.send(bytes);
+                    }
+                    if e.powered_off {
+                        e.listeners.clear();
+                        return;
+                    }
+                }
+            }
+        });
+
+        Self { emulator, inbound }
+    }
+
+    pub fn set_faults(&self, faults: EmulatorFaults) {
+        self.emulator.lock().unwrap().faults = faults;
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut EmulatedHeadphone)) {
+        f(&mut self.emulator.lock().unwrap().headphone);
+    }
+
+    pub fn headphone(&self) -> EmulatedHeadphone {
+        self.emulator.lock().unwrap().headphone.clone()
+    }
+
+    /// How many acks the host has sent us so far
+    pub fn received_acks(&self) -> usize {
+        self.emulator.lock().unwrap().received_acks
+    }
+
+    /// Push an unsolicited packet, e.g. `VolumeChangedNotify`
+    pub fn notify(&self, packet: MDRPacket) {
+        let mut e = self.emulator.lock().unwrap();
+        let bytes = e.next_frame(&packet);
+        e.send(bytes);
+    }
+}
+
+impl DeviceCommunication for EmulatedDeviceCommunication {
+    fn tx(&self) -> Sender<Vec<u8>> {
+        self.inbound.clone()
+    }
+
+    fn rx(&self) -> Receiver<Vec<u8>> {
+        let (tx, rx) = channel(24);
+        self.emulator.lock().unwrap().listeners.push(tx);
+        rx
+    }
+
+    fn close(&self) {
+        self.emulator.lock().unwrap().listeners.clear();
+    }
+}
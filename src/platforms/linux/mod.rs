@@ -0,0 +1,216 @@
+use std::sync::{self, Arc};
+
+use anyhow::Result;
+use tokio::{
+    io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        watch, Mutex,
+    },
+};
+
+use crate::platforms::{traits::DeviceCommunication, MacAddress};
+use socket::RfcommStream;
+
+pub mod sdp;
+pub mod socket;
+
+async fn wait_closed(closed: &mut watch::Receiver<bool>) {
+    let _ = closed.wait_for(|closed| *closed).await;
+}
+
+/// Talks to the headset over anything that looks like a socket.
+/// In practice this is an [`RfcommStream`], but a `UnixStream::pair()` works too
+#[derive(Debug)]
+pub struct LinuxDeviceCommunication<S = RfcommStream> {
+    /// Taken by the first `rx`, one task reads it for everyone
+    reader: Arc<sync::Mutex<Option<ReadHalf<S>>>>,
+    /// Every `rx` so far, each one gets every read
+    listeners: Arc<sync::Mutex<Vec<Sender<Vec<u8>>>>>,
+    writer: Arc<Mutex<WriteHalf<S>>>,
+    closed: watch::Sender<bool>,
+}
+
+impl LinuxDeviceCommunication<RfcommStream> {
+    pub async fn new(address: MacAddress, service_id: &str) -> Result<Self> {
+        let channel = sdp::find_rfcomm_channel(address, service_id).await?;
+        debug_println!("{address} {service_id} is on rfcomm channel {channel}");
+
+        let stream = RfcommStream::connect(address, channel).await?;
+        Ok(Self::from_stream(stream))
+    }
+}
+
+impl<S> LinuxDeviceCommunication<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    pub fn from_stream(stream: S) -> Self {
+        let (reader, writer) = split(stream);
+        let (closed, _) = watch::channel(false);
+
+        Self {
+            reader: Arc::new(sync::Mutex::new(Some(reader))),
+            listeners: Arc::default(),
+            writer: Arc::new(Mutex::new(writer)),
+            closed,
+        }
+    }
+}
+
+impl<S> Clone for LinuxDeviceCommunication<S> {
+    fn clone(&self) -> Self {
+        Self {
+            reader: self.reader.clone(),
+            listeners: self.listeners.clone(),
+            writer: self.writer.clone(),
+            closed: self.closed.clone(),
+        }
+    }
+}
+
+impl<S> DeviceCommunication for LinuxDeviceCommunication<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    fn tx(&self) -> Sender<Vec<u8>> {
+        let (tx, mut rx) = channel::<Vec<u8>>(24);
+        let writer = self.writer.clone();
+        let mut closed = self.closed.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(value) = rx.recv() => {
+                        let mut writer = writer.lock().await;
+                        if let Err(e) = writer.write_all(&value).await {
+                            debug_println!("write failed: {e}");
+                            break;
+                        }
+                    }
+                    _ = wait_closed(&mut closed) => {
+                        let _ = writer.lock().await.shutdown().await;
+                        break;
+                    }
+                    else => break,
+                }
+            }
+        });
+
+        tx
+    }
+
+    fn rx(&self) -> Receiver<Vec<u8>> {
+        let (tx, rx) = channel(24);
+        {
+            let mut listeners = self.listeners.lock().unwrap();
+            // nothing is going to be read anymore, dropping `tx` ends `rx` right away
+            if *self.closed.borrow() {
+                return rx;
+            }
+            listeners.push(tx);
+        }
+
+        let Some(mut reader) = self.reader.lock().unwrap().take() else {
+            // already being read
+            return rx;
+        };
+        let listeners = self.listeners.clone();
+        let closed_tx = self.closed.clone();
+        let mut closed = self.closed.subscribe();
+
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            loop {
+                let size = tokio::select! {
+                    result = reader.read(&mut buffer) => match result {
+                        Ok(0) => break,
+                        Ok(size) => size,
+                        Err(e) => {
+                            debug_println!("read failed: {e}");
+                            break;
+                        }
+                    },
+                    _ = wait_closed(&mut closed) => break,
+                };
+                // sent outside the lock so a slow listener doesn't block `rx` calls
+                let senders = listeners.lock().unwrap().clone();
+                for sender in senders {
+                    let _ = sender.send(buffer[0..size].to_vec()).await;
+                }
+                listeners.lock().unwrap().retain(|l| !l.is_closed());
+            }
+            // the stream is done either way, hang up the writer too and end every `rx`
+            closed_tx.send_replace(true);
+            listeners.lock().unwrap().clear();
+        });
+
+        rx
+    }
+
+    fn close(&self) {
+        self.closed.send_replace(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncReadExt, net::UnixStream};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn talks_over_a_socketpair() {
+        let (ours, mut theirs) = UnixStream::pair().unwrap();
+        let communication = LinuxDeviceCommunication::from_stream(ours);
+
+        communication.tx().send(vec![1, 2, 3]).await.unwrap();
+        let mut buffer = [0u8; 3];
+        theirs.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+
+        let mut rx = communication.rx();
+        theirs.write_all(&[4, 5]).await.unwrap();
+        assert_eq!(rx.recv().await, Some(vec![4, 5]));
+    }
+
+    #[tokio::test]
+    async fn every_rx_gets_every_read() {
+        let (ours, mut theirs) = UnixStream::pair().unwrap();
+        let communication = LinuxDeviceCommunication::from_stream(ours);
+
+        let mut first = communication.rx();
+        // used to wait forever on the reader the first one held
+        let mut second = communication.clone().rx();
+        theirs.write_all(&[7]).await.unwrap();
+        assert_eq!(first.recv().await, Some(vec![7]));
+        assert_eq!(second.recv().await, Some(vec![7]));
+
+        // one going away doesn't stop the others
+        drop(first);
+        theirs.write_all(&[8]).await.unwrap();
+        assert_eq!(second.recv().await, Some(vec![8]));
+    }
+
+    #[tokio::test]
+    async fn rx_ends_when_the_stream_does() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let communication = LinuxDeviceCommunication::from_stream(ours);
+
+        let mut rx = communication.rx();
+        drop(theirs);
+        assert_eq!(rx.recv().await, None);
+        // and later ones don't wait on a stream that's gone
+        assert_eq!(communication.rx().recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn close_ends_rx() {
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let communication = LinuxDeviceCommunication::from_stream(ours);
+
+        let mut rx = communication.rx();
+        communication.close();
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -0,0 +1,347 @@
+use std::{io, time::Duration};
+
+use anyhow::{bail, Context, Result};
+
+use crate::platforms::{
+    linux::socket::{
+        connect_l2cap_blocking, recv_blocking, send_blocking, set_recv_timeout, SDP_PSM,
+    },
+    MacAddress,
+};
+
+// Bluetooth Core Spec Vol 3 Part B
+const SDP_SERVICE_SEARCH_ATTR_REQ: u8 = 0x06;
+const SDP_SERVICE_SEARCH_ATTR_RSP: u8 = 0x07;
+const SDP_ATTR_PROTOCOL_DESCRIPTOR_LIST: u16 = 0x0004;
+const RFCOMM_UUID16: u16 = 0x0003;
+
+// one record fits in a single response, a server that keeps going is broken
+const MAX_CONTINUATIONS: u16 = 16;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 0000xxxx-0000-1000-8000-00805f9b34fb
+const BASE_UUID_TAIL: [u8; 12] = [
+    0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0x80, 0x5f, 0x9b, 0x34, 0xfb,
+];
+
+#[derive(Debug, Clone)]
+enum DataElement {
+    Uint(u64),
+    Uuid(Vec<u8>),
+    Sequence(Vec<DataElement>),
+    // nil, int, string, bool, url; we dont care about those
+    Other,
+}
+
+impl DataElement {
+    fn parse(bytes: &[u8]) -> Result<(DataElement, usize)> {
+        let Some(&descriptor) = bytes.first() else {
+            bail!("empty data element")
+        };
+        let kind = descriptor >> 3;
+        let (size, header) = match descriptor & 0x07 {
+            0 if kind == 0 => (0, 1),
+            0 => (1, 1),
+            1 => (2, 1),
+            2 => (4, 1),
+            3 => (8, 1),
+            4 => (16, 1),
+            5 => (*bytes.get(1).context("truncated size")? as usize, 2),
+            6 => (
+                u16::from_be_bytes(bytes.get(1..3).context("truncated size")?.try_into()?) as usize,
+                3,
+            ),
+            _ => (
+                u32::from_be_bytes(bytes.get(1..5).context("truncated size")?.try_into()?) as usize,
+                5,
+            ),
+        };
+        let body = bytes
+            .get(header..header + size)
+            .context("truncated data element")?;
+
+        let mut number = [0u8; 8];
+        if size <= 8 {
+            number[8 - size..].copy_from_slice(body);
+        }
+
+        let element = match kind {
+            1 => DataElement::Uint(u64::from_be_bytes(number)),
+            3 => DataElement::Uuid(body.to_vec()),
+            0 | 2 | 4 | 5 | 8 => DataElement::Other,
+            6 | 7 => {
+                let mut items = vec![];
+                let mut index = 0;
+                while index < body.len() {
+                    let (item, used) = DataElement::parse(&body[index..])?;
+                    items.push(item);
+                    index += used;
+                }
+                DataElement::Sequence(items)
+            }
+            _ => bail!("unknown data element type {kind}"),
+        };
+
+        Ok((element, header + size))
+    }
+
+    fn is_uuid16(&self, value: u16) -> bool {
+        match self {
+            DataElement::Uuid(bytes) if bytes.len() == 2 => bytes[..] == value.to_be_bytes(),
+            DataElement::Uuid(bytes) if bytes.len() == 16 => {
+                bytes[0..2] == [0, 0]
+                    && bytes[2..4] == value.to_be_bytes()
+                    && bytes[4..] == BASE_UUID_TAIL
+            }
+            _ => false,
+        }
+    }
+
+    // looking for `Sequence [Uuid(RFCOMM), Uint(channel)]`
+    fn find_rfcomm_channel(&self) -> Option<u8> {
+        let DataElement::Sequence(items) = self else {
+            return None;
+        };
+        if let [uuid, DataElement::Uint(channel), ..] = items.as_slice() {
+            if uuid.is_uuid16(RFCOMM_UUID16) {
+                return Some(*channel as u8);
+            }
+        }
+        items.iter().find_map(|item| item.find_rfcomm_channel())
+    }
+}
+
+pub fn parse_uuid(s: &str) -> Result<[u8; 16]> {
+    let hex: String = s.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        bail!("invalid uuid {s}")
+    }
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let Ok(digit) = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) else {
+            bail!("invalid hex")
+        };
+        *byte = digit;
+    }
+    Ok(bytes)
+}
+
+fn service_search_attribute_request(
+    transaction_id: u16,
+    uuid: &[u8; 16],
+    continuation: &[u8],
+) -> Vec<u8> {
+    let mut params = vec![0x35, 17, 0x1c]; // DES { uuid128 }
+    params.extend_from_slice(uuid);
+    params.extend_from_slice(&u16::MAX.to_be_bytes()); // max attribute byte count
+    params.extend_from_slice(&[0x35, 3, 0x09]); // DES { uint16 }
+    params.extend_from_slice(&SDP_ATTR_PROTOCOL_DESCRIPTOR_LIST.to_be_bytes());
+    params.push(continuation.len() as u8);
+    params.extend_from_slice(continuation);
+
+    let mut pdu = vec![SDP_SERVICE_SEARCH_ATTR_REQ];
+    pdu.extend_from_slice(&transaction_id.to_be_bytes());
+    pdu.extend_from_slice(&(params.len() as u16).to_be_bytes());
+    pdu.extend_from_slice(&params);
+    pdu
+}
+
+/// Every attribute list the server has for `uuid`, following continuations.
+/// `exchange` sends a request and reads the response into the buffer
+fn search_attribute_lists(
+    uuid: &[u8; 16],
+    mut exchange: impl FnMut(&[u8], &mut [u8]) -> io::Result<usize>,
+) -> Result<Vec<u8>> {
+    let mut attribute_lists = vec![];
+    let mut continuation = vec![];
+    let mut buffer = [0u8; 1024];
+    for transaction_id in 0..=MAX_CONTINUATIONS {
+        let request = service_search_attribute_request(transaction_id, uuid, &continuation);
+        let size = exchange(&request, &mut buffer).context("no sdp response")?;
+        let response = &buffer[..size];
+
+        if size < 7 || response[0] != SDP_SERVICE_SEARCH_ATTR_RSP {
+            bail!("unexpected sdp response {:02x?}", response)
+        }
+        let count = u16::from_be_bytes([response[5], response[6]]) as usize;
+        let lists = response
+            .get(7..7 + count)
+            .context("truncated sdp response")?;
+        attribute_lists.extend_from_slice(lists);
+
+        let continuation_len = *response.get(7 + count).context("missing continuation")? as usize;
+        continuation = response
+            .get(8 + count..8 + count + continuation_len)
+            .context("truncated continuation")?
+            .to_vec();
+        if continuation.is_empty() {
+            return Ok(attribute_lists);
+        }
+    }
+    bail!("sdp response still going after {MAX_CONTINUATIONS} continuations")
+}
+
+/// Ask the device's SDP server which RFCOMM channel `service_id` is listening on
+pub fn find_rfcomm_channel_blocking(address: MacAddress, service_id: &str) -> Result<u8> {
+    let uuid = parse_uuid(service_id)?;
+    let fd = connect_l2cap_blocking(address, SDP_PSM).context("connecting to sdp server")?;
+    set_recv_timeout(&fd, RESPONSE_TIMEOUT)?;
+
+    let attribute_lists = search_attribute_lists(&uuid, |request, buffer| {
+        send_blocking(&fd, request)?;
+        recv_blocking(&fd, buffer)
+    })?;
+    let (records, _) = DataElement::parse(&attribute_lists)?;
+    records
+        .find_rfcomm_channel()
+        .with_context(|| format!("{service_id} is not advertised by {address}"))
+}
+
+pub async fn find_rfcomm_channel(address: MacAddress, service_id: &str) -> Result<u8> {
+    let service_id = service_id.to_owned();
+    tokio::task::spawn_blocking(move || find_rfcomm_channel_blocking(address, &service_id)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICE: &str = "956c7b26-d49a-4ba8-b03f-b17d393cb6e2";
+
+    fn parse(bytes: &[u8]) -> DataElement {
+        let (element, size) = DataElement::parse(bytes).unwrap();
+        assert_eq!(size, bytes.len());
+        element
+    }
+
+    /// DES { uint16 0x0004, DES { DES { L2CAP }, DES { `rfcomm`, uint8 channel } } }
+    fn protocol_descriptor_list(rfcomm: &[u8], channel: u8) -> Vec<u8> {
+        let rfcomm_part = [
+            &[0x35, rfcomm.len() as u8 + 2][..],
+            rfcomm,
+            &[0x08, channel],
+        ]
+        .concat();
+        let list = [&[0x35, 0x03, 0x19, 0x01, 0x00][..], &rfcomm_part].concat();
+        let attributes = [&[0x09, 0x00, 0x04][..], &[0x35, list.len() as u8], &list].concat();
+        [&[0x35, attributes.len() as u8][..], &attributes].concat()
+    }
+
+    #[test]
+    fn data_elements() {
+        assert!(matches!(parse(&[0x08, 0x3c]), DataElement::Uint(0x3c)));
+        assert!(matches!(
+            parse(&[0x09, 0x01, 0x00]),
+            DataElement::Uint(0x100)
+        ));
+        assert!(matches!(
+            parse(&[0x0a, 0x00, 0x01, 0x00, 0x00]),
+            DataElement::Uint(0x10000)
+        ));
+        assert!(parse(&[0x19, 0x00, 0x03]).is_uuid16(RFCOMM_UUID16));
+        // nil, and a string
+        assert!(matches!(parse(&[0x00]), DataElement::Other));
+        assert!(matches!(
+            parse(&[0x25, 0x02, b'h', b'i']),
+            DataElement::Other
+        ));
+
+        // one, two and four byte sizes
+        for header in [
+            &[0x35, 0x04][..],
+            &[0x36, 0x00, 0x04],
+            &[0x37, 0x00, 0x00, 0x00, 0x04],
+        ] {
+            let bytes = [header, &[0x08, 0x01, 0x08, 0x02]].concat();
+            assert!(matches!(
+                parse(&bytes),
+                DataElement::Sequence(items)
+                    if matches!(items.as_slice(), [DataElement::Uint(1), DataElement::Uint(2)])
+            ));
+        }
+
+        // only as much as the element says
+        let (_, size) = DataElement::parse(&[0x08, 0x01, 0xff]).unwrap();
+        assert_eq!(size, 2);
+    }
+
+    #[test]
+    fn broken_data_elements() {
+        assert!(DataElement::parse(&[]).is_err());
+        assert!(DataElement::parse(&[0x09, 0x01]).is_err());
+        assert!(DataElement::parse(&[0x36, 0x00]).is_err());
+        // a sequence longer than what's there, and one with a broken item
+        assert!(DataElement::parse(&[0x35, 0x04, 0x08, 0x01]).is_err());
+        assert!(DataElement::parse(&[0x35, 0x02, 0x09, 0x01]).is_err());
+        assert!(DataElement::parse(&[0xf8, 0x01]).is_err());
+    }
+
+    #[test]
+    fn rfcomm_channel_in_the_protocol_list() {
+        let uuid16 = protocol_descriptor_list(&[0x19, 0x00, 0x03], 9);
+        assert_eq!(parse(&uuid16).find_rfcomm_channel(), Some(9));
+
+        let mut uuid128 = vec![0x1c, 0x00, 0x00, 0x00, 0x03];
+        uuid128.extend_from_slice(&BASE_UUID_TAIL);
+        let records = protocol_descriptor_list(&uuid128, 12);
+        assert_eq!(parse(&records).find_rfcomm_channel(), Some(12));
+
+        // l2cap isn't rfcomm
+        let l2cap = protocol_descriptor_list(&[0x19, 0x01, 0x00], 9);
+        assert_eq!(parse(&l2cap).find_rfcomm_channel(), None);
+        assert_eq!(parse(&[0x08, 0x03]).find_rfcomm_channel(), None);
+    }
+
+    fn response(lists: &[u8], continuation: &[u8]) -> Vec<u8> {
+        let mut response = vec![SDP_SERVICE_SEARCH_ATTR_RSP, 0x00, 0x00, 0x00, 0x00];
+        response.extend_from_slice(&(lists.len() as u16).to_be_bytes());
+        response.extend_from_slice(lists);
+        response.push(continuation.len() as u8);
+        response.extend_from_slice(continuation);
+        response
+    }
+
+    fn reply(buffer: &mut [u8], response: &[u8]) -> io::Result<usize> {
+        buffer[..response.len()].copy_from_slice(response);
+        Ok(response.len())
+    }
+
+    #[test]
+    fn continuations_are_followed() {
+        let uuid = parse_uuid(SERVICE).unwrap();
+        let records = protocol_descriptor_list(&[0x19, 0x00, 0x03], 9);
+        let (first, second) = records.split_at(5);
+
+        let mut requests = vec![];
+        let lists = search_attribute_lists(&uuid, |request, buffer| {
+            requests.push(request.to_vec());
+            match requests.len() {
+                1 => reply(buffer, &response(first, &[0xaa])),
+                _ => reply(buffer, &response(second, &[])),
+            }
+        })
+        .unwrap();
+        assert_eq!(lists, records);
+        // the second one carries the continuation state
+        assert_eq!(requests[1].last(), Some(&0xaa));
+    }
+
+    #[test]
+    fn endless_continuations_give_up() {
+        let uuid = parse_uuid(SERVICE).unwrap();
+        let mut rounds = 0;
+        let result = search_attribute_lists(&uuid, |_, buffer| {
+            rounds += 1;
+            reply(buffer, &response(&[0x08], &[0xaa]))
+        });
+        assert!(result.is_err());
+        assert_eq!(rounds, MAX_CONTINUATIONS as usize + 1);
+    }
+
+    #[test]
+    fn silent_server_is_an_error() {
+        let uuid = parse_uuid(SERVICE).unwrap();
+        let result = search_attribute_lists(&uuid, |_, _| Err(io::ErrorKind::WouldBlock.into()));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    io,
+    mem::size_of,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::platforms::MacAddress;
+
+// not exported by libc
+const BTPROTO_L2CAP: libc::c_int = 0;
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+pub const SDP_PSM: u16 = 0x0001;
+
+// <bluetooth/rfcomm.h>
+#[repr(C)]
+struct SockaddrRc {
+    rc_family: libc::sa_family_t,
+    rc_bdaddr: [u8; 6],
+    rc_channel: u8,
+}
+
+// <bluetooth/l2cap.h>
+#[repr(C)]
+struct SockaddrL2 {
+    l2_family: libc::sa_family_t,
+    l2_psm: u16,
+    l2_bdaddr: [u8; 6],
+    l2_cid: u16,
+    l2_bdaddr_type: u8,
+}
+
+fn socket(kind: libc::c_int, protocol: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_BLUETOOTH, kind | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn connect<T>(fd: &OwnedFd, addr: &T) -> io::Result<()> {
+    let result = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            addr as *const T as *const libc::sockaddr,
+            size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0
+        || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// MacAddress is already little endian, same as bdaddr_t
+pub fn connect_l2cap_blocking(address: MacAddress, psm: u16) -> io::Result<OwnedFd> {
+    let fd = socket(libc::SOCK_SEQPACKET, BTPROTO_L2CAP)?;
+    let addr = SockaddrL2 {
+        l2_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        l2_psm: psm.to_le(),
+        l2_bdaddr: address.0,
+        l2_cid: 0,
+        l2_bdaddr_type: 0, // BDADDR_BREDR
+    };
+    connect(&fd, &addr)?;
+    Ok(fd)
+}
+
+pub fn connect_rfcomm_blocking(address: MacAddress, channel: u8) -> io::Result<OwnedFd> {
+    let fd = socket(libc::SOCK_STREAM, BTPROTO_RFCOMM)?;
+    let addr = SockaddrRc {
+        rc_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        rc_bdaddr: address.0,
+        rc_channel: channel,
+    };
+    connect(&fd, &addr)?;
+    Ok(fd)
+}
+
+/// Blocking reads give up with `WouldBlock` after `timeout`
+pub fn set_recv_timeout(fd: &OwnedFd, timeout: Duration) -> io::Result<()> {
+    let timeval = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeval as *const libc::timeval as *const libc::c_void,
+            size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn send_blocking(fd: &OwnedFd, buffer: &[u8]) -> io::Result<usize> {
+    let size = unsafe { libc::send(fd.as_raw_fd(), buffer.as_ptr().cast(), buffer.len(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+pub fn recv_blocking(fd: &OwnedFd, buffer: &mut [u8]) -> io::Result<usize> {
+    let size = unsafe { libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// Connected RFCOMM socket driven by tokio
+#[derive(Debug)]
+pub struct RfcommStream {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl RfcommStream {
+    pub async fn connect(address: MacAddress, channel: u8) -> io::Result<RfcommStream> {
+        // connect() on bluetooth sockets can take a few seconds, dont block the runtime
+        let fd = tokio::task::spawn_blocking(move || connect_rfcomm_blocking(address, channel))
+            .await
+            .map_err(io::Error::other)??;
+        set_nonblocking(&fd)?;
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+}
+
+impl AsyncRead for RfcommStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|fd| recv_blocking(fd.get_ref(), unfilled)) {
+                Ok(Ok(size)) => {
+                    buf.advance(size);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for RfcommStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+            match guard.try_io(|fd| send_blocking(fd.get_ref(), buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = unsafe { libc::shutdown(self.fd.as_raw_fd(), libc::SHUT_RDWR) };
+        if result < 0 {
+            return Poll::Ready(Err(io::Error::last_os_error()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
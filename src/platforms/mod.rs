@@ -1,12 +1,38 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use serde::Serialize;
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+#[cfg(feature = "emulator")]
+pub mod emulator;
 pub mod traits;
-pub mod windows;
 pub mod utils;
+#[cfg(target_os = "linux")]
+pub mod linux;
+#[cfg(target_os = "windows")]
+pub mod windows;
+
+#[cfg(target_os = "linux")]
+pub type PlatformDeviceCommunication = linux::LinuxDeviceCommunication;
+#[cfg(target_os = "windows")]
+pub type PlatformDeviceCommunication = windows::WindowsDeviceCommunication;
+
+// TODO: let the ui pick a device
+#[cfg(target_os = "linux")]
+pub async fn connect(service_id: &str) -> Result<PlatformDeviceCommunication> {
+    use anyhow::Context;
+
+    let address = std::env::var("XM5_ADDRESS").context("XM5_ADDRESS is not set")?;
+    linux::LinuxDeviceCommunication::new(address.parse()?, service_id).await
+}
 
-#[derive(Clone, Copy, Debug, Serialize)]
+#[cfg(target_os = "windows")]
+pub async fn connect(service_id: &str) -> Result<PlatformDeviceCommunication> {
+    windows::WindowsDeviceCommunication::new(service_id).await
+}
+
+/// Stored little endian like `bdaddr_t`, displayed and parsed as `AA:BB:CC:DD:EE:FF`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct MacAddress([u8; 6]);
 
 impl MacAddress {
@@ -27,12 +53,58 @@ impl Display for MacAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{:02x?}::{:02x?}::{:02x?}::{:02x?}::{:02x?}::{:02x?}",
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
             self.0[5], self.0[4], self.0[3], self.0[2], self.0[1], self.0[0]
         )
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidMacAddress(pub String);
+
+impl Display for InvalidMacAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid mac address {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidMacAddress {}
+
+impl FromStr for MacAddress {
+    type Err = InvalidMacAddress;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidMacAddress(s.to_owned());
+        let mut bytes = [0u8; 6];
+        let mut parts = s.split(':');
+        // most significant byte first
+        for byte in bytes.iter_mut().rev() {
+            let part = parts.next().ok_or_else(invalid)?;
+            if part.len() != 2 {
+                return Err(invalid());
+            }
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(MacAddress(bytes))
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BluetoothDeviceInfo {
     pub name: String,
@@ -1,6 +1,15 @@
+use std::io;
+
 use anyhow::Result;
+use bytes::Bytes;
+use futures::{future, stream, SinkExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
+use tokio_util::{
+    io::{CopyToBytes, SinkWriter, StreamReader},
+    sync::PollSender,
+};
 
 use crate::platforms::MacAddress;
 
@@ -16,6 +25,26 @@ pub trait DeviceCommunication {
     fn close(&self);
 }
 
+/// `tx` and `rx` as a single `AsyncRead + AsyncWrite`, so it can go through a codec
+pub fn communication_io(
+    communication: &impl DeviceCommunication,
+) -> impl AsyncRead + AsyncWrite + Send + 'static {
+    let rx = communication.rx();
+    let incoming = stream::unfold(rx, |mut rx| async move {
+        let bytes = rx.recv().await?;
+        Some((Ok::<_, io::Error>(Bytes::from(bytes)), rx))
+    });
+
+    let outgoing = PollSender::new(communication.tx())
+        .sink_map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+        .with(|bytes: Bytes| future::ready(Ok::<_, io::Error>(bytes.to_vec())));
+
+    tokio::io::join(
+        StreamReader::new(Box::pin(incoming)),
+        SinkWriter::new(CopyToBytes::new(outgoing)),
+    )
+}
+
 pub trait ServiceHandler {
     async fn send(&self, buffer: &[u8]) -> Result<()>;
     fn receive_rx(&self) -> Result<Receiver<u8>>;
@@ -35,7 +35,7 @@ impl WindowsDeviceCommunication {
         let service = services.pop().unwrap();
         let service = RfcommDeviceService::FromIdAsync(&service.Id()?)?.await?;
         let a = service.Device()?;
-        println!("{service:?} {:?}", a.Name()?);
+        debug_println!("{service:?} {:?}", a.Name()?);
 
         let socket: StreamSocket = StreamSocket::new()?;
         socket
@@ -0,0 +1,179 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{
+    platforms::traits::DeviceCommunication,
+    protocols::{
+        connection::{HeadphoneAppCommand, HeadphoneConnection},
+        mdr::{DetectedActivity, NcAsmMode, NcAsmParam},
+        properties::HeadphoneProperty,
+    },
+};
+
+// Adaptive Sound Control on the headphone only detects what we are doing, switching noise control
+// to match is the app's job. the sony app keeps a setting per activity and so do we
+
+/// Noise control to switch to for one activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityProfile {
+    pub mode: NcAsmMode,
+    /// Only matters in ambient mode
+    #[serde(default)]
+    pub ambient_level: u8,
+    #[serde(default)]
+    pub voice_passthrough: bool,
+}
+
+impl ActivityProfile {
+    pub fn new(mode: NcAsmMode, ambient_level: u8, voice_passthrough: bool) -> Self {
+        Self {
+            mode,
+            ambient_level,
+            voice_passthrough,
+        }
+    }
+
+    pub fn param(&self) -> NcAsmParam {
+        NcAsmParam::new(self.mode, self.ambient_level, self.voice_passthrough)
+    }
+
+    /// Whether the headphone is already there, the inquired type doesn't count
+    fn is_applied(&self, current: &NcAsmParam) -> bool {
+        let param = self.param();
+        current.mode == param.mode
+            && current.ambient_level == param.ambient_level
+            && current.voice_passthrough == param.voice_passthrough
+    }
+}
+
+/// Stored as json, see `default_profiles_path`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdaptiveProfiles {
+    pub staying: ActivityProfile,
+    pub walking: ActivityProfile,
+    pub running: ActivityProfile,
+    pub transport: ActivityProfile,
+}
+
+impl Default for AdaptiveProfiles {
+    // close to what the sony app starts with
+    fn default() -> Self {
+        Self {
+            staying: ActivityProfile::new(NcAsmMode::NoiseCancelling, 0, false),
+            walking: ActivityProfile::new(NcAsmMode::AmbientSound, 10, true),
+            running: ActivityProfile::new(NcAsmMode::AmbientSound, 20, false),
+            transport: ActivityProfile::new(NcAsmMode::NoiseCancelling, 0, false),
+        }
+    }
+}
+
+impl AdaptiveProfiles {
+    pub fn get(&self, activity: DetectedActivity) -> ActivityProfile {
+        match activity {
+            DetectedActivity::Staying => self.staying,
+            DetectedActivity::Walking => self.walking,
+            DetectedActivity::Running => self.running,
+            DetectedActivity::Transport => self.transport,
+        }
+    }
+
+    pub fn set(&mut self, activity: DetectedActivity, profile: ActivityProfile) {
+        match activity {
+            DetectedActivity::Staying => self.staying = profile,
+            DetectedActivity::Walking => self.walking = profile,
+            DetectedActivity::Running => self.running = profile,
+            DetectedActivity::Transport => self.transport = profile,
+        }
+    }
+
+    /// A missing file means the defaults
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// `$XM5_ADAPTIVE_PROFILES`, or `xm5-thing/adaptive.json` in the config dir
+pub fn default_profiles_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("XM5_ADAPTIVE_PROFILES") {
+        return PathBuf::from(path);
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| std::env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(std::env::temp_dir);
+    config_dir.join("xm5-thing").join("adaptive.json")
+}
+
+/// Applies the profile for the detected activity while Adaptive Sound Control is on.
+/// Stops when dropped
+pub struct AdaptiveSound {
+    task: JoinHandle<()>,
+}
+
+impl Drop for AdaptiveSound {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl AdaptiveSound {
+    /// The profiles are read again on every switch, so edits apply without a restart
+    pub fn start<D>(connection: HeadphoneConnection<D>, profiles_path: PathBuf) -> Self
+    where
+        D: DeviceCommunication + Clone + Send + Sync + 'static,
+    {
+        let task = tokio::spawn(async move {
+            let mut rx = connection.properties_rx();
+            while let Some(change) = rx.recv().await {
+                let relevant = change.changed.iter().any(|property| {
+                    matches!(
+                        property,
+                        HeadphoneProperty::AutoNcAsm | HeadphoneProperty::Activity
+                    )
+                });
+                let properties = change.properties;
+                if !relevant || properties.auto_nc_asm != Some(true) {
+                    continue;
+                }
+                let Some(activity) = properties.activity else {
+                    continue;
+                };
+
+                let profiles = AdaptiveProfiles::load(&profiles_path).unwrap_or_else(|e| {
+                    debug_println!("Can't read {}: {e}", profiles_path.display());
+                    AdaptiveProfiles::default()
+                });
+                let profile = profiles.get(activity);
+                if properties
+                    .nc_asm
+                    .is_some_and(|current| profile.is_applied(&current))
+                {
+                    continue;
+                }
+                debug_println!("{activity:?} detected, switching to {profile:?}");
+                let command = HeadphoneAppCommand::SetNcAsm(profile.param());
+                if let Err(e) = connection.send(command).await {
+                    debug_println!("Can't apply the {activity:?} profile: {e}");
+                }
+            }
+        });
+        Self { task }
+    }
+}
@@ -0,0 +1,553 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, task::JoinHandle, time::Instant};
+
+use crate::{
+    platforms::traits::DeviceCommunication,
+    protocols::{
+        connection::HeadphoneConnection,
+        mdr::{BatteryInquiredType, FunctionType, MDRPacket},
+        properties::{BatteryLevel, HeadphoneProperties, HeadphoneProperty},
+    },
+};
+
+// keeps an eye on the battery for as long as the connection lives
+// most headphones push `CommonNtfyBatteryLevel` on their own, polling is for the ones that don't
+
+/// Samples further apart than this are not the same discharge, the headphone was probably off
+const RUN_GAP: TimeDelta = TimeDelta::hours(1);
+/// Roughly a few months of use, older samples are dropped from memory and the file alike
+const MAX_HISTORY_SAMPLES: usize = 5000;
+
+#[derive(Debug, Clone)]
+pub struct BatteryMonitorConfig {
+    /// Ask the headphone if it didn't say anything for this long
+    pub poll_interval: Duration,
+    /// `BatteryEvent::Low` fires when it drops below this while not charging
+    pub low_threshold: u8,
+    /// Nothing is written if `None`
+    pub history_path: Option<PathBuf>,
+}
+
+impl Default for BatteryMonitorConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(5 * 60),
+            low_threshold: 20,
+            history_path: Some(default_history_path()),
+        }
+    }
+}
+
+impl BatteryMonitorConfig {
+    /// The default, with `$XM5_LOW_BATTERY` (percent) and `$XM5_BATTERY_POLL` (seconds) applied
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let var = |name| -> Option<u64> { std::env::var(name).ok()?.parse().ok() };
+        if let Some(threshold) = var("XM5_LOW_BATTERY") {
+            config.low_threshold = threshold.min(100) as u8;
+        }
+        if let Some(seconds) = var("XM5_BATTERY_POLL") {
+            config.poll_interval = Duration::from_secs(seconds);
+        }
+        config
+    }
+}
+
+/// `$XM5_BATTERY_HISTORY`, or `xm5-thing/battery.jsonl` in the data dir
+pub fn default_history_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("XM5_BATTERY_HISTORY") {
+        return PathBuf::from(path);
+    }
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .or_else(|| std::env::var_os("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir);
+    data_dir.join("xm5-thing").join("battery.jsonl")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatterySample {
+    pub time: DateTime<Utc>,
+    pub level: u8,
+    pub is_charging: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatteryEvent {
+    /// Once per discharge, charging or going back above the threshold rearms it
+    Low { level: u8, threshold: u8 },
+}
+
+/// Only changes are kept, one json object per line on disk
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatteryHistory {
+    pub samples: Vec<BatterySample>,
+}
+
+impl BatteryHistory {
+    /// A missing file is an empty history, lines that don't parse are skipped
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let mut samples = vec![];
+        for line in BufReader::new(file).lines() {
+            if let Ok(sample) = serde_json::from_str(&line?) {
+                samples.push(sample);
+            }
+        }
+
+        if samples.len() > MAX_HISTORY_SAMPLES {
+            samples.drain(..samples.len() - MAX_HISTORY_SAMPLES);
+            let history = Self { samples };
+            history.save(path)?;
+            return Ok(history);
+        }
+        Ok(Self { samples })
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut bytes = vec![];
+        for sample in &self.samples {
+            serde_json::to_writer(&mut bytes, sample)?;
+            bytes.push(b'\n');
+        }
+        fs::write(path, bytes)
+    }
+
+    fn append(path: &Path, sample: &BatterySample) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_vec(sample)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)
+    }
+
+    /// Returns whether it was kept, same level and charging state as the last one isn't
+    pub fn push(&mut self, sample: BatterySample) -> bool {
+        let unchanged = self.samples.last().is_some_and(|last| {
+            last.level == sample.level && last.is_charging == sample.is_charging
+        });
+        if unchanged {
+            return false;
+        }
+        self.samples.push(sample);
+        if self.samples.len() > MAX_HISTORY_SAMPLES {
+            self.samples
+                .drain(..self.samples.len() - MAX_HISTORY_SAMPLES);
+        }
+        true
+    }
+
+    /// `push`, then the same on disk
+    fn push_to(&mut self, sample: BatterySample, path: &Path) -> io::Result<bool> {
+        let full = self.samples.len() >= MAX_HISTORY_SAMPLES;
+        if !self.push(sample) {
+            return Ok(false);
+        }
+        // the oldest line has to go as well
+        if full {
+            self.save(path)?;
+        } else {
+            Self::append(path, &sample)?;
+        }
+        Ok(true)
+    }
+
+    /// The samples since it was last charged, oldest first
+    fn discharge(&self) -> &[BatterySample] {
+        let mut start = self.samples.len();
+        while let Some(sample) = start.checked_sub(1).map(|i| &self.samples[i]) {
+            if sample.is_charging {
+                break;
+            }
+            if let Some(next) = self.samples.get(start) {
+                if sample.level < next.level || next.time - sample.time > RUN_GAP {
+                    break;
+                }
+            }
+            start -= 1;
+        }
+        &self.samples[start..]
+    }
+
+    /// Time until empty at the current rate, `None` while charging or until it dropped a bit
+    pub fn estimate_remaining(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let discharge = self.discharge();
+        let (first, last) = (discharge.first()?, discharge.last()?);
+        if first.level <= last.level || now - last.time > RUN_GAP {
+            return None;
+        }
+
+        // levels are whole percents, a fit over all of them is steadier than the two ends
+        let points: Vec<(f64, f64)> = discharge
+            .iter()
+            .map(|s| ((s.time - first.time).num_seconds() as f64, s.level as f64))
+            .collect();
+        let count = points.len() as f64;
+        let mean_time = points.iter().map(|(t, _)| t).sum::<f64>() / count;
+        let mean_level = points.iter().map(|(_, l)| l).sum::<f64>() / count;
+        let covariance: f64 = points
+            .iter()
+            .map(|(t, l)| (t - mean_time) * (l - mean_level))
+            .sum();
+        let variance: f64 = points.iter().map(|(t, _)| (t - mean_time).powi(2)).sum();
+        if variance == 0.0 {
+            return None;
+        }
+        let per_second = covariance / variance;
+        if per_second >= 0.0 {
+            return None;
+        }
+
+        let since_last = (now - last.time).num_seconds() as f64;
+        let remaining = last.level as f64 / -per_second - since_last;
+        Some(Duration::from_secs_f64(remaining.max(0.0)))
+    }
+}
+
+/// What's tracked, earbuds go by whichever side is lower
+pub fn tracked_battery(properties: &HeadphoneProperties) -> Option<BatteryLevel> {
+    properties.battery.or_else(|| {
+        properties
+            .left_right_battery
+            .map(|battery| std::cmp::min_by_key(battery.left, battery.right, |b| b.level))
+    })
+}
+
+struct Monitor {
+    history: Mutex<BatteryHistory>,
+    events_tx: broadcast::Sender<BatteryEvent>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+/// Stops when the last clone is dropped
+#[derive(Clone)]
+pub struct BatteryMonitor {
+    monitor: Arc<Monitor>,
+}
+
+impl BatteryMonitor {
+    pub fn start<D>(connection: HeadphoneConnection<D>, config: BatteryMonitorConfig) -> Self
+    where
+        D: DeviceCommunication + Clone + Send + Sync + 'static,
+    {
+        let history = match &config.history_path {
+            Some(path) => BatteryHistory::load(path).unwrap_or_else(|e| {
+                debug_println!("Can't read battery history {}: {e}", path.display());
+                BatteryHistory::default()
+            }),
+            None => BatteryHistory::default(),
+        };
+        let (events_tx, _) = broadcast::channel(8);
+        let monitor = Arc::new(Monitor {
+            history: Mutex::new(history),
+            events_tx,
+            task: Mutex::new(None),
+        });
+
+        // weak so the task doesn't keep the monitor alive
+        let weak = Arc::downgrade(&monitor);
+        let task = tokio::spawn(async move {
+            let mut rx = connection.properties_rx();
+            let mut low_sent = false;
+            let poll = tokio::time::sleep(config.poll_interval);
+            tokio::pin!(poll);
+
+            // whatever is already known counts as the first sample
+            let mut battery = tracked_battery(&connection.properties());
+            loop {
+                if let Some(battery) = battery.take() {
+                    poll.as_mut().reset(Instant::now() + config.poll_interval);
+                    let Some(monitor) = weak.upgrade() else { break };
+                    monitor.record(battery, &config, &mut low_sent);
+                }
+
+                tokio::select! {
+                    change = rx.recv() => {
+                        let Some(change) = change else { break };
+                        let battery_changed = change.changed.iter().any(|property| {
+                            matches!(
+                                property,
+                                HeadphoneProperty::Battery | HeadphoneProperty::LeftRightBattery
+                            )
+                        });
+                        if battery_changed {
+                            battery = tracked_battery(&change.properties);
+                        }
+                    }
+                    _ = &mut poll => {
+                        poll.as_mut().reset(Instant::now() + config.poll_interval);
+                        // an unchanged answer doesn't show up in `properties_rx`
+                        let properties = connection.properties();
+                        let inquired_type = if properties.battery.is_none()
+                            && properties.supports_any(&[FunctionType::LeftRightBatteryLevel])
+                        {
+                            BatteryInquiredType::LeftRightBattery
+                        } else {
+                            BatteryInquiredType::Battery
+                        };
+                        match connection.request(MDRPacket::CommonGetBatteryLevel { inquired_type }).await {
+                            Ok(_) => battery = tracked_battery(&connection.properties()),
+                            Err(e) => debug_println!("Battery poll failed: {e}"),
+                        }
+                        // not tracked, but kept fresh for whoever shows it
+                        if properties.supports_any(&[FunctionType::CradleBatteryLevel]) {
+                            let request = MDRPacket::CommonGetBatteryLevel {
+                                inquired_type: BatteryInquiredType::CradleBattery,
+                            };
+                            if let Err(e) = connection.request(request).await {
+                                debug_println!("Cradle battery poll failed: {e}");
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        *monitor.task.lock().unwrap() = Some(task);
+
+        Self { monitor }
+    }
+
+    /// Low battery warnings
+    pub fn subscribe(&self) -> broadcast::Receiver<BatteryEvent> {
+        self.monitor.events_tx.subscribe()
+    }
+
+    pub fn history(&self) -> BatteryHistory {
+        self.monitor.history.lock().unwrap().clone()
+    }
+
+    pub fn estimate_remaining(&self) -> Option<Duration> {
+        self.monitor
+            .history
+            .lock()
+            .unwrap()
+            .estimate_remaining(Utc::now())
+    }
+}
+
+impl Monitor {
+    fn record(&self, battery: BatteryLevel, config: &BatteryMonitorConfig, low_sent: &mut bool) {
+        let sample = BatterySample {
+            time: Utc::now(),
+            level: battery.level,
+            is_charging: battery.is_charging,
+        };
+        let mut history = self.history.lock().unwrap();
+        match &config.history_path {
+            Some(path) => {
+                if let Err(e) = history.push_to(sample, path) {
+                    debug_println!("Can't write battery history {}: {e}", path.display());
+                }
+            }
+            None => {
+                history.push(sample);
+            }
+        }
+        drop(history);
+
+        let is_low = !battery.is_charging && battery.level < config.low_threshold;
+        if is_low && !*low_sent {
+            let _ = self.events_tx.send(BatteryEvent::Low {
+                level: battery.level,
+                threshold: config.low_threshold,
+            });
+        }
+        *low_sent = is_low;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap() + TimeDelta::minutes(minutes)
+    }
+
+    fn sample(minutes: i64, level: u8, is_charging: bool) -> BatterySample {
+        BatterySample {
+            time: at(minutes),
+            level,
+            is_charging,
+        }
+    }
+
+    fn history(samples: &[BatterySample]) -> BatteryHistory {
+        let mut history = BatteryHistory::default();
+        for sample in samples {
+            history.push(*sample);
+        }
+        history
+    }
+
+    fn assert_close(estimate: Option<Duration>, expected: Duration) {
+        let estimate = estimate.expect("no estimate");
+        let off = estimate.abs_diff(expected);
+        assert!(
+            off < Duration::from_secs(1),
+            "{estimate:?}, not {expected:?}"
+        );
+    }
+
+    #[test]
+    fn steady_drain_runs_out_on_time() {
+        // 1% every 10 minutes
+        let samples: Vec<_> = (0..=10)
+            .map(|i| sample(i * 10, 100 - i as u8, false))
+            .collect();
+        let history = history(&samples);
+
+        assert_close(
+            history.estimate_remaining(at(100)),
+            Duration::from_secs(90 * 10 * 60),
+        );
+        // time since the last change counts too
+        assert_close(
+            history.estimate_remaining(at(130)),
+            Duration::from_secs(87 * 10 * 60),
+        );
+    }
+
+    #[test]
+    fn fit_goes_through_every_sample() {
+        // a quick start then a long 1%, the two ends alone would say 3% in 40 minutes
+        let history = history(&[
+            sample(0, 100, false),
+            sample(5, 99, false),
+            sample(10, 98, false),
+            sample(40, 97, false),
+        ]);
+        // worked out by hand, the slope is 2% every 31 minutes
+        assert_close(
+            history.estimate_remaining(at(40)),
+            Duration::from_secs(97 * 31 * 60 / 2),
+        );
+    }
+
+    #[test]
+    fn only_the_current_discharge_counts() {
+        let before_charging = [
+            sample(0, 100, false),
+            sample(300, 40, false),
+            sample(310, 40, true),
+            sample(400, 90, false),
+        ];
+
+        // nothing to go on yet
+        assert_eq!(history(&before_charging).estimate_remaining(at(400)), None);
+
+        let mut samples = before_charging.to_vec();
+        samples.extend([sample(410, 89, false), sample(420, 88, false)]);
+        assert_close(
+            history(&samples).estimate_remaining(at(420)),
+            Duration::from_secs(88 * 10 * 60),
+        );
+
+        // off for a while, then a new run
+        samples.extend([sample(600, 87, false), sample(605, 86, false)]);
+        assert_close(
+            history(&samples).estimate_remaining(at(605)),
+            Duration::from_secs(86 * 5 * 60),
+        );
+    }
+
+    #[test]
+    fn no_estimate_without_a_drain() {
+        let charging = history(&[sample(0, 50, true), sample(10, 60, true)]);
+        assert_eq!(charging.estimate_remaining(at(10)), None);
+
+        let one = history(&[sample(0, 50, false)]);
+        assert_eq!(one.estimate_remaining(at(10)), None);
+
+        // nothing heard in a while, it was probably off
+        let stale = history(&[sample(0, 50, false), sample(10, 49, false)]);
+        assert!(stale.estimate_remaining(at(10)).is_some());
+        assert_eq!(stale.estimate_remaining(at(100)), None);
+    }
+
+    #[test]
+    fn history_is_trimmed_in_memory_and_on_disk() {
+        let path = std::env::temp_dir().join(format!("xm5-battery-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = BatteryHistory::default();
+        let samples = (0..MAX_HISTORY_SAMPLES as i64 + 3).map(|i| sample(i, (i % 2) as u8, false));
+        for sample in samples {
+            assert!(history.push_to(sample, &path).unwrap());
+        }
+        assert!(!history.push_to(sample(99_999, 0, false), &path).unwrap());
+
+        assert_eq!(history.samples.len(), MAX_HISTORY_SAMPLES);
+        assert_eq!(history.samples[0].time, at(3));
+        assert_eq!(BatteryHistory::load(&path).unwrap(), history);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn low_fires_once_per_discharge() {
+        let (events_tx, mut events) = broadcast::channel(8);
+        let monitor = Monitor {
+            history: Mutex::new(BatteryHistory::default()),
+            events_tx,
+            task: Mutex::new(None),
+        };
+        let config = BatteryMonitorConfig {
+            low_threshold: 20,
+            history_path: None,
+            ..Default::default()
+        };
+        let mut low_sent = false;
+        let mut record = |level, is_charging| {
+            monitor.record(BatteryLevel { level, is_charging }, &config, &mut low_sent);
+            std::iter::from_fn(|| events.try_recv().ok()).collect::<Vec<_>>()
+        };
+        let low = |level| {
+            vec![BatteryEvent::Low {
+                level,
+                threshold: 20,
+            }]
+        };
+
+        assert_eq!(record(21, false), vec![]);
+        assert_eq!(record(19, false), low(19));
+        assert_eq!(record(15, false), vec![]);
+        assert_eq!(record(10, false), vec![]);
+        // charging rearms it
+        assert_eq!(record(12, true), vec![]);
+        assert_eq!(record(12, false), low(12));
+        // and so does going back above the threshold
+        assert_eq!(record(25, false), vec![]);
+        assert_eq!(record(18, false), low(18));
+    }
+}
@@ -1,26 +1,208 @@
-use serde::Serialize;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc::{Receiver, Sender},
+    oneshot, watch,
+};
 
 use crate::{
-    platforms::{traits::DeviceCommunication, BluetoothDeviceInfo, MacAddress},
+    platforms::{
+        traits::{communication_io, DeviceCommunication},
+        BluetoothDeviceInfo, MacAddress,
+    },
     protocols::{
-        frame::{Frame, FrameDataType},
-        mdr::{BatteryInquiredType, MDRPacket},
-        properties::{self, HeadphoneProperties},
+        link::{FrameLink, LinkConfig, LinkError},
+        mdr::{
+            AssignableKey, AssignablePreset, AudioInquiredType, AutoPowerOff, BatteryInquiredType,
+            ConnectionMode, DeviceInfoInquiredType, EqEbbInquiredType, EqEbbParam, EqParam,
+            EqPreset, FramePackets, FunctionType, KeyAssignment, MDRPacket, MultipointAction,
+            NcAsmInquiredType, NcAsmMode, NcAsmParam, OnOffSetting, SmartTalkingConfig,
+            SystemInquiredType, EQ_CUSTOM_BAND_COUNT, EQ_LEVEL_OFFSET,
+        },
+        properties::{HeadphoneProperties, PropertiesChanged},
     },
 };
 
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    Link(LinkError),
+    /// The packet is acked but never answered, use `send_packet` instead
+    NoReplyExpected,
+    Timeout,
+    /// The headphone didn't list anything the command needs, see `HeadphoneAppCommand::required_functions`,
+    /// or its dialect has no such packet
+    Unsupported,
+    /// Not one of the options the headphone reported, e.g. an assignment its key can't take or an
+    /// eq preset it doesn't have
+    UnavailableOption,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Link(e) => write!(f, "{}", e),
+            RequestError::NoReplyExpected => write!(f, "Packet has no reply"),
+            RequestError::Timeout => write!(f, "Timed out waiting for reply"),
+            RequestError::Unsupported => write!(f, "Not supported by this headphone"),
+            RequestError::UnavailableOption => write!(f, "Not an option on this headphone"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<LinkError> for RequestError {
+    fn from(err: LinkError) -> Self {
+        RequestError::Link(err)
+    }
+}
+
+/// How the link went away, see `HeadphoneConnection::disconnected`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Disconnect {
+    /// We closed it, or asked the headphone to turn off
+    Expected,
+    /// Out of range, turned off by hand, or anything else we didn't ask for
+    Lost,
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    id: u64,
+    request: MDRPacket,
+    reply_tx: oneshot::Sender<MDRPacket>,
+}
+
+#[derive(Debug, Default)]
+struct PendingRequests {
+    next_id: u64,
+    requests: Vec<PendingRequest>,
+}
+
 #[derive(Debug, Clone)]
 pub struct HeadphoneConnection<D: DeviceCommunication> {
     // device_info: BluetoothDeviceInfo,
-    properties: HeadphoneProperties,
+    properties: Arc<Mutex<HeadphoneProperties>>,
     communication: D,
+    link: FrameLink,
+    pending: Arc<Mutex<PendingRequests>>,
+    packets_tx: broadcast::Sender<MDRPacket>,
+    changes_tx: broadcast::Sender<PropertiesChanged>,
+    /// Set before we drop the link on purpose
+    closing: Arc<AtomicBool>,
+    disconnected_rx: watch::Receiver<Option<Disconnect>>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum HeadphoneAppCommand {
+    /// Move playback to another connected source
     SwitchDevice(MacAddress),
     EnablePinning(bool),
+    ConnectDevice(MacAddress),
+    DisconnectDevice(MacAddress),
+    /// Forget the pairing, the device has to be paired again from scratch
+    UnpairDevice(MacAddress),
+    SetNcAsm(NcAsmParam),
+    SetEqPreset(EqPreset),
+    /// dB, clamped to what the headphone supports
+    SetEqBands([i8; EQ_CUSTOM_BAND_COUNT]),
+    SetClearBass(i8),
+    EnableSpeakToChat(bool),
+    SetSpeakToChat(SmartTalkingConfig),
+    EnableAutoNcAsm(bool),
+    /// DSEE
+    EnableUpscaling(bool),
+    /// Might make the headphone reconnect to switch codec
+    SetConnectionMode(ConnectionMode),
+    SetAutoPowerOff(AutoPowerOff),
+    /// Pause when taken off, play again when put back on
+    EnableControlByWearing(bool),
+    /// Checked against `HeadphoneProperties::assignable_capability`
+    SetAssignment(AssignableKey, AssignablePreset),
+    /// The link drops right after, `disconnected` resolves with `Disconnect::Expected`
+    PowerOff,
+}
+
+// a headphone needs any one of these
+const MULTIPOINT_FUNCTIONS: &[FunctionType] = &[FunctionType::PairingDeviceManagementClassicBt];
+const NC_FUNCTIONS: &[FunctionType] = &[
+    FunctionType::NoiseCancelling,
+    FunctionType::NoiseCancellingAndAmbientSoundMode,
+];
+const ASM_FUNCTIONS: &[FunctionType] = &[
+    FunctionType::AmbientSoundMode,
+    FunctionType::NoiseCancellingAndAmbientSoundMode,
+];
+const NC_ASM_FUNCTIONS: &[FunctionType] = &[
+    FunctionType::NoiseCancelling,
+    FunctionType::NoiseCancellingAndAmbientSoundMode,
+    FunctionType::AmbientSoundMode,
+];
+const EQ_FUNCTIONS: &[FunctionType] = &[
+    FunctionType::PresetEq,
+    FunctionType::PresetEqNoncustomizable,
+];
+
+impl HeadphoneAppCommand {
+    /// The headphone has to support any one of these for the command to do anything
+    pub fn required_functions(&self) -> &'static [FunctionType] {
+        match self {
+            HeadphoneAppCommand::SwitchDevice(_)
+            | HeadphoneAppCommand::EnablePinning(_)
+            | HeadphoneAppCommand::ConnectDevice(_)
+            | HeadphoneAppCommand::DisconnectDevice(_)
+            | HeadphoneAppCommand::UnpairDevice(_) => MULTIPOINT_FUNCTIONS,
+            HeadphoneAppCommand::SetNcAsm(param) => match param.mode {
+                NcAsmMode::NoiseCancelling | NcAsmMode::WindNoiseReduction => NC_FUNCTIONS,
+                NcAsmMode::AmbientSound => ASM_FUNCTIONS,
+                NcAsmMode::Off => NC_ASM_FUNCTIONS,
+            },
+            HeadphoneAppCommand::SetEqPreset(_) => EQ_FUNCTIONS,
+            // the noncustomizable one only has presets
+            HeadphoneAppCommand::SetEqBands(_) => &[FunctionType::PresetEq],
+            HeadphoneAppCommand::SetClearBass(_) => &[FunctionType::Ebb],
+            HeadphoneAppCommand::EnableSpeakToChat(_) | HeadphoneAppCommand::SetSpeakToChat(_) => {
+                &[FunctionType::SmartTalkingMode]
+            }
+            HeadphoneAppCommand::EnableAutoNcAsm(_) => &[FunctionType::AutoNcAsm],
+            HeadphoneAppCommand::EnableUpscaling(_) => &[FunctionType::Upscaling],
+            HeadphoneAppCommand::SetConnectionMode(_) => &[FunctionType::ConnectionMode],
+            HeadphoneAppCommand::SetAutoPowerOff(_) => &[FunctionType::AutoPowerOff],
+            HeadphoneAppCommand::EnableControlByWearing(_) => &[FunctionType::ControlByWearing],
+            HeadphoneAppCommand::SetAssignment(..) => &[FunctionType::AssignableSettings],
+            HeadphoneAppCommand::PowerOff => &[FunctionType::PowerOff],
+        }
+    }
+
+    pub fn is_supported(&self, properties: &HeadphoneProperties) -> bool {
+        properties.supports_any(self.required_functions())
+    }
+}
+
+// ask for the one it has, older and smaller ones only do half of it
+fn nc_asm_inquired_type(properties: &HeadphoneProperties) -> NcAsmInquiredType {
+    let Some(functions) = &properties.supported_functions else {
+        return NcAsmInquiredType::NoiseCancellingAndAmbientSoundMode;
+    };
+    if functions.contains(FunctionType::NoiseCancellingAndAmbientSoundMode) {
+        NcAsmInquiredType::NoiseCancellingAndAmbientSoundMode
+    } else if functions.contains(FunctionType::NoiseCancelling) {
+        NcAsmInquiredType::NoiseCancelling
+    } else {
+        NcAsmInquiredType::AmbientSoundMode
+    }
 }
 
 // we should have 1 actor to deal with Actual stuff
@@ -28,64 +210,538 @@ pub enum HeadphoneAppCommand {
 // exposed event on_property_change to ui
 
 impl<D: DeviceCommunication> HeadphoneConnection<D> {
-    pub async fn new(mut communication: D) -> Self {
+    pub async fn new(communication: D) -> Self {
         // pub async fn new(device_info: BluetoothDeviceInfo, mut communication: D) -> Self {
-        let properties = HeadphoneProperties::new(&mut communication).await;
+        let properties = Arc::new(Mutex::new(HeadphoneProperties::default()));
+        let (link, mut frame_rx) =
+            FrameLink::new(communication_io(&communication), LinkConfig::default());
+        let pending = Arc::new(Mutex::new(PendingRequests::default()));
+        let (packets_tx, _) = broadcast::channel(64);
+        let (changes_tx, _) = broadcast::channel(64);
+        let closing = Arc::new(AtomicBool::new(false));
+        let (disconnected_tx, disconnected_rx) = watch::channel(None);
+
+        let p = pending.clone();
+        let tx = packets_tx.clone();
+        let props = properties.clone();
+        let c_tx = changes_tx.clone();
+        let c = closing.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = frame_rx.recv().await {
+                debug_println!(" 𐘀 {}", frame);
+                let dialect = props.lock().unwrap().dialect();
+                let packets = match MDRPacket::from_frame(frame, dialect) {
+                    Ok(FramePackets { packets, leftover }) => {
+                        // the packets before it are still good
+                        if leftover > 0 {
+                            debug_println!("{} bytes left after the last packet", leftover);
+                        }
+                        packets
+                    }
+                    Err(e) => {
+                        debug_println!("Error parsing packet: {}", e);
+                        continue;
+                    }
+                };
+                for packet in packets {
+                    debug_println!("   𐘀 {:.?}", packet);
+                    if let MDRPacket::Unknown { opcode, .. } = packet {
+                        debug_println!("   not described yet: 0x{:02x}", opcode);
+                    }
+                    let mut properties = props.lock().unwrap();
+                    let changed = properties.update(&packet);
+                    if !changed.is_empty() {
+                        let _ = c_tx.send(PropertiesChanged {
+                            properties: properties.clone(),
+                            changed,
+                        });
+                    }
+                    drop(properties);
+
+                    // after the update, so whoever asked sees the new properties
+                    let mut pending = p.lock().unwrap();
+                    if let Some(index) = pending
+                        .requests
+                        .iter()
+                        .position(|r| packet.is_reply_to(&r.request))
+                    {
+                        let request = pending.requests.remove(index);
+                        let _ = request.reply_tx.send(packet.clone());
+                    }
+                    drop(pending);
+
+                    // everything still goes to subscribers, replies included
+                    let _ = tx.send(packet);
+                }
+            }
+
+            // nothing is going to answer them, dropping `reply_tx` fails them with `LinkError::Closed`
+            p.lock().unwrap().requests.clear();
+
+            // the link only stops when the bytes do
+            let disconnect = if c.load(Ordering::SeqCst) {
+                Disconnect::Expected
+            } else {
+                Disconnect::Lost
+            };
+            debug_println!("Link is gone: {disconnect:?}");
+            let _ = disconnected_tx.send(Some(disconnect));
+        });
 
         Self {
             // device_info,
             properties,
             communication,
+            link,
+            pending,
+            packets_tx,
+            changes_tx,
+            closing,
+            disconnected_rx,
         }
     }
 
-    pub async fn send(&mut self, command: HeadphoneAppCommand) {
-        let value = vec![0];
-        self.communication.tx().send(value).await.unwrap();
+    /// Latest known state, see `properties_rx` to get notified
+    pub fn properties(&self) -> HeadphoneProperties {
+        self.properties.lock().unwrap().clone()
     }
 
-    // TODO: make this callable only once
-    // we should start this immediately once new'ed
-    pub fn properties_rx(&self) -> Receiver<HeadphoneProperties> {
-        let (tx, rx) = tokio::sync::mpsc::channel(24);
-        // this shit again...
-        let byte_rx = self.communication.rx();
-        let mut frame_rx = Frame::from_byte_stream(byte_rx);
-        let communication_tx = self.communication.tx();
-
-        let ack = async move |seq| {
-            let frame = Frame::new_ack(seq);
-            communication_tx.send(frame.into()).await.unwrap();
+    /// Ask the headphone for everything `HeadphoneProperties` holds.
+    /// Resolves once everything is answered or timed out, changes also come in through `properties_rx`
+    pub async fn refresh(&self) {
+        // what else to ask depends on what it has
+        self.query_all([
+            MDRPacket::ConnectGetProtocolInfo,
+            MDRPacket::ConnectGetSupportFunction,
+        ])
+        .await;
+
+        let properties = self.properties();
+        let battery = |inquired_type| MDRPacket::CommonGetBatteryLevel { inquired_type };
+        let queries = [
+            (
+                &[][..],
+                MDRPacket::ConnectGetDeviceInfo {
+                    inquired_type: DeviceInfoInquiredType::ModelName,
+                },
+            ),
+            (
+                &[],
+                MDRPacket::ConnectGetDeviceInfo {
+                    inquired_type: DeviceInfoInquiredType::FwVersion,
+                },
+            ),
+            (
+                &[],
+                MDRPacket::ConnectGetDeviceInfo {
+                    inquired_type: DeviceInfoInquiredType::SeriesAndColorInfo,
+                },
+            ),
+            (
+                &[FunctionType::BatteryLevel],
+                battery(BatteryInquiredType::Battery),
+            ),
+            (
+                &[FunctionType::LeftRightBatteryLevel],
+                battery(BatteryInquiredType::LeftRightBattery),
+            ),
+            (
+                &[FunctionType::CradleBatteryLevel],
+                battery(BatteryInquiredType::CradleBattery),
+            ),
+            (
+                MULTIPOINT_FUNCTIONS,
+                MDRPacket::ConnectedDeviecesGet { b1: 0x02 },
+            ),
+            (
+                NC_ASM_FUNCTIONS,
+                MDRPacket::NcAsmGetParam {
+                    inquired_type: nc_asm_inquired_type(&properties),
+                },
+            ),
+            (
+                EQ_FUNCTIONS,
+                MDRPacket::EqEbbGetCapability {
+                    inquired_type: EqEbbInquiredType::PresetEq,
+                },
+            ),
+            (
+                EQ_FUNCTIONS,
+                MDRPacket::EqEbbGetParam {
+                    inquired_type: EqEbbInquiredType::PresetEq,
+                },
+            ),
+            (
+                &[FunctionType::Ebb],
+                MDRPacket::EqEbbGetCapability {
+                    inquired_type: EqEbbInquiredType::Ebb,
+                },
+            ),
+            (
+                &[FunctionType::Ebb],
+                MDRPacket::EqEbbGetParam {
+                    inquired_type: EqEbbInquiredType::Ebb,
+                },
+            ),
+            (
+                &[FunctionType::SmartTalkingMode],
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::SmartTalkingMode,
+                },
+            ),
+            (
+                &[FunctionType::SmartTalkingMode],
+                MDRPacket::SystemGetExtParam {
+                    inquired_type: SystemInquiredType::SmartTalkingMode,
+                },
+            ),
+            (
+                &[FunctionType::AutoNcAsm],
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::AutoNcAsm,
+                },
+            ),
+            (
+                &[FunctionType::ControlByWearing],
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::ControlByWearing,
+                },
+            ),
+            (
+                &[FunctionType::ControlByWearing],
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::WearingStatus,
+                },
+            ),
+            (
+                &[FunctionType::AssignableSettings],
+                MDRPacket::SystemGetCapability {
+                    inquired_type: SystemInquiredType::AssignableSettings,
+                },
+            ),
+            (
+                &[FunctionType::AssignableSettings],
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::AssignableSettings,
+                },
+            ),
+            (
+                &[FunctionType::AutoPowerOff],
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::AutoPowerOff,
+                },
+            ),
+            (
+                &[FunctionType::CodecIndicator],
+                MDRPacket::CommonGetAudioCodec,
+            ),
+            (
+                &[FunctionType::UpscalingIndicator],
+                MDRPacket::CommonGetUpscalingEffect,
+            ),
+            (
+                &[FunctionType::Upscaling],
+                MDRPacket::AudioGetParam {
+                    inquired_type: AudioInquiredType::Upscaling,
+                },
+            ),
+            (
+                &[FunctionType::ConnectionMode],
+                MDRPacket::AudioGetParam {
+                    inquired_type: AudioInquiredType::ConnectionMode,
+                },
+            ),
+        ];
+        let queries = queries
+            .into_iter()
+            .filter(|(functions, _)| properties.supports_any(functions))
+            .map(|(_, packet)| packet);
+        self.query_all(queries).await;
+    }
+
+    async fn query_all(&self, queries: impl IntoIterator<Item = MDRPacket>) {
+        // the link sends them one by one, but the replies are awaited together
+        let requests = queries.into_iter().map(|packet| async move {
+            if let Err(e) = self.request(packet.clone()).await {
+                debug_println!("Failed to query {packet:.?}: {e}");
+            }
+        });
+        join_all(requests).await;
+    }
+
+    /// Resolves once the headphone acks it
+    pub async fn send_packet(&self, packet: MDRPacket) -> Result<(), RequestError> {
+        let (data_type, bytes) = packet
+            .encode(self.properties().dialect())
+            .map_err(|_| RequestError::Unsupported)?;
+        Ok(self.link.send(data_type, bytes).await?)
+    }
+
+    /// Send a query and wait for its answer, e.g. `ConnectGetDeviceInfo` -> `ConnectRetDeviceInfo`
+    pub async fn request(&self, packet: MDRPacket) -> Result<MDRPacket, RequestError> {
+        self.request_with_timeout(packet, REQUEST_TIMEOUT).await
+    }
+
+    pub async fn request_with_timeout(
+        &self,
+        packet: MDRPacket,
+        timeout: Duration,
+    ) -> Result<MDRPacket, RequestError> {
+        if !packet.expects_reply() {
+            return Err(RequestError::NoReplyExpected);
+        }
+
+        // register before sending, the reply can beat the ack
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            let id = pending.next_id;
+            pending.next_id += 1;
+            pending.requests.push(PendingRequest {
+                id,
+                request: packet.clone(),
+                reply_tx,
+            });
+            id
+        };
+        let forget = || {
+            self.pending.lock().unwrap().requests.retain(|r| r.id != id);
         };
 
-        let communication_tx = self.communication.tx();
+        if let Err(e) = self.send_packet(packet).await {
+            forget();
+            return Err(e);
+        }
 
-        tokio::spawn(async move {
-            let mut seq = 0;
-            let mut send = async |packet: MDRPacket| {
-                let Some(bytes) = packet.to_bytes() else {
-                    println!("Unsupported {packet:.?}");
-                    return;
+        match tokio::time::timeout(timeout, reply_rx).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(RequestError::Link(LinkError::Closed)),
+            Err(_) => {
+                forget();
+                Err(RequestError::Timeout)
+            }
+        }
+    }
+
+    /// Drop the underlying link, pending requests fail with `LinkError::Closed`
+    pub fn close(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        self.communication.close();
+    }
+
+    /// Resolves once the link is gone, right away if it already is
+    pub async fn disconnected(&self) -> Disconnect {
+        let mut rx = self.disconnected_rx.clone();
+        let disconnect = match rx.wait_for(Option::is_some).await {
+            Ok(disconnect) => *disconnect,
+            // the reader task went away without saying, can't be on purpose
+            Err(_) => None,
+        };
+        disconnect.unwrap_or(Disconnect::Lost)
+    }
+
+    /// Every packet the headphone sends, notifications and replies alike
+    pub fn subscribe(&self) -> broadcast::Receiver<MDRPacket> {
+        self.packets_tx.subscribe()
+    }
+
+    pub async fn send(&self, command: HeadphoneAppCommand) -> Result<(), RequestError> {
+        if !command.is_supported(&self.properties()) {
+            return Err(RequestError::Unsupported);
+        }
+
+        let device_action = |action, address: MacAddress| MDRPacket::MultipointDeviceAction {
+            action,
+            mac_address: address,
+        };
+
+        let packet = match command {
+            HeadphoneAppCommand::SwitchDevice(address) => MDRPacket::MultipointActiveDeviceSet {
+                mac_address: address,
+            },
+            HeadphoneAppCommand::EnablePinning(enabled) => {
+                MDRPacket::MultipointPinningSet { enabled }
+            }
+            HeadphoneAppCommand::ConnectDevice(address) => {
+                device_action(MultipointAction::Connect, address)
+            }
+            HeadphoneAppCommand::DisconnectDevice(address) => {
+                device_action(MultipointAction::Disconnect, address)
+            }
+            HeadphoneAppCommand::UnpairDevice(address) => {
+                device_action(MultipointAction::Unpair, address)
+            }
+            // the layout goes by the inquired type, it has to be the one the headphone has
+            HeadphoneAppCommand::SetNcAsm(param) => MDRPacket::NcAsmSetParam(NcAsmParam {
+                inquired_type: nc_asm_inquired_type(&self.properties()),
+                ..param
+            }),
+            HeadphoneAppCommand::SetEqPreset(preset) => {
+                self.check_eq_preset(preset).await?;
+                MDRPacket::EqEbbSetParam(EqEbbParam::Eq(EqParam {
+                    preset,
+                    levels: vec![],
+                }))
+            }
+            HeadphoneAppCommand::SetEqBands(levels) => {
+                let properties = self.properties();
+                let levels = match &properties.eq_capability {
+                    Some(capability) => capability.clamp_levels(&levels),
+                    None => levels
+                        .iter()
+                        .map(|level| (*level).clamp(-EQ_LEVEL_OFFSET, EQ_LEVEL_OFFSET))
+                        .collect(),
                 };
-                // TODO: listen for ack
-                let frame = Frame::new(FrameDataType::DataMdr, seq, &bytes);
-                seq ^= 1;
-                communication_tx.send(frame.into()).await.unwrap();
-            };
+                // bands only apply to the custom presets
+                let preset = match properties.eq.map(|eq| eq.preset) {
+                    Some(preset @ (EqPreset::Manual | EqPreset::Custom1 | EqPreset::Custom2)) => {
+                        preset
+                    }
+                    _ => EqPreset::Manual,
+                };
+                MDRPacket::EqEbbSetParam(EqEbbParam::Eq(EqParam { preset, levels }))
+            }
+            HeadphoneAppCommand::SetClearBass(level) => {
+                let level = match self.properties().clear_bass_capability {
+                    Some(capability) => level.clamp(capability.min_level, capability.max_level),
+                    None => level.clamp(-EQ_LEVEL_OFFSET, EQ_LEVEL_OFFSET),
+                };
+                MDRPacket::EqEbbSetParam(EqEbbParam::Ebb(level))
+            }
+            HeadphoneAppCommand::EnableSpeakToChat(enabled) => MDRPacket::SmartTalkingSetParam {
+                enabled: enabled.into(),
+                preview_mode: OnOffSetting::Off,
+            },
+            HeadphoneAppCommand::SetSpeakToChat(config) => {
+                MDRPacket::SmartTalkingSetExtParam(config)
+            }
+            HeadphoneAppCommand::EnableAutoNcAsm(enabled) => MDRPacket::AutoNcAsmSetParam {
+                enabled: enabled.into(),
+            },
+            HeadphoneAppCommand::EnableUpscaling(enabled) => {
+                MDRPacket::UpscalingSetParam { enabled }
+            }
+            HeadphoneAppCommand::SetConnectionMode(mode) => {
+                MDRPacket::ConnectionModeSetParam { mode }
+            }
+            HeadphoneAppCommand::SetAutoPowerOff(setting) => {
+                MDRPacket::AutoPowerOffSetParam(setting)
+            }
+            HeadphoneAppCommand::EnableControlByWearing(enabled) => {
+                MDRPacket::ControlByWearingSetParam {
+                    enabled: enabled.into(),
+                }
+            }
+            HeadphoneAppCommand::SetAssignment(key, preset) => MDRPacket::AssignableSetParam {
+                assignments: self.assignments_with(key, preset).await?,
+            },
+            HeadphoneAppCommand::PowerOff => return self.power_off().await,
+        };
 
-            send(MDRPacket::ConnectGetProtocolInfo).await;
-            send(MDRPacket::ConnectedDeviecesGet { b1: 0x02 }).await;
+        let is_multipoint = matches!(
+            packet,
+            MDRPacket::MultipointActiveDeviceSet { .. }
+                | MDRPacket::MultipointPinningSet { .. }
+                | MDRPacket::MultipointDeviceAction { .. }
+        );
+        self.send_packet(packet).await?;
 
-            while let Some(frame) = frame_rx.recv().await {
-                println!(" 𐘀 {}", frame);
-                ack(frame.sequence_number).await;
-                let packets = MDRPacket::from_frame(frame);
-                for packet in packets {
-                    println!("   𐘀 {:.?}", packet);
-                    let p = HeadphoneProperties {
-                        placeholder_text: format!("{:.?}", packet).to_owned(),
-                    };
-                    tx.send(p).await.unwrap();
+        // the headphone only acks these, ask again so `devices` reflects what happened
+        if is_multipoint {
+            self.request(MDRPacket::ConnectedDeviecesGet { b1: 0x02 })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Only the presets the headphone listed, it has no use for the others
+    async fn check_eq_preset(&self, preset: EqPreset) -> Result<(), RequestError> {
+        if self.properties().eq_capability.is_none() {
+            self.request(MDRPacket::EqEbbGetCapability {
+                inquired_type: EqEbbInquiredType::PresetEq,
+            })
+            .await?;
+        }
+
+        let is_offered = self
+            .properties()
+            .eq_capability
+            .is_some_and(|capability| capability.presets.iter().any(|info| info.preset == preset));
+        if !is_offered {
+            return Err(RequestError::UnavailableOption);
+        }
+        Ok(())
+    }
+
+    /// Every assignment as it is now with `key` changed, once we know the headphone offers it
+    async fn assignments_with(
+        &self,
+        key: AssignableKey,
+        preset: AssignablePreset,
+    ) -> Result<Vec<KeyAssignment>, RequestError> {
+        if self.properties().assignable_capability.is_none() {
+            self.request(MDRPacket::SystemGetCapability {
+                inquired_type: SystemInquiredType::AssignableSettings,
+            })
+            .await?;
+        }
+        // the set packet carries every key, the others have to stay as they are
+        if self.properties().assignments.is_none() {
+            self.request(MDRPacket::SystemGetParam {
+                inquired_type: SystemInquiredType::AssignableSettings,
+            })
+            .await?;
+        }
+
+        let properties = self.properties();
+        let is_offered = properties
+            .assignable_capability
+            .iter()
+            .flatten()
+            .any(|info| info.key == key && info.presets.contains(&preset));
+        if !is_offered {
+            return Err(RequestError::UnavailableOption);
+        }
+
+        let mut assignments = properties.assignments.unwrap_or_default();
+        match assignments.iter_mut().find(|a| a.key == key) {
+            Some(assignment) => assignment.preset = preset,
+            None => assignments.push(KeyAssignment { key, preset }),
+        }
+        Ok(assignments)
+    }
+
+    async fn power_off(&self) -> Result<(), RequestError> {
+        // set first, the link can drop before the ack comes back
+        self.closing.store(true, Ordering::SeqCst);
+        match self.send_packet(MDRPacket::CommonSetPowerOff).await {
+            // it went down before acking, which is what we asked for
+            Ok(()) | Err(RequestError::Link(LinkError::Closed)) => {}
+            // NoAck doesn't say whether it got there, the link has moved on to the next sequence
+            // number either way. we can't call a drop after this expected, so it's the caller's
+            Err(e) => {
+                self.closing.store(false, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        // don't wait on it to hang up
+        self.communication.close();
+        Ok(())
+    }
+
+    pub fn properties_rx(&self) -> Receiver<PropertiesChanged> {
+        let (tx, rx) = tokio::sync::mpsc::channel(24);
+        let mut changes_rx = self.changes_tx.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let change = match changes_rx.recv().await {
+                    Ok(change) => change,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if tx.send(change).await.is_err() {
+                    break;
                 }
             }
         });
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use crate::protocols::{frame::FrameDataType, mdr::MDRPacketType};
+
+// sony renumbered some packets between protocol versions. v1 is the whole table and later versions
+// only list what they changed. `mdr_packets!` numbers packets the v1 way, that's just what
+// `MDRPacketType` is, what actually goes on the wire (and on which channel) is looked up here
+
+/// Which opcode table the headphone speaks, picked from `ConnectRetProtocolInfo`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MdrDialect {
+    /// Also what we assume until the headphone tells us
+    #[default]
+    V1,
+    /// v3 only added packets, so it shares the table
+    V2,
+}
+
+/// Where a packet is in a dialect, the opcode alone is ambiguous across channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub data_type: FrameDataType,
+    pub opcode: u8,
+}
+
+const fn mdr(opcode: u8) -> Opcode {
+    Opcode {
+        data_type: FrameDataType::DataMdr,
+        opcode,
+    }
+}
+
+const fn mdr2(opcode: u8) -> Opcode {
+    Opcode {
+        data_type: FrameDataType::DataMdrNo2,
+        opcode,
+    }
+}
+
+const V1_TABLE: &[(MDRPacketType, Opcode)] = &[
+    (MDRPacketType::ConnectGetProtocolInfo, mdr(0x00)),
+    (MDRPacketType::ConnectRetProtocolInfo, mdr(0x01)),
+    (MDRPacketType::ConnectGetCapabilityInfo, mdr(0x02)),
+    (MDRPacketType::ConnectGetDeviceInfo, mdr(0x04)),
+    (MDRPacketType::ConnectRetDeviceInfo, mdr(0x05)),
+    (MDRPacketType::ConnectGetSupportFunction, mdr(0x06)),
+    (MDRPacketType::ConnectRetSupportFunction, mdr(0x07)),
+    (MDRPacketType::CommonGetBatteryLevel, mdr(0x10)),
+    (MDRPacketType::CommonRetBatteryLevel, mdr(0x11)),
+    (MDRPacketType::CommonNtfyBatteryLevel, mdr(0x13)),
+    (MDRPacketType::CommonGetUpscalingEffect, mdr(0x14)),
+    (MDRPacketType::CommonRetUpscalingEffect, mdr(0x15)),
+    (MDRPacketType::CommonNtfyUpscalingEffect, mdr(0x17)),
+    (MDRPacketType::CommonGetAudioCodec, mdr(0x18)),
+    (MDRPacketType::CommonRetAudioCodec, mdr(0x19)),
+    (MDRPacketType::CommonNtfyAudioCodec, mdr(0x1B)),
+    (MDRPacketType::CommonSetPowerOff, mdr(0x22)),
+    (MDRPacketType::ConnectedDeviecesGet, mdr(0x36)),
+    (MDRPacketType::MultipointPinningSet, mdr(0x38)),
+    (MDRPacketType::ConnectedDeviecesRet, mdr(0x39)),
+    (MDRPacketType::MultipointActiveDeviceSet, mdr(0x3C)),
+    (MDRPacketType::EqEbbGetCapability, mdr(0x50)),
+    (MDRPacketType::EqEbbRetCapability, mdr(0x51)),
+    (MDRPacketType::EqEbbGetParam, mdr(0x56)),
+    (MDRPacketType::EqEbbRetParam, mdr(0x57)),
+    (MDRPacketType::EqEbbSetParam, mdr(0x58)),
+    (MDRPacketType::EqEbbNtfyParam, mdr(0x59)),
+    (MDRPacketType::NcAsmGetParam, mdr(0x66)),
+    (MDRPacketType::NcAsmRetParam, mdr(0x67)),
+    (MDRPacketType::NcAsmSetParam, mdr(0x68)),
+    (MDRPacketType::NcAsmNtfyParam, mdr(0x69)),
+    (MDRPacketType::VolumeChangedNotify, mdr(0xA9)),
+    (MDRPacketType::AudioGetParam, mdr(0xE6)),
+    (MDRPacketType::AudioRetParam, mdr(0xE7)),
+    (MDRPacketType::AudioSetParam, mdr(0xE8)),
+    (MDRPacketType::AudioNtfyParam, mdr(0xE9)),
+    (MDRPacketType::SystemGetCapability, mdr(0xF0)),
+    (MDRPacketType::SystemRetCapability, mdr(0xF1)),
+    (MDRPacketType::SystemGetParam, mdr(0xF6)),
+    (MDRPacketType::SystemRetParam, mdr(0xF7)),
+    (MDRPacketType::SystemSetParam, mdr(0xF8)),
+    (MDRPacketType::SystemNtfyParam, mdr(0xF9)),
+    (MDRPacketType::SystemGetExtParam, mdr(0xFA)),
+    (MDRPacketType::SystemRetExtParam, mdr(0xFB)),
+    (MDRPacketType::SystemSetExtParam, mdr(0xFC)),
+    (MDRPacketType::SystemNtfyExtParam, mdr(0xFD)),
+    // the second channel has its own opcodes
+    (MDRPacketType::VoiceGuidanceGetParam, mdr2(0x46)),
+    (MDRPacketType::VoiceGuidanceRetParam, mdr2(0x47)),
+    (MDRPacketType::VoiceGuidanceSetParam, mdr2(0x48)),
+    (MDRPacketType::VoiceGuidanceNtfyParam, mdr2(0x49)),
+];
+
+// what v2 does differently, anything not in here is the same as v1. battery took 0x22 and we
+// don't know where power off went, so v2 can't be turned off from here
+const V2_OVERRIDES: &[(MDRPacketType, Option<Opcode>)] = &[
+    (MDRPacketType::CommonGetBatteryLevel, Some(mdr(0x22))),
+    (MDRPacketType::CommonRetBatteryLevel, Some(mdr(0x23))),
+    (MDRPacketType::CommonNtfyBatteryLevel, Some(mdr(0x25))),
+    (MDRPacketType::CommonSetPowerOff, None),
+];
+
+impl MdrDialect {
+    /// The high byte is the major version
+    pub fn from_protocol_version(version: u16) -> Self {
+        match version >> 8 {
+            0 | 1 => MdrDialect::V1,
+            _ => MdrDialect::V2,
+        }
+    }
+
+    fn overrides(self) -> &'static [(MDRPacketType, Option<Opcode>)] {
+        match self {
+            MdrDialect::V1 => &[],
+            MdrDialect::V2 => V2_OVERRIDES,
+        }
+    }
+
+    /// Where to send it, `None` if this dialect doesn't have it
+    pub fn opcode(self, packet_type: MDRPacketType) -> Option<Opcode> {
+        if let Some((_, opcode)) = self.overrides().iter().find(|(t, _)| *t == packet_type) {
+            return *opcode;
+        }
+        V1_TABLE
+            .iter()
+            .find(|(t, _)| *t == packet_type)
+            .map(|(_, opcode)| *opcode)
+    }
+
+    /// What came in, `None` if we don't know it in this dialect
+    pub fn packet_type(self, opcode: Opcode) -> Option<MDRPacketType> {
+        if let Some((packet_type, _)) = self.overrides().iter().find(|(_, o)| *o == Some(opcode)) {
+            return Some(*packet_type);
+        }
+        V1_TABLE
+            .iter()
+            .find(|(_, o)| *o == opcode)
+            .map(|(t, _)| *t)
+            // moved or gone, whatever is there now isn't it
+            .filter(|t| !self.overrides().iter().any(|(o, _)| o == t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIALECTS: [MdrDialect; 2] = [MdrDialect::V1, MdrDialect::V2];
+
+    #[test]
+    fn opcodes_go_both_ways() {
+        for dialect in DIALECTS {
+            for opcode in 0..=u8::MAX {
+                let Ok(packet_type) = MDRPacketType::try_from(opcode) else {
+                    continue;
+                };
+                if let Some(opcode) = dialect.opcode(packet_type) {
+                    assert_eq!(dialect.packet_type(opcode), Some(packet_type));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn battery_moved_in_v2() {
+        assert_eq!(
+            MdrDialect::V1.opcode(MDRPacketType::CommonGetBatteryLevel),
+            Some(mdr(0x10))
+        );
+        assert_eq!(
+            MdrDialect::V2.opcode(MDRPacketType::CommonGetBatteryLevel),
+            Some(mdr(0x22))
+        );
+        assert_eq!(
+            MdrDialect::V2.packet_type(mdr(0x23)),
+            Some(MDRPacketType::CommonRetBatteryLevel)
+        );
+        assert_eq!(
+            MdrDialect::V2.packet_type(mdr(0x25)),
+            Some(MDRPacketType::CommonNtfyBatteryLevel)
+        );
+
+        // 0x22 is power off in v1, and the old battery opcodes mean nothing in v2
+        assert_eq!(
+            MdrDialect::V1.packet_type(mdr(0x22)),
+            Some(MDRPacketType::CommonSetPowerOff)
+        );
+        assert_eq!(MdrDialect::V2.packet_type(mdr(0x10)), None);
+        assert_eq!(MdrDialect::V2.packet_type(mdr(0x13)), None);
+        assert_eq!(
+            MdrDialect::V2.opcode(MDRPacketType::CommonSetPowerOff),
+            None
+        );
+
+        // and the rest is the same
+        assert_eq!(
+            MdrDialect::V2.opcode(MDRPacketType::NcAsmSetParam),
+            MdrDialect::V1.opcode(MDRPacketType::NcAsmSetParam)
+        );
+    }
+
+    #[test]
+    fn channels_are_told_apart() {
+        for dialect in DIALECTS {
+            assert_eq!(
+                dialect.opcode(MDRPacketType::VoiceGuidanceGetParam),
+                Some(mdr2(0x46))
+            );
+            assert_eq!(
+                dialect.packet_type(mdr2(0x47)),
+                Some(MDRPacketType::VoiceGuidanceRetParam)
+            );
+            assert_eq!(dialect.packet_type(mdr(0x47)), None);
+            // same byte, other channel
+            assert_eq!(dialect.packet_type(mdr2(0x39)), None);
+            assert_eq!(
+                dialect.packet_type(mdr(0x39)),
+                Some(MDRPacketType::ConnectedDeviecesRet)
+            );
+        }
+    }
+}
@@ -1,7 +1,8 @@
-use std::fmt::Display;
+use std::{fmt::Display, io};
 
+use bytes::{Buf, BytesMut};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use tokio::sync::mpsc::Receiver;
+use tokio_util::codec::{Decoder, Encoder};
 
 use crate::platforms::utils::U8ArrayExtension;
 
@@ -30,7 +31,7 @@ pub fn escape(bytes: &[u8]) -> Vec<u8> {
     out
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq)]
+#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
 #[repr(u8)]
 pub enum FrameDataType {
     Data = 0x00,
@@ -57,22 +58,64 @@ impl Display for FrameDataType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Frame {
     pub data_type: FrameDataType,
     pub sequence_number: u8,
     pub content: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FrameParseError {
-    InvalidCheckSum { expected: u8, actual: u8 },
+    InvalidCheckSum {
+        expected: u8,
+        actual: u8,
+    },
     TooSmall,
     InvalidFormat,
-    IncorrectLenght,
+    /// The length field asks for more content than the frame has
+    LengthOverflow {
+        length: u32,
+        available: usize,
+    },
+    /// Bytes between the checksum and the end
+    TrailingGarbage {
+        count: usize,
+    },
     InvalidDataType,
+    /// A new frame started before this one ended
+    Unterminated,
+    /// Went past `FrameDecoder`'s size cap without seeing an end
+    TooLarge,
+}
+
+impl Display for FrameParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameParseError::InvalidCheckSum { expected, actual } => write!(
+                f,
+                "Invalid checksum, expected {:02x} got {:02x}",
+                expected, actual
+            ),
+            FrameParseError::TooSmall => write!(f, "Frame too small"),
+            FrameParseError::InvalidFormat => write!(f, "Invalid frame format"),
+            FrameParseError::LengthOverflow { length, available } => write!(
+                f,
+                "Length says {} bytes but only {} are there",
+                length, available
+            ),
+            FrameParseError::TrailingGarbage { count } => {
+                write!(f, "{} bytes after the checksum", count)
+            }
+            FrameParseError::InvalidDataType => write!(f, "Invalid data type"),
+            FrameParseError::Unterminated => write!(f, "Frame without an end"),
+            FrameParseError::TooLarge => write!(f, "Frame too large"),
+        }
+    }
 }
 
+impl std::error::Error for FrameParseError {}
+
 impl Display for Frame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -90,34 +133,50 @@ impl Display for Frame {
 impl TryFrom<&[u8]> for Frame {
     type Error = FrameParseError;
 
-    // without
+    // already unescaped, with the start and end bytes
+    // never indexes before checking, the length field comes straight off the air
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         if value.len() < 9 {
             return Err(FrameParseError::TooSmall);
         }
 
-        if value[0] != TANDEM_FRAME_START || *value.last().unwrap() != TANDEM_FRAME_END {
+        let [TANDEM_FRAME_START, value @ .., TANDEM_FRAME_END] = value else {
             return Err(FrameParseError::InvalidFormat);
         };
+        let [data_type, sequence_number, l0, l1, l2, l3, rest @ ..] = value else {
+            return Err(FrameParseError::TooSmall);
+        };
 
-        let value = &value[1..(value.len() - 1)];
+        let Ok(data_type) = FrameDataType::try_from(*data_type) else {
+            return Err(FrameParseError::InvalidDataType);
+        };
 
-        let lenght = u32::from_be_bytes(value[2..6].try_into().unwrap());
-        let checksum_pos: usize = (lenght + 6) as usize;
-        let checksum = value[checksum_pos];
-        if value.len() < checksum_pos + 1 {
-            return Err(FrameParseError::IncorrectLenght);
+        // rest is content + checksum
+        let lenght = u32::from_be_bytes([*l0, *l1, *l2, *l3]);
+        let available = rest.len() - 1;
+        let content_len = match usize::try_from(lenght) {
+            Ok(content_len) if content_len <= available => content_len,
+            _ => {
+                return Err(FrameParseError::LengthOverflow {
+                    length: lenght,
+                    available,
+                })
+            }
+        };
+        if content_len < available {
+            return Err(FrameParseError::TrailingGarbage {
+                count: available - content_len,
+            });
         }
 
-        let Ok(data_type) = FrameDataType::try_from(value[0]) else {
-            return Err(FrameParseError::InvalidDataType);
+        let (content, [checksum]) = rest.split_at(content_len) else {
+            return Err(FrameParseError::InvalidFormat);
         };
-
-        let packet = Frame::new(data_type, value[1], value[6..checksum_pos].into());
+        let packet = Frame::new(data_type, *sequence_number, content);
         let expected = packet.checksum();
-        if checksum != expected {
+        if *checksum != expected {
             return Err(FrameParseError::InvalidCheckSum {
-                actual: checksum,
+                actual: *checksum,
                 expected,
             });
         }
@@ -128,23 +187,7 @@ impl TryFrom<&[u8]> for Frame {
 
 impl Into<Vec<u8>> for Frame {
     fn into(self) -> Vec<u8> {
-        let mut payload: Vec<u8> = vec![self.data_type.into(), self.sequence_number];
-
-        for b in (self.content.len() as u32).to_be_bytes() {
-            payload.push(b);
-        }
-
-        payload.extend_from_slice(&self.content);
-        payload.push(self.checksum());
-
-        let escaped = escape(&payload);
-        payload.clear(); // reuse
-
-        payload.push(TANDEM_FRAME_START);
-        payload.extend_from_slice(&escaped);
-        payload.push(TANDEM_FRAME_END);
-
-        payload
+        self.encode_with_checksum(self.checksum())
     }
 }
 
@@ -158,10 +201,10 @@ impl Frame {
     }
 
     pub fn new_ack(sequence_number: u8) -> Frame {
-        // assert!(sequence_number < 0x02, "seq number must be 0 or 1");
+        // should be 0 or 1, but it comes from the other side so don't underflow on anything else
         Frame {
             data_type: FrameDataType::Ack,
-            sequence_number: 1 - sequence_number,
+            sequence_number: 1u8.wrapping_sub(sequence_number),
             content: vec![],
         }
     }
@@ -175,57 +218,437 @@ impl Frame {
             .wrapping_add(self.content.len() as u8)
     }
 
-    pub fn from_byte_stream(mut bytes_rx: Receiver<Vec<u8>>) -> Receiver<Frame> {
-        let (tx, rx) = tokio::sync::mpsc::channel(512);
-        tokio::spawn(async move {
-            let mut buffer: Vec<u8> = vec![];
-            let mut escape_next = false;
-            println!("Waiting ");
-            while let Some(bytes) = bytes_rx.recv().await {
-                for byte in bytes {
-                    // print!("{:02x} ", byte);
-                    match byte {
-                        TANDEM_FRAME_START => {
-                            buffer.clear();
-                            buffer.push(TANDEM_FRAME_START);
-                        }
-                        TANDEM_FRAME_END => {
-                            buffer.push(TANDEM_FRAME_END);
-                            let Ok(frame) = Frame::try_from(buffer.as_slice()) else {
-                                // TODO: close stream
-                                break;
-                            };
-                            tx.send(frame).await.unwrap();
-                        }
-                        TANDEM_ESCAPE => escape_next = true,
-                        _ => buffer.push(if escape_next { unescape(byte) } else { byte }),
-                    };
-                }
-            }
-            println!("Done (packet) bytes_rx.is_closed:{}", bytes_rx.is_closed());
-        });
+    // the emulator uses this to send corrupted frames
+    pub fn encode_with_checksum(&self, checksum: u8) -> Vec<u8> {
+        let mut payload: Vec<u8> = vec![self.data_type.into(), self.sequence_number];
 
-        rx
+        for b in (self.content.len() as u32).to_be_bytes() {
+            payload.push(b);
+        }
+
+        payload.extend_from_slice(&self.content);
+        payload.push(checksum);
+
+        let escaped = escape(&payload);
+        payload.clear(); // reuse
+
+        payload.push(TANDEM_FRAME_START);
+        payload.extend_from_slice(&escaped);
+        payload.push(TANDEM_FRAME_END);
+
+        payload
+    }
+}
+
+/// Way more than any mdr packet, it's here so garbage can't grow the buffer forever
+pub const MAX_FRAME_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecoderStats {
+    pub frames: usize,
+    /// Frames that ended but didn't parse, or never ended
+    pub errors: usize,
+    /// Every byte that didn't end up in a frame, garbage between frames included
+    pub dropped_bytes: usize,
+}
+
+/// Turns bytes into frames as they arrive, in whatever chunks they come in.
+/// Doesn't do any io itself so it works from sync and async code alike
+#[derive(Debug)]
+pub struct FrameDecoder {
+    // start byte, unescaped bytes, end byte, which is what `Frame::try_from` wants
+    buffer: Vec<u8>,
+    in_frame: bool,
+    // kept across `push` calls, an escape can be the last byte of a read
+    escape_next: bool,
+    max_frame_size: usize,
+    stats: DecoderStats,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::with_max_frame_size(MAX_FRAME_SIZE)
+    }
+
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self {
+            buffer: vec![],
+            in_frame: false,
+            escape_next: false,
+            max_frame_size,
+            stats: DecoderStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    /// Returns every frame that ended in `bytes`, broken ones included
+    pub fn push(&mut self, mut bytes: &[u8]) -> Vec<Result<Frame, FrameParseError>> {
+        let mut results = vec![];
+        while !bytes.is_empty() {
+            let (consumed, result) = self.decode(bytes);
+            bytes = &bytes[consumed..];
+            results.extend(result);
+        }
+        results
+    }
+
+    /// Stops at the first frame that ends, returns how many bytes it went through
+    pub fn decode(&mut self, bytes: &[u8]) -> (usize, Option<Result<Frame, FrameParseError>>) {
+        for (i, byte) in bytes.iter().enumerate() {
+            if let Some(result) = self.push_byte(*byte) {
+                return (i + 1, Some(result));
+            }
+        }
+        (bytes.len(), None)
     }
 
-    pub fn to_mdr_bytes_stream(
-        mut frame_rx: Receiver<Result<Frame, FrameParseError>>,
-    ) -> Receiver<u8> {
-        let (tx, rx) = tokio::sync::mpsc::channel(512);
-        tokio::spawn(async move {
-            while let Some(result) = frame_rx.recv().await {
-                if let Ok(frame) = result {
-                    for b in frame.content {
-                        tx.send(b).await.unwrap();
+    fn push_byte(&mut self, byte: u8) -> Option<Result<Frame, FrameParseError>> {
+        if !self.in_frame {
+            if byte == TANDEM_FRAME_START {
+                self.start_frame();
+            } else {
+                self.stats.dropped_bytes += 1;
+            }
+            return None;
+        }
+
+        match byte {
+            TANDEM_FRAME_START => {
+                // resync, whatever we had is lost
+                let lost = self.buffer.len();
+                self.start_frame();
+                Some(self.fail(FrameParseError::Unterminated, lost))
+            }
+            TANDEM_FRAME_END => {
+                self.buffer.push(TANDEM_FRAME_END);
+                self.in_frame = false;
+                let result = if self.escape_next {
+                    Err(FrameParseError::InvalidFormat)
+                } else {
+                    Frame::try_from(self.buffer.as_slice())
+                };
+                match result {
+                    Ok(frame) => {
+                        self.stats.frames += 1;
+                        Some(Ok(frame))
                     }
+                    Err(e) => Some(self.fail(e, self.buffer.len())),
+                }
+            }
+            TANDEM_ESCAPE if self.escape_next => {
+                // nothing escapes to an escape, the frame is broken so wait for the next one
+                self.in_frame = false;
+                Some(self.fail(FrameParseError::InvalidFormat, self.buffer.len()))
+            }
+            TANDEM_ESCAPE => {
+                self.escape_next = true;
+                None
+            }
+            _ => {
+                let byte = if self.escape_next {
+                    unescape(byte)
                 } else {
-                    break;
-                    // TODO: error handling? close stream?
+                    byte
+                };
+                self.escape_next = false;
+                self.buffer.push(byte);
+
+                if self.buffer.len() > self.max_frame_size {
+                    self.in_frame = false;
+                    return Some(self.fail(FrameParseError::TooLarge, self.buffer.len()));
+                }
+                None
+            }
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.buffer.clear();
+        self.buffer.push(TANDEM_FRAME_START);
+        self.in_frame = true;
+        self.escape_next = false;
+    }
+
+    fn fail(&mut self, error: FrameParseError, dropped: usize) -> Result<Frame, FrameParseError> {
+        self.stats.errors += 1;
+        self.stats.dropped_bytes += dropped;
+        Err(error)
+    }
+}
+
+/// [`FrameDecoder`] for `Framed`, broken frames come out as items so one bad frame doesn't end the stream
+#[derive(Debug, Default)]
+pub struct FrameCodec {
+    decoder: FrameDecoder,
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.decoder.stats()
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Result<Frame, FrameParseError>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (consumed, result) = self.decoder.decode(src);
+        src.advance(consumed);
+        Ok(result)
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let bytes: Vec<u8> = frame.into();
+        dst.extend_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(content: &[u8]) -> Frame {
+        Frame::new(FrameDataType::DataMdr, 1, content)
+    }
+
+    fn encode(frame: Frame) -> Vec<u8> {
+        frame.into()
+    }
+
+    /// Feeds `chunks` one read at a time like `Framed` would
+    fn decode_chunks(
+        codec: &mut FrameCodec,
+        chunks: &[&[u8]],
+    ) -> Vec<Result<Frame, FrameParseError>> {
+        let mut buffer = BytesMut::new();
+        let mut results = vec![];
+        for chunk in chunks {
+            buffer.extend_from_slice(chunk);
+            while let Some(result) = codec.decode(&mut buffer).unwrap() {
+                results.push(result);
+            }
+        }
+        results
+    }
+
+    #[test]
+    fn escape_split_across_reads() {
+        let expected = frame(&[0x01, TANDEM_FRAME_END, 0x02]);
+        let bytes = encode(expected.clone());
+        let escape_at = bytes[1..].iter().position(|b| *b == TANDEM_ESCAPE).unwrap() + 1;
+
+        // every split point, the one right after the escape is the interesting one
+        for split in [escape_at, escape_at + 1, 1, bytes.len() - 1] {
+            let mut codec = FrameCodec::new();
+            let (first, second) = bytes.split_at(split);
+            let results = decode_chunks(&mut codec, &[first, second]);
+            assert_eq!(results, vec![Ok(expected.clone())], "split at {split}");
+        }
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let expected = frame(&[TANDEM_FRAME_START, TANDEM_ESCAPE, TANDEM_FRAME_END]);
+        let bytes = encode(expected.clone());
+        let chunks: Vec<&[u8]> = bytes.chunks(1).collect();
+
+        let mut codec = FrameCodec::new();
+        assert_eq!(decode_chunks(&mut codec, &chunks), vec![Ok(expected)]);
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let expected = frame(&[0x01, 0x02]);
+        let garbage = [0x00, 0xff, TANDEM_FRAME_END, 0x12];
+
+        let mut codec = FrameCodec::new();
+        let bytes = encode(expected.clone());
+        let results = decode_chunks(&mut codec, &[&garbage, &bytes]);
+
+        assert_eq!(results, vec![Ok(expected)]);
+        assert_eq!(
+            codec.stats(),
+            DecoderStats {
+                frames: 1,
+                errors: 0,
+                dropped_bytes: garbage.len(),
+            }
+        );
+    }
+
+    #[test]
+    fn new_start_drops_the_unterminated_frame() {
+        let expected = frame(&[0x01]);
+        let mut bytes = encode(frame(&[0x05, 0x06]));
+        bytes.pop();
+        let cut = bytes.len();
+        bytes.extend(encode(expected.clone()));
+
+        let mut codec = FrameCodec::new();
+        let (first, second) = bytes.split_at(cut / 2);
+        let results = decode_chunks(&mut codec, &[first, second]);
+
+        assert_eq!(
+            results,
+            vec![Err(FrameParseError::Unterminated), Ok(expected)]
+        );
+        assert_eq!(codec.stats().frames, 1);
+        assert_eq!(codec.stats().errors, 1);
+        assert_eq!(codec.stats().dropped_bytes, cut);
+    }
+
+    #[test]
+    fn caps_the_frame_size() {
+        let expected = frame(&[0x01]);
+        let mut codec = FrameCodec {
+            decoder: FrameDecoder::with_max_frame_size(16),
+        };
+        let large = encode(frame(&[0x20; 32]));
+        let bytes = encode(expected.clone());
+        let results = decode_chunks(&mut codec, &[&large[..10], &large[10..], &bytes]);
+
+        assert_eq!(results, vec![Err(FrameParseError::TooLarge), Ok(expected)]);
+        let stats = codec.stats();
+        assert_eq!((stats.frames, stats.errors), (1, 1));
+        // the cap stops buffering, the rest of the large frame is still dropped
+        assert_eq!(stats.dropped_bytes, large.len());
+    }
+
+    #[test]
+    fn double_escape_is_a_bad_frame() {
+        let expected = frame(&[0x01]);
+        let mut broken = encode(frame(&[0x02, 0x03]));
+        broken.insert(3, TANDEM_ESCAPE);
+        broken.insert(3, TANDEM_ESCAPE);
+        let bytes = encode(expected.clone());
+
+        let mut codec = FrameCodec::new();
+        let results = decode_chunks(&mut codec, &[&broken[..4], &broken[4..], &bytes]);
+
+        assert_eq!(
+            results,
+            vec![Err(FrameParseError::InvalidFormat), Ok(expected)]
+        );
+        assert_eq!(codec.stats().frames, 1);
+        assert_eq!(codec.stats().errors, 1);
+    }
+
+    #[test]
+    fn bad_checksum_is_counted() {
+        let broken = frame(&[0x01]);
+        let bytes = broken.encode_with_checksum(broken.checksum().wrapping_add(1));
+
+        let mut codec = FrameCodec::new();
+        let results = decode_chunks(&mut codec, &[&bytes]);
+
+        assert!(matches!(
+            results.as_slice(),
+            [Err(FrameParseError::InvalidCheckSum { .. })]
+        ));
+        assert_eq!(codec.stats().errors, 1);
+        assert_eq!(codec.stats().dropped_bytes, bytes.len());
+    }
+
+    fn data_types() -> impl Iterator<Item = FrameDataType> {
+        (0..=u8::MAX).filter_map(|b| FrameDataType::try_from(b).ok())
+    }
+
+    /// What `Frame::try_from` takes, unescaped
+    fn raw(frame: &Frame) -> Vec<u8> {
+        let mut bytes = vec![
+            TANDEM_FRAME_START,
+            frame.data_type.into(),
+            frame.sequence_number,
+        ];
+        bytes.extend((frame.content.len() as u32).to_be_bytes());
+        bytes.extend(&frame.content);
+        bytes.push(frame.checksum());
+        bytes.push(TANDEM_FRAME_END);
+        bytes
+    }
+
+    #[test]
+    fn round_trips_every_data_type() {
+        let mut payloads: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![TANDEM_FRAME_END, TANDEM_ESCAPE, TANDEM_FRAME_START],
+            vec![TANDEM_ESCAPE; 8],
+            (0..=u8::MAX).collect(),
+        ];
+        // single bytes also walk the checksum through the special bytes
+        payloads.extend((0..=u8::MAX).map(|b| vec![b]));
+
+        let mut count = 0;
+        for data_type in data_types() {
+            for sequence_number in [0, 1] {
+                for content in &payloads {
+                    let frame = Frame::new(data_type, sequence_number, content);
+                    let bytes: Vec<u8> = frame.clone().into();
+
+                    let mut decoder = FrameDecoder::new();
+                    assert_eq!(decoder.push(&bytes), vec![Ok(frame.clone())]);
+                    assert_eq!(Frame::try_from(raw(&frame).as_slice()), Ok(frame));
+                    count += 1;
                 }
             }
-            println!("Done frame_rx.is_closed:{}", frame_rx.is_closed());
-        });
+        }
+        assert_eq!(count, 16 * 2 * payloads.len());
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let bytes = raw(&frame(&[0x01, 0x02, 0x03]));
+        for end in 0..bytes.len() {
+            assert!(Frame::try_from(&bytes[..end]).is_err(), "cut at {end}");
+        }
+    }
+
+    #[test]
+    fn length_field_is_checked() {
+        let mut bytes = raw(&frame(&[0x01, 0x02, 0x03]));
+
+        bytes[3..7].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            Frame::try_from(bytes.as_slice()),
+            Err(FrameParseError::LengthOverflow {
+                length: u32::MAX,
+                available: 3,
+            })
+        );
+
+        bytes[3..7].copy_from_slice(&1u32.to_be_bytes());
+        assert_eq!(
+            Frame::try_from(bytes.as_slice()),
+            Err(FrameParseError::TrailingGarbage { count: 2 })
+        );
+    }
 
-        rx
+    #[test]
+    fn rejects_unknown_data_types() {
+        let mut bytes = raw(&frame(&[0x01]));
+        bytes[1] = 0xff;
+        assert_eq!(
+            Frame::try_from(bytes.as_slice()),
+            Err(FrameParseError::InvalidDataType)
+        );
     }
 }
@@ -0,0 +1,295 @@
+use std::{fmt, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{
+        mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::{sleep_until, Instant},
+};
+use tokio_util::codec::Framed;
+
+use crate::protocols::frame::{Frame, FrameCodec, FrameDataType};
+
+// sits between frames and mdr packets
+// owns sequence numbers: acks everything that comes in and waits for an ack for everything that goes out
+
+#[derive(Debug, Clone, Copy)]
+pub struct LinkConfig {
+    pub ack_timeout: Duration,
+    /// How many times a frame is resent before giving up
+    pub max_retries: u8,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_millis(500),
+            max_retries: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    NoAck { attempts: u8 },
+    Closed,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::NoAck { attempts } => {
+                write!(f, "Headphone did not ack after {} attempts", attempts)
+            }
+            LinkError::Closed => write!(f, "Connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+struct Outgoing {
+    data_type: FrameDataType,
+    content: Vec<u8>,
+    done: oneshot::Sender<Result<(), LinkError>>,
+}
+
+struct InFlight {
+    frame: Frame,
+    attempts: u8,
+    deadline: Instant,
+    done: oneshot::Sender<Result<(), LinkError>>,
+}
+
+/// Send queue that holds each frame until the headphone acks it
+#[derive(Debug, Clone)]
+pub struct FrameLink {
+    outgoing_tx: Sender<Outgoing>,
+}
+
+impl FrameLink {
+    /// Returns the link and the stream of incoming (already acked, deduplicated) data frames.
+    /// `io` is anything that carries tandem bytes, see `traits::communication_io` for a `DeviceCommunication`.
+    /// The stream isn't bounded, an acked frame is never sent again so it can't be dropped
+    pub fn new<T>(io: T, config: LinkConfig) -> (FrameLink, UnboundedReceiver<Frame>)
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (outgoing_tx, outgoing_rx) = channel(24);
+        let (incoming_tx, incoming_rx) = unbounded_channel();
+
+        let framed = Framed::new(io, FrameCodec::new());
+        tokio::spawn(run(config, framed, outgoing_rx, incoming_tx));
+
+        (FrameLink { outgoing_tx }, incoming_rx)
+    }
+
+    /// Resolves once the frame is acked
+    pub async fn send(&self, data_type: FrameDataType, content: Vec<u8>) -> Result<(), LinkError> {
+        let (done, done_rx) = oneshot::channel();
+        self.outgoing_tx
+            .send(Outgoing {
+                data_type,
+                content,
+                done,
+            })
+            .await
+            .map_err(|_| LinkError::Closed)?;
+
+        done_rx.await.unwrap_or(Err(LinkError::Closed))
+    }
+}
+
+async fn run<T>(
+    config: LinkConfig,
+    framed: Framed<T, FrameCodec>,
+    mut outgoing_rx: Receiver<Outgoing>,
+    incoming_tx: UnboundedSender<Frame>,
+) where
+    T: AsyncRead + AsyncWrite,
+{
+    let mut framed = std::pin::pin!(framed);
+    let mut sequence_number = 0;
+    let mut last_received: Option<u8> = None;
+    let mut in_flight: Option<InFlight> = None;
+    let mut outgoing_closed = false;
+
+    loop {
+        let deadline = in_flight.as_ref().map(|f| f.deadline);
+
+        tokio::select! {
+            frame = framed.next() => {
+                let frame = match frame {
+                    Some(Ok(Ok(frame))) => frame,
+                    // nothing to ack, the headphone will resend it
+                    Some(Ok(Err(e))) => {
+                        debug_println!("Dropped frame: {e} ({:?})", framed.codec().stats());
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        debug_println!("Link read failed: {e}");
+                        break;
+                    }
+                    None => break,
+                };
+
+                if frame.data_type == FrameDataType::Ack {
+                    // ack for seq n is numbered 1 - n
+                    if in_flight
+                        .as_ref()
+                        .is_some_and(|f| f.frame.sequence_number != frame.sequence_number)
+                    {
+                        let f = in_flight.take().unwrap();
+                        let _ = f.done.send(Ok(()));
+                        sequence_number ^= 1;
+                    }
+                    continue;
+                }
+
+                if framed.send(Frame::new_ack(frame.sequence_number)).await.is_err() {
+                    break;
+                }
+
+                // our ack got lost and this is a retransmission
+                if last_received == Some(frame.sequence_number) {
+                    continue;
+                }
+                last_received = Some(frame.sequence_number);
+
+                // already acked, waiting on a slow reader would hold up acks and retransmissions
+                let _ = incoming_tx.send(frame);
+            }
+            outgoing = outgoing_rx.recv(), if in_flight.is_none() && !outgoing_closed => {
+                let Some(outgoing) = outgoing else {
+                    outgoing_closed = true;
+                    continue;
+                };
+
+                let frame = Frame::new(outgoing.data_type, sequence_number, &outgoing.content);
+                if framed.send(frame.clone()).await.is_err() {
+                    let _ = outgoing.done.send(Err(LinkError::Closed));
+                    break;
+                }
+                in_flight = Some(InFlight {
+                    frame,
+                    attempts: 1,
+                    deadline: Instant::now() + config.ack_timeout,
+                    done: outgoing.done,
+                });
+            }
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                let mut f = in_flight.take().unwrap();
+                if f.attempts > config.max_retries {
+                    // usually it's the acks that got lost and the headphone took the frame, so it
+                    // would drop the next one as a retransmission if it had the same number
+                    sequence_number ^= 1;
+                    let _ = f.done.send(Err(LinkError::NoAck { attempts: f.attempts }));
+                    continue;
+                }
+
+                debug_println!("No ack for {}, retrying ({})", f.frame, f.attempts);
+                if framed.send(f.frame.clone()).await.is_err() {
+                    let _ = f.done.send(Err(LinkError::Closed));
+                    break;
+                }
+                f.attempts += 1;
+                f.deadline = Instant::now() + config.ack_timeout;
+                in_flight = Some(f);
+            }
+        }
+    }
+
+    if let Some(f) = in_flight {
+        let _ = f.done.send(Err(LinkError::Closed));
+    }
+    debug_println!("Done (link) {:?}", framed.codec().stats());
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+
+    fn quick() -> LinkConfig {
+        LinkConfig {
+            ack_timeout: Duration::from_millis(20),
+            max_retries: 1,
+        }
+    }
+
+    /// The headphone's end of the link
+    fn peer(
+        config: LinkConfig,
+    ) -> (
+        FrameLink,
+        UnboundedReceiver<Frame>,
+        Framed<DuplexStream, FrameCodec>,
+    ) {
+        let (ours, theirs) = duplex(1 << 16);
+        let (link, incoming) = FrameLink::new(ours, config);
+        (link, incoming, Framed::new(theirs, FrameCodec::new()))
+    }
+
+    async fn next_frame(peer: &mut Framed<DuplexStream, FrameCodec>) -> Frame {
+        peer.next().await.unwrap().unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_new_sequence_number() {
+        let (link, _incoming, mut peer) = peer(quick());
+
+        let send = tokio::spawn({
+            let link = link.clone();
+            async move { link.send(FrameDataType::DataMdr, vec![0x01]).await }
+        });
+        // took it both times, the acks are what got lost
+        let first = next_frame(&mut peer).await;
+        let resent = next_frame(&mut peer).await;
+        assert_eq!(first, resent);
+        assert_eq!(send.await.unwrap(), Err(LinkError::NoAck { attempts: 2 }));
+
+        let send = tokio::spawn({
+            let link = link.clone();
+            async move { link.send(FrameDataType::DataMdr, vec![0x02]).await }
+        });
+        let next = next_frame(&mut peer).await;
+        assert_ne!(next.sequence_number, first.sequence_number);
+        peer.send(Frame::new_ack(next.sequence_number))
+            .await
+            .unwrap();
+        assert_eq!(send.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn slow_reader_doesnt_hold_up_acks() {
+        let (link, mut incoming, mut peer) = peer(LinkConfig::default());
+
+        // a lot, and nobody reads it yet
+        let count = 600;
+        for i in 0..count {
+            let frame = Frame::new(FrameDataType::DataMdr, (i % 2) as u8, &[i as u8]);
+            peer.send(frame).await.unwrap();
+            let ack = next_frame(&mut peer).await;
+            assert_eq!(ack.data_type, FrameDataType::Ack);
+        }
+
+        // and sending still works
+        let send = tokio::spawn(async move { link.send(FrameDataType::DataMdr, vec![]).await });
+        let frame = next_frame(&mut peer).await;
+        peer.send(Frame::new_ack(frame.sequence_number))
+            .await
+            .unwrap();
+        assert_eq!(send.await.unwrap(), Ok(()));
+
+        // they were acked, so all of them are still there and in order
+        for i in 0..count {
+            let frame = incoming.recv().await.unwrap();
+            assert_eq!(frame.content, vec![i as u8]);
+        }
+        assert!(incoming.try_recv().is_err());
+    }
+}
@@ -1,31 +1,19 @@
+use std::collections::BTreeSet;
 use std::fmt;
-use std::io::BufReader;
 
-use num_enum::{IntoPrimitive, TryFromPrimitive};
-use tokio::sync::mpsc::Receiver;
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
 
-use crate::protocols::frame::{Frame, FrameDataType};
+use crate::{
+    platforms::{InvalidMacAddress, MacAddress},
+    protocols::{
+        dialect::{MdrDialect, Opcode},
+        frame::{Frame, FrameDataType},
+        wire::{MdrField, PacketReader},
+    },
+};
 
-// TODO: find this
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive)]
-#[repr(u8)]
-pub enum MDRPacketType {
-    ConnectGetProtocolInfo = 0x00,
-    ConnectRetProtocolInfo = 0x01,
-    ConnectGetCapabilityInfo = 0x02,
-    ConnectGetDeviceInfo = 0x04,
-    ConnectRetDeviceInfo = 0x05,
-    ConnectGetSupportFunction = 0x06,
-    CommonNtfyBatteryLevel = 0x13,
-    ConnectedDeviecesGet = 0x36,
-    MultipointPinningSet = 0x38,
-    ConnectedDeviecesRet = 0x39, //??/
-    MultipointActiveDeviceSet = 0x3C,
-    VolumeChangedNotify = 0xA9,
-    Test = 0xFF, // its reserved for testing tho
-}
-
-// TODO: check v2, this is probably v1
+// gadgetbridge asks the same way in v1 and v2
 #[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
 #[repr(u8)]
 pub enum DeviceInfoInquiredType {
@@ -35,7 +23,9 @@ pub enum DeviceInfoInquiredType {
     InstructionGuide = 0x04,
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
 #[repr(u8)]
 pub enum ModelSeries {
     NoSeries = 0x00,
@@ -46,7 +36,9 @@ pub enum ModelSeries {
     Casual = 0x50,
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
 #[repr(u8)]
 pub enum ModelColor {
     Default = 0x00,
@@ -74,8 +66,108 @@ pub enum BatteryInquiredType {
     CradleBattery = 0x03,
 }
 
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum NcAsmInquiredType {
+    NoiseCancelling = 0x01,
+    NoiseCancellingAndAmbientSoundMode = 0x02,
+    AmbientSoundMode = 0x03,
+}
+
+#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EqEbbInquiredType {
+    PresetEq = 0x01,
+    Ebb = 0x02,
+    PresetEqNoncustomizable = 0x03,
+}
+
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, FromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum EqPreset {
+    Off = 0x00,
+    Rock = 0x01,
+    Pop = 0x02,
+    Jazz = 0x03,
+    Dance = 0x04,
+    Edm = 0x05,
+    RnbHipHop = 0x06,
+    Acoustic = 0x07,
+    Bright = 0x10,
+    Excited = 0x11,
+    Mellow = 0x12,
+    Relaxed = 0x13,
+    Vocal = 0x14,
+    TrebleBoost = 0x15,
+    BassBoost = 0x16,
+    Speech = 0x17,
+    Manual = 0xa0,
+    Custom1 = 0xa1,
+    Custom2 = 0xa2,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+// from https://github.com/AndreasOlofsson/mdr-protocol
+#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NcAsmEffect {
+    Off = 0x00,
+    On = 0x01,
+    AdjustmentInProgress = 0x10,
+    AdjustmentCompletion = 0x11,
+}
+
+#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NcSettingType {
+    OnOff = 0x00,
+    LevelAdjustment = 0x01,
+    DualSingleOff = 0x02,
+}
+
+// single is what the app calls wind noise reduction
+#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NcDualSingleValue {
+    Off = 0x00,
+    Single = 0x01,
+    Dual = 0x02,
+}
+
 #[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
 #[repr(u8)]
+pub enum AsmSettingType {
+    OnOff = 0x00,
+    LevelAdjustment = 0x01,
+}
+
+#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AsmId {
+    Normal = 0x00,
+    Voice = 0x01,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    IntoPrimitive,
+    FromPrimitive,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[repr(u8)]
 pub enum FunctionType {
     BatteryLevel = 0x11,
     UpscalingIndicator = 0x12,
@@ -115,6 +207,41 @@ pub enum FunctionType {
     SmartTalkingMode = 0xf5,
     AutoPowerOff = 0xf4,
     AssignableSettings = 0xf6,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// What the headphone says it can do
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupportedFunctions(pub BTreeSet<FunctionType>);
+
+impl SupportedFunctions {
+    pub fn contains(&self, function: FunctionType) -> bool {
+        self.0.contains(&function)
+    }
+
+    pub fn contains_any(&self, functions: &[FunctionType]) -> bool {
+        functions.iter().any(|function| self.contains(*function))
+    }
+}
+
+impl FromIterator<FunctionType> for SupportedFunctions {
+    fn from_iter<I: IntoIterator<Item = FunctionType>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+// a count then one byte per function
+impl MdrField for SupportedFunctions {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (functions, size) = Vec::<FunctionType>::read(bytes)?;
+        Ok((functions.into_iter().collect(), size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let functions: Vec<_> = self.0.iter().copied().collect();
+        functions.write(bytes);
+    }
 }
 
 #[derive(Debug)]
@@ -123,6 +250,11 @@ pub enum PacketError {
     InvalidUtf8(std::string::FromUtf8Error),
     UnimplementedPacketType(u8),
     InvalidPacketBody(u8),
+    InvalidMacAddress(InvalidMacAddress),
+    NotInDialect {
+        packet_type: MDRPacketType,
+        dialect: MdrDialect,
+    },
 }
 
 impl fmt::Display for PacketError {
@@ -136,26 +268,156 @@ impl fmt::Display for PacketError {
             PacketError::InvalidPacketBody(t) => {
                 write!(f, "Invalid packet body for type: 0x{:02x}", t)
             }
+            PacketError::InvalidMacAddress(e) => write!(f, "{}", e),
+            PacketError::NotInDialect {
+                packet_type,
+                dialect,
+            } => write!(f, "{:?} doesn't exist in {:?}", packet_type, dialect),
         }
     }
 }
 
 impl std::error::Error for PacketError {}
 
+impl From<InvalidMacAddress> for PacketError {
+    fn from(err: InvalidMacAddress) -> Self {
+        PacketError::InvalidMacAddress(err)
+    }
+}
+
 impl From<std::string::FromUtf8Error> for PacketError {
     fn from(err: std::string::FromUtf8Error) -> Self {
         PacketError::InvalidUtf8(err)
     }
 }
 
+/// Major class from the bluetooth class of device
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum DeviceClass {
+    Miscellaneous = 0x00,
+    Computer = 0x01,
+    Phone = 0x02,
+    Network = 0x03,
+    AudioVideo = 0x04,
+    Peripheral = 0x05,
+    Imaging = 0x06,
+    Wearable = 0x07,
+    Toy = 0x08,
+    Health = 0x09,
+    Uncategorized = 0x1F,
+}
+
+// a status byte then 3 bytes of class of device
+// which status bit means what is a best guess
+const DEVICE_CONNECTED: u8 = 0x01;
+const DEVICE_ACTIVE: u8 = 0x02;
+const DEVICE_PINNED: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectedDeviceFlags {
+    pub connected: bool,
+    /// The one audio is coming from
+    pub active: bool,
+    pub pinned: bool,
+    /// 24 bits
+    pub class_of_device: u32,
+    /// Status bits we don't know about, kept so the flags round trip
+    pub reserved: u8,
+}
+
+impl ConnectedDeviceFlags {
+    pub fn device_class(&self) -> Option<DeviceClass> {
+        if self.class_of_device == 0 {
+            return None;
+        }
+        DeviceClass::try_from(((self.class_of_device >> 8) & 0x1F) as u8).ok()
+    }
+}
+
+impl From<u32> for ConnectedDeviceFlags {
+    fn from(value: u32) -> Self {
+        let status = (value >> 24) as u8;
+        ConnectedDeviceFlags {
+            connected: status & DEVICE_CONNECTED != 0,
+            active: status & DEVICE_ACTIVE != 0,
+            pinned: status & DEVICE_PINNED != 0,
+            class_of_device: value & 0x00FF_FFFF,
+            reserved: status & !(DEVICE_CONNECTED | DEVICE_ACTIVE | DEVICE_PINNED),
+        }
+    }
+}
+
+impl From<ConnectedDeviceFlags> for u32 {
+    fn from(flags: ConnectedDeviceFlags) -> Self {
+        let mut status = flags.reserved;
+        if flags.connected {
+            status |= DEVICE_CONNECTED;
+        }
+        if flags.active {
+            status |= DEVICE_ACTIVE;
+        }
+        if flags.pinned {
+            status |= DEVICE_PINNED;
+        }
+        (status as u32) << 24 | (flags.class_of_device & 0x00FF_FFFF)
+    }
+}
+
+impl MdrField for ConnectedDeviceFlags {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (value, size) = u32::read(bytes)?;
+        Ok((value.into(), size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        u32::from(*self).write(bytes);
+    }
+}
+
 // 17 bytes of mac addr string 💀💀💀 + 4 bytes flags + name.len() + name
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConnectedDevice {
-    pub mac_address: String,
-    pub flags: u32,
+    pub mac_address: MacAddress,
+    pub flags: ConnectedDeviceFlags,
     pub name: String,
 }
 
+impl MdrField for ConnectedDevice {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let device = ConnectedDevice {
+            mac_address: reader.read()?,
+            flags: reader.read()?,
+            name: reader.read()?,
+        };
+        Ok((device, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.mac_address.write(bytes);
+        self.flags.write(bytes);
+        self.name.write(bytes);
+    }
+}
+
+// second byte of the multipoint packets, says which of them it is
+pub const MULTIPOINT_SOURCE_SWITCH: u8 = 0x01;
+pub const MULTIPOINT_PAIRING_MANAGEMENT: u8 = 0x02;
+
+/// What to do with a paired device, see `MDRPacket::MultipointDeviceAction`
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum MultipointAction {
+    Connect = 0x00,
+    Disconnect = 0x01,
+    Unpair = 0x02,
+}
+
 #[derive(Debug, Clone)]
 pub enum ConnectRetDeviceInfo {
     ModelName(String),
@@ -165,50 +427,44 @@ pub enum ConnectRetDeviceInfo {
 }
 
 impl ConnectRetDeviceInfo {
-    pub fn from_bytes(payload: &[u8]) -> Result<(Self, usize), PacketError> {
-        if payload.len() < 2 {
-            return Err(PacketError::BufferTooShort);
+    pub fn inquired_type(&self) -> DeviceInfoInquiredType {
+        match self {
+            ConnectRetDeviceInfo::ModelName(_) => DeviceInfoInquiredType::ModelName,
+            ConnectRetDeviceInfo::FwVersion(_) => DeviceInfoInquiredType::FwVersion,
+            ConnectRetDeviceInfo::SeriesAndColorInfo(_, _) => {
+                DeviceInfoInquiredType::SeriesAndColorInfo
+            }
+            ConnectRetDeviceInfo::InstructionGuide(_) => DeviceInfoInquiredType::InstructionGuide,
         }
-        let inquired_type = DeviceInfoInquiredType::try_from(payload[0])
-            .map_err(|_| PacketError::InvalidPacketBody(payload[0]))?;
+    }
+}
 
-        match inquired_type {
-            DeviceInfoInquiredType::ModelName => {
-                let len = payload[1] as usize;
-                if payload.len() < 2 + len {
-                    return Err(PacketError::BufferTooShort);
-                }
-                let name = String::from_utf8(payload[2..2 + len].to_vec())?;
-                Ok((ConnectRetDeviceInfo::ModelName(name), 2 + len))
-            }
-            DeviceInfoInquiredType::FwVersion => {
-                let len = payload[1] as usize;
-                if payload.len() < 2 + len {
-                    return Err(PacketError::BufferTooShort);
-                }
-                let version = String::from_utf8(payload[2..2 + len].to_vec())?;
-                Ok((ConnectRetDeviceInfo::FwVersion(version), 2 + len))
-            }
+impl MdrField for ConnectRetDeviceInfo {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let info = match reader.read()? {
+            DeviceInfoInquiredType::ModelName => ConnectRetDeviceInfo::ModelName(reader.read()?),
+            DeviceInfoInquiredType::FwVersion => ConnectRetDeviceInfo::FwVersion(reader.read()?),
             DeviceInfoInquiredType::SeriesAndColorInfo => {
-                if payload.len() < 3 {
-                    return Err(PacketError::BufferTooShort);
-                }
-                let series = ModelSeries::try_from(payload[1])
-                    .map_err(|_| PacketError::InvalidPacketBody(payload[1]))?;
-                let color = ModelColor::try_from(payload[2])
-                    .map_err(|_| PacketError::InvalidPacketBody(payload[2]))?;
-                Ok((ConnectRetDeviceInfo::SeriesAndColorInfo(series, color), 3))
+                ConnectRetDeviceInfo::SeriesAndColorInfo(reader.read()?, reader.read()?)
             }
             DeviceInfoInquiredType::InstructionGuide => {
-                let len = payload[1] as usize;
-                if payload.len() < 2 + len {
-                    return Err(PacketError::BufferTooShort);
-                }
-                Ok((
-                    ConnectRetDeviceInfo::InstructionGuide(payload[2..2 + len].to_vec()),
-                    2 + len,
-                ))
+                ConnectRetDeviceInfo::InstructionGuide(reader.read()?)
             }
+        };
+        Ok((info, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.inquired_type().write(bytes);
+        match self {
+            ConnectRetDeviceInfo::ModelName(name) => name.write(bytes),
+            ConnectRetDeviceInfo::FwVersion(version) => version.write(bytes),
+            ConnectRetDeviceInfo::SeriesAndColorInfo(series, color) => {
+                series.write(bytes);
+                color.write(bytes);
+            }
+            ConnectRetDeviceInfo::InstructionGuide(guide) => guide.write(bytes),
         }
     }
 }
@@ -232,295 +488,1450 @@ pub enum CommonRetBatteryLevel {
 }
 
 impl CommonRetBatteryLevel {
-    pub fn from_bytes(payload: &[u8]) -> Result<(Self, usize), PacketError> {
-        if payload.len() < 1 {
+    pub fn inquired_type(&self) -> BatteryInquiredType {
+        match self {
+            CommonRetBatteryLevel::Battery { .. } => BatteryInquiredType::Battery,
+            CommonRetBatteryLevel::LeftRightBattery { .. } => BatteryInquiredType::LeftRightBattery,
+            CommonRetBatteryLevel::CradleBattery { .. } => BatteryInquiredType::CradleBattery,
+        }
+    }
+}
+
+// level then whether it's charging, for each battery
+impl MdrField for CommonRetBatteryLevel {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let info = match reader.read()? {
+            BatteryInquiredType::Battery => CommonRetBatteryLevel::Battery {
+                level: reader.read()?,
+                is_charging: reader.read()?,
+            },
+            BatteryInquiredType::LeftRightBattery => CommonRetBatteryLevel::LeftRightBattery {
+                left_level: reader.read()?,
+                left_charging: reader.read()?,
+                right_level: reader.read()?,
+                right_charging: reader.read()?,
+            },
+            BatteryInquiredType::CradleBattery => CommonRetBatteryLevel::CradleBattery {
+                level: reader.read()?,
+                is_charging: reader.read()?,
+            },
+        };
+        Ok((info, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.inquired_type().write(bytes);
+        match self {
+            CommonRetBatteryLevel::Battery { level, is_charging }
+            | CommonRetBatteryLevel::CradleBattery { level, is_charging } => {
+                level.write(bytes);
+                is_charging.write(bytes);
+            }
+            CommonRetBatteryLevel::LeftRightBattery {
+                left_level,
+                left_charging,
+                right_level,
+                right_charging,
+            } => {
+                left_level.write(bytes);
+                left_charging.write(bytes);
+                right_level.write(bytes);
+                right_charging.write(bytes);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NcAsmMode {
+    Off,
+    NoiseCancelling,
+    WindNoiseReduction,
+    AmbientSound,
+}
+
+pub const MAX_AMBIENT_LEVEL: u8 = 20;
+
+/// Noise cancelling / ambient sound state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NcAsmParam {
+    pub inquired_type: NcAsmInquiredType,
+    pub mode: NcAsmMode,
+    /// 0..=20, only matters in ambient mode
+    pub ambient_level: u8,
+    /// "focus on voice"
+    pub voice_passthrough: bool,
+}
+
+impl NcAsmParam {
+    pub fn new(mode: NcAsmMode, ambient_level: u8, voice_passthrough: bool) -> Self {
+        Self {
+            inquired_type: NcAsmInquiredType::NoiseCancellingAndAmbientSoundMode,
+            mode,
+            ambient_level: ambient_level.min(MAX_AMBIENT_LEVEL),
+            voice_passthrough,
+        }
+    }
+}
+
+impl MdrField for NcAsmParam {
+    // [inquired type, effect, ...nc part, ...asm part]
+    // nc part: setting type, value
+    // asm part: setting type, asm id, level
+    fn read(payload: &[u8]) -> Result<(Self, usize), PacketError> {
+        if payload.len() < 2 {
             return Err(PacketError::BufferTooShort);
         }
-        let inquired_type = BatteryInquiredType::try_from(payload[0])
+        let inquired_type = NcAsmInquiredType::try_from(payload[0])
             .map_err(|_| PacketError::InvalidPacketBody(payload[0]))?;
+        let effect = NcAsmEffect::try_from(payload[1])
+            .map_err(|_| PacketError::InvalidPacketBody(payload[1]))?;
 
-        match inquired_type {
-            BatteryInquiredType::Battery => {
-                if payload.len() < 3 {
-                    return Err(PacketError::BufferTooShort);
-                }
-                Ok((
-                    CommonRetBatteryLevel::Battery {
-                        level: payload[1],
-                        is_charging: payload[2] != 0,
-                    },
-                    3,
-                ))
+        let size = match inquired_type {
+            NcAsmInquiredType::NoiseCancelling => 4,
+            NcAsmInquiredType::NoiseCancellingAndAmbientSoundMode => 7,
+            NcAsmInquiredType::AmbientSoundMode => 5,
+        };
+        if payload.len() < size {
+            return Err(PacketError::BufferTooShort);
+        }
+
+        let (nc, asm) = match inquired_type {
+            NcAsmInquiredType::NoiseCancelling => (Some(&payload[2..4]), None),
+            NcAsmInquiredType::NoiseCancellingAndAmbientSoundMode => {
+                (Some(&payload[2..4]), Some(&payload[4..7]))
             }
-            BatteryInquiredType::LeftRightBattery => {
-                if payload.len() < 5 {
-                    return Err(PacketError::BufferTooShort);
+            NcAsmInquiredType::AmbientSoundMode => (None, Some(&payload[2..5])),
+        };
+
+        let nc_value = match nc {
+            Some([setting_type, value]) => {
+                match NcSettingType::try_from(*setting_type)
+                    .map_err(|_| PacketError::InvalidPacketBody(*setting_type))?
+                {
+                    NcSettingType::DualSingleOff => NcDualSingleValue::try_from(*value)
+                        .map_err(|_| PacketError::InvalidPacketBody(*value))?,
+                    _ if *value != 0 => NcDualSingleValue::Dual,
+                    _ => NcDualSingleValue::Off,
                 }
-                Ok((
-                    CommonRetBatteryLevel::LeftRightBattery {
-                        left_level: payload[1],
-                        left_charging: payload[2] != 0,
-                        right_level: payload[3],
-                        right_charging: payload[4] != 0,
-                    },
-                    5,
-                ))
             }
-            BatteryInquiredType::CradleBattery => {
-                if payload.len() < 3 {
-                    return Err(PacketError::BufferTooShort);
-                }
-                Ok((
-                    CommonRetBatteryLevel::CradleBattery {
-                        level: payload[1],
-                        is_charging: payload[2] != 0,
-                    },
-                    3,
-                ))
+            _ => NcDualSingleValue::Off,
+        };
+        let (voice_passthrough, ambient_level) = match asm {
+            Some([_setting_type, asm_id, level]) => (
+                AsmId::try_from(*asm_id).map_err(|_| PacketError::InvalidPacketBody(*asm_id))?
+                    == AsmId::Voice,
+                *level,
+            ),
+            _ => (false, 0),
+        };
+
+        let mode = match (effect, nc_value) {
+            (NcAsmEffect::Off, _) => NcAsmMode::Off,
+            (_, NcDualSingleValue::Dual) => NcAsmMode::NoiseCancelling,
+            (_, NcDualSingleValue::Single) => NcAsmMode::WindNoiseReduction,
+            (_, NcDualSingleValue::Off) if asm.is_some() => NcAsmMode::AmbientSound,
+            (_, NcDualSingleValue::Off) => NcAsmMode::Off,
+        };
+
+        Ok((
+            NcAsmParam {
+                inquired_type,
+                mode,
+                ambient_level,
+                voice_passthrough,
+            },
+            size,
+        ))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let effect = match self.mode {
+            NcAsmMode::Off => NcAsmEffect::Off,
+            _ => NcAsmEffect::AdjustmentCompletion,
+        };
+        let nc_value = match self.mode {
+            NcAsmMode::NoiseCancelling => NcDualSingleValue::Dual,
+            NcAsmMode::WindNoiseReduction => NcDualSingleValue::Single,
+            NcAsmMode::Off | NcAsmMode::AmbientSound => NcDualSingleValue::Off,
+        };
+        let asm_id = if self.voice_passthrough {
+            AsmId::Voice
+        } else {
+            AsmId::Normal
+        };
+
+        bytes.extend::<[u8; 2]>([self.inquired_type.into(), effect.into()]);
+        if self.inquired_type != NcAsmInquiredType::AmbientSoundMode {
+            bytes.extend::<[u8; 2]>([NcSettingType::DualSingleOff.into(), nc_value.into()]);
+        }
+        if self.inquired_type != NcAsmInquiredType::NoiseCancelling {
+            bytes.extend::<[u8; 3]>([
+                AsmSettingType::LevelAdjustment.into(),
+                asm_id.into(),
+                self.ambient_level.min(MAX_AMBIENT_LEVEL),
+            ]);
+        }
+    }
+}
+
+// levels are sent as 0..=20 with 10 being flat
+pub const EQ_LEVEL_OFFSET: i8 = 10;
+pub const EQ_CUSTOM_BAND_COUNT: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EqPresetInfo {
+    pub preset: EqPreset,
+    pub name: String,
+}
+
+impl MdrField for EqPresetInfo {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let info = EqPresetInfo {
+            preset: reader.read()?,
+            name: reader.read()?,
+        };
+        Ok((info, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.preset.write(bytes);
+        self.name.write(bytes);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EqCapability {
+    pub customizable: bool,
+    pub band_count: u8,
+    pub level_steps: u8,
+    pub presets: Vec<EqPresetInfo>,
+}
+
+impl EqCapability {
+    /// Band level range in dB
+    pub fn level_range(&self) -> (i8, i8) {
+        let half = (self.level_steps / 2).min(EQ_LEVEL_OFFSET as u8) as i8;
+        (-half, half)
+    }
+
+    pub fn clamp_levels(&self, levels: &[i8]) -> Vec<i8> {
+        let (min, max) = self.level_range();
+        levels
+            .iter()
+            .take(self.band_count as usize)
+            .map(|level| (*level).clamp(min, max))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EbbCapability {
+    pub min_level: i8,
+    pub max_level: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EqEbbCapability {
+    Eq(EqCapability),
+    Ebb(EbbCapability),
+}
+
+impl EqEbbCapability {
+    pub fn inquired_type(&self) -> EqEbbInquiredType {
+        match self {
+            EqEbbCapability::Eq(eq) if eq.customizable => EqEbbInquiredType::PresetEq,
+            EqEbbCapability::Eq(_) => EqEbbInquiredType::PresetEqNoncustomizable,
+            EqEbbCapability::Ebb(_) => EqEbbInquiredType::Ebb,
+        }
+    }
+}
+
+impl MdrField for EqEbbCapability {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let capability = match reader.read()? {
+            inquired_type @ (EqEbbInquiredType::PresetEq
+            | EqEbbInquiredType::PresetEqNoncustomizable) => EqEbbCapability::Eq(EqCapability {
+                customizable: inquired_type == EqEbbInquiredType::PresetEq,
+                band_count: reader.read()?,
+                level_steps: reader.read()?,
+                presets: reader.read()?,
+            }),
+            EqEbbInquiredType::Ebb => EqEbbCapability::Ebb(EbbCapability {
+                min_level: (reader.read::<u8>()? as i8).wrapping_sub(EQ_LEVEL_OFFSET),
+                max_level: (reader.read::<u8>()? as i8).wrapping_sub(EQ_LEVEL_OFFSET),
+            }),
+        };
+        Ok((capability, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.inquired_type().write(bytes);
+        match self {
+            EqEbbCapability::Eq(eq) => {
+                eq.band_count.write(bytes);
+                eq.level_steps.write(bytes);
+                eq.presets.write(bytes);
+            }
+            EqEbbCapability::Ebb(ebb) => {
+                bytes.push(ebb.min_level.wrapping_add(EQ_LEVEL_OFFSET) as u8);
+                bytes.push(ebb.max_level.wrapping_add(EQ_LEVEL_OFFSET) as u8);
             }
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum MDRPacket {
-    ConnectGetProtocolInfo,
-    ConnectRetProtocolInfo {
+/// Current equalizer, levels are in dB
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EqParam {
+    pub preset: EqPreset,
+    pub levels: Vec<i8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EqEbbParam {
+    Eq(EqParam),
+    /// Clear Bass
+    Ebb(i8),
+}
+
+impl EqEbbParam {
+    pub fn inquired_type(&self) -> EqEbbInquiredType {
+        match self {
+            EqEbbParam::Eq(_) => EqEbbInquiredType::PresetEq,
+            EqEbbParam::Ebb(_) => EqEbbInquiredType::Ebb,
+        }
+    }
+}
+
+impl MdrField for EqEbbParam {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let param = match reader.read()? {
+            EqEbbInquiredType::PresetEq | EqEbbInquiredType::PresetEqNoncustomizable => {
+                let preset = reader.read()?;
+                let levels: Vec<u8> = reader.read()?;
+                let levels = levels
+                    .iter()
+                    .map(|level| (*level as i8).wrapping_sub(EQ_LEVEL_OFFSET))
+                    .collect();
+                EqEbbParam::Eq(EqParam { preset, levels })
+            }
+            EqEbbInquiredType::Ebb => {
+                EqEbbParam::Ebb((reader.read::<u8>()? as i8).wrapping_sub(EQ_LEVEL_OFFSET))
+            }
+        };
+        Ok((param, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.inquired_type().write(bytes);
+        match self {
+            EqEbbParam::Eq(eq) => {
+                eq.preset.write(bytes);
+                let levels: Vec<u8> = eq
+                    .levels
+                    .iter()
+                    .map(|level| level.wrapping_add(EQ_LEVEL_OFFSET) as u8)
+                    .collect();
+                levels.write(bytes);
+            }
+            EqEbbParam::Ebb(level) => bytes.push(level.wrapping_add(EQ_LEVEL_OFFSET) as u8),
+        }
+    }
+}
+
+/// Bluetooth codec in use
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, FromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum AudioCodec {
+    /// Nothing playing yet
+    Unsettled = 0x00,
+    Sbc = 0x01,
+    Aac = 0x02,
+    Ldac = 0x10,
+    AptX = 0x20,
+    AptXHd = 0x21,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Which DSEE the headphone has
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, FromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum UpscalingType {
+    DseeHx = 0x00,
+    Dsee = 0x01,
+    DseeHxAi = 0x02,
+    DseeExtreme = 0x03,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Whether upscaling is doing anything right now, turning it on doesn't mean it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpscalingIndicator {
+    pub upscaling_type: UpscalingType,
+    pub active: bool,
+}
+
+impl MdrField for UpscalingIndicator {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let indicator = UpscalingIndicator {
+            upscaling_type: reader.read()?,
+            active: reader.read()?,
+        };
+        Ok((indicator, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.upscaling_type.write(bytes);
+        self.active.write(bytes);
+    }
+}
+
+/// Second byte of the audio packets, says which setting it is about
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum AudioInquiredType {
+    ConnectionMode = 0x00,
+    /// DSEE
+    Upscaling = 0x01,
+}
+
+/// "Bluetooth connection quality" in the sony app, LDAC is only used with sound quality
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum ConnectionMode {
+    SoundQuality = 0x00,
+    StableConnection = 0x01,
+}
+
+/// A button or touch area that can be given another job
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, FromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum AssignableKey {
+    /// The NC/AMB or custom button
+    NcAmbButton = 0x00,
+    TouchPanel = 0x01,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// What a key can do
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, FromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum AssignablePreset {
+    AmbientSoundControl = 0x00,
+    VolumeControl = 0x10,
+    PlaybackControl = 0x20,
+    /// Whatever the phone uses
+    VoiceRecognition = 0x30,
+    GoogleAssistant = 0x31,
+    AmazonAlexa = 0x32,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+    NoFunction = 0xff,
+}
+
+/// A key and everything it can be set to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssignableKeyInfo {
+    pub key: AssignableKey,
+    pub presets: Vec<AssignablePreset>,
+}
+
+impl MdrField for AssignableKeyInfo {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let info = AssignableKeyInfo {
+            key: reader.read()?,
+            presets: reader.read()?,
+        };
+        Ok((info, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.key.write(bytes);
+        self.presets.write(bytes);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyAssignment {
+    pub key: AssignableKey,
+    pub preset: AssignablePreset,
+}
+
+impl MdrField for KeyAssignment {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let assignment = KeyAssignment {
+            key: reader.read()?,
+            preset: reader.read()?,
+        };
+        Ok((assignment, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.key.write(bytes);
+        self.preset.write(bytes);
+    }
+}
+
+/// Second byte of the system packets, says which setting it is about
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum SystemInquiredType {
+    /// Pause when taken off
+    ControlByWearing = 0x01,
+    /// Not a setting, whether it's on someone's head
+    WearingStatus = 0x02,
+    /// What the buttons do
+    AssignableSettings = 0x03,
+    AutoPowerOff = 0x04,
+    /// Adaptive Sound Control
+    AutoNcAsm = 0x0a,
+    /// Not a setting, what it thinks we are doing
+    AutoNcAsmStatus = 0x0b,
+    SmartTalkingMode = 0x0c,
+    /// Not a setting, whether it's in a talk session right now
+    SmartTalkingModeStatus = 0x0d,
+}
+
+/// Sony's own on/off, on is 0
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum OnOffSetting {
+    On = 0x00,
+    Off = 0x01,
+}
+
+impl OnOffSetting {
+    pub fn is_on(self) -> bool {
+        self == OnOffSetting::On
+    }
+}
+
+impl From<bool> for OnOffSetting {
+    fn from(on: bool) -> Self {
+        if on {
+            OnOffSetting::On
+        } else {
+            OnOffSetting::Off
+        }
+    }
+}
+
+/// When the headphone turns itself off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AutoPowerOff {
+    Off,
+    After5Minutes,
+    After30Minutes,
+    After1Hour,
+    After3Hours,
+    /// Right after it's taken off, needs wear detection
+    WhenRemoved,
+}
+
+impl AutoPowerOff {
+    // two bytes on the wire, the second one doesn't always follow the first
+    const CODES: &'static [(AutoPowerOff, [u8; 2])] = &[
+        (AutoPowerOff::Off, [0x11, 0x00]),
+        (AutoPowerOff::After5Minutes, [0x00, 0x00]),
+        (AutoPowerOff::After30Minutes, [0x01, 0x01]),
+        (AutoPowerOff::After1Hour, [0x02, 0x02]),
+        (AutoPowerOff::After3Hours, [0x03, 0x03]),
+        (AutoPowerOff::WhenRemoved, [0x10, 0x00]),
+    ];
+}
+
+impl MdrField for AutoPowerOff {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let codes: [u8; 2] = bytes
+            .get(..2)
+            .ok_or(PacketError::BufferTooShort)?
+            .try_into()
+            .unwrap();
+        let (setting, _) = Self::CODES
+            .iter()
+            .find(|(_, c)| *c == codes)
+            .ok_or(PacketError::InvalidPacketBody(codes[0]))?;
+        Ok((*setting, 2))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let (_, codes) = Self::CODES.iter().find(|(s, _)| s == self).unwrap();
+        bytes.extend_from_slice(codes);
+    }
+}
+
+/// What Adaptive Sound Control thinks we are doing
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum DetectedActivity {
+    Staying = 0x00,
+    Walking = 0x01,
+    Running = 0x02,
+    /// On a bus, train and the like
+    Transport = 0x03,
+}
+
+/// How readily talking starts a Speak-to-Chat session
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum SmartTalkingSensitivity {
+    Auto = 0x00,
+    High = 0x01,
+    Low = 0x02,
+}
+
+/// How long after the last word music comes back
+#[derive(
+    Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum SmartTalkingTimeout {
+    /// 5s
+    Short = 0x00,
+    /// 15s
+    Standard = 0x01,
+    /// 30s
+    Long = 0x02,
+    /// Until it's turned off by hand
+    Off = 0x03,
+}
+
+/// Speak-to-Chat behaviour, on/off is separate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmartTalkingConfig {
+    pub sensitivity: SmartTalkingSensitivity,
+    /// Let voices through during the session, like `NcAsmParam::voice_passthrough`
+    pub voice_passthrough: bool,
+    pub timeout: SmartTalkingTimeout,
+}
+
+impl MdrField for SmartTalkingConfig {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let config = SmartTalkingConfig {
+            sensitivity: reader.read()?,
+            voice_passthrough: reader.read()?,
+            timeout: reader.read()?,
+        };
+        Ok((config, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        self.sensitivity.write(bytes);
+        self.voice_passthrough.write(bytes);
+        self.timeout.write(bytes);
+    }
+}
+
+mdr_enum_field!(
+    DeviceInfoInquiredType,
+    ModelSeries,
+    ModelColor,
+    BatteryInquiredType,
+    NcAsmInquiredType,
+    EqEbbInquiredType,
+    EqPreset,
+    MultipointAction,
+    FunctionType,
+    AudioCodec,
+    UpscalingType,
+    AudioInquiredType,
+    ConnectionMode,
+    AssignableKey,
+    AssignablePreset,
+    SystemInquiredType,
+    OnOffSetting,
+    DetectedActivity,
+    SmartTalkingSensitivity,
+    SmartTalkingTimeout,
+);
+
+mdr_packets! {
+    ConnectGetProtocolInfo = 0x00 [0x00];
+    ConnectRetProtocolInfo = 0x01 {
         protocol_version: u16,
-    },
-    ConnectGetCapabilityInfo,
-    ConnectGetDeviceInfo {
+    };
+    ConnectGetCapabilityInfo = 0x02 [0x00];
+    ConnectGetDeviceInfo = 0x04 {
         inquired_type: DeviceInfoInquiredType,
-    },
-    ConnectRetDeviceInfo(ConnectRetDeviceInfo),
-    ConnectGetSupportFunction,
-    CommonNtfyBatteryLevel(CommonRetBatteryLevel),
-    ConnectedDeviecesGet {
+    };
+    ConnectRetDeviceInfo = 0x05 (ConnectRetDeviceInfo);
+    ConnectGetSupportFunction = 0x06 [0x00];
+    ConnectRetSupportFunction = 0x07 [0x00] (SupportedFunctions);
+    CommonGetBatteryLevel = 0x10 {
+        inquired_type: BatteryInquiredType,
+    };
+    CommonRetBatteryLevel = 0x11 (CommonRetBatteryLevel);
+    CommonNtfyBatteryLevel = 0x13 (CommonRetBatteryLevel);
+    CommonGetUpscalingEffect = 0x14 [0x00];
+    CommonRetUpscalingEffect = 0x15 [0x00] (UpscalingIndicator);
+    CommonNtfyUpscalingEffect = 0x17 [0x00] (UpscalingIndicator);
+    CommonGetAudioCodec = 0x18 [0x00];
+    CommonRetAudioCodec = 0x19 [0x00] {
+        codec: AudioCodec,
+    };
+    CommonNtfyAudioCodec = 0x1B [0x00] {
+        codec: AudioCodec,
+    };
+    /// Acked, then the link drops
+    CommonSetPowerOff = 0x22 [0x00, 0x01];
+    ConnectedDeviecesGet = 0x36 {
         b1: u8,
-    },
-    ConnectedDeviecesRet {
+    };
+    MultipointPinningSet = 0x38 [MULTIPOINT_SOURCE_SWITCH] {
+        enabled: bool,
+    };
+    /// Sometimes a few more bytes come after the list, no idea what they are or how long they
+    /// can be. they end up as leftover or `Unknown` rather than taking the start of the next packet
+    ConnectedDeviecesRet = 0x39 {
         connected_count: u8,
-        paired_count: u8,
         devices: Vec<ConnectedDevice>,
-    },
-    MultipointPinningSet {
-        payload: Vec<u8>,
-    },
-    MultipointActiveDeviceSet {
-        flag1: u8,
-        mac_address: String,
-    },
-    VolumeChangedNotify {
+    };
+    MultipointActiveDeviceSet = 0x3C [MULTIPOINT_SOURCE_SWITCH] {
+        mac_address: MacAddress,
+    };
+    MultipointDeviceAction = MultipointActiveDeviceSet [MULTIPOINT_PAIRING_MANAGEMENT] {
+        action: MultipointAction,
+        mac_address: MacAddress,
+    };
+    /// Spoken prompts like "power on", these go on `DataMdrNo2`
+    VoiceGuidanceGetParam = 0x46 [0x01, 0x01];
+    VoiceGuidanceRetParam = 0x47 [0x01, 0x01] {
+        enabled: bool,
+    };
+    VoiceGuidanceSetParam = 0x48 [0x01, 0x01] {
+        enabled: bool,
+    };
+    VoiceGuidanceNtfyParam = 0x49 [0x01, 0x01] {
+        enabled: bool,
+    };
+    EqEbbGetCapability = 0x50 {
+        inquired_type: EqEbbInquiredType,
+    } [0x00]; // display language, undefined
+    EqEbbRetCapability = 0x51 (EqEbbCapability);
+    EqEbbGetParam = 0x56 {
+        inquired_type: EqEbbInquiredType,
+    };
+    EqEbbRetParam = 0x57 (EqEbbParam);
+    EqEbbSetParam = 0x58 (EqEbbParam);
+    EqEbbNtfyParam = 0x59 (EqEbbParam);
+    NcAsmGetParam = 0x66 {
+        inquired_type: NcAsmInquiredType,
+    };
+    NcAsmRetParam = 0x67 (NcAsmParam);
+    NcAsmSetParam = 0x68 (NcAsmParam);
+    NcAsmNtfyParam = 0x69 (NcAsmParam);
+    VolumeChangedNotify = 0xA9 [0x20] {
         volume: u8,
-    },
-    Unknown {
-        payload: Vec<u8>,
-    },
+    };
+    AudioGetParam = 0xE6 {
+        inquired_type: AudioInquiredType,
+    };
+    type AudioRetParam = 0xE7;
+    type AudioSetParam = 0xE8;
+    type AudioNtfyParam = 0xE9;
+    ConnectionModeRetParam = AudioRetParam [AudioInquiredType::ConnectionMode as u8] {
+        mode: ConnectionMode,
+    };
+    ConnectionModeSetParam = AudioSetParam [AudioInquiredType::ConnectionMode as u8] {
+        mode: ConnectionMode,
+    };
+    ConnectionModeNtfyParam = AudioNtfyParam [AudioInquiredType::ConnectionMode as u8] {
+        mode: ConnectionMode,
+    };
+    UpscalingRetParam = AudioRetParam [AudioInquiredType::Upscaling as u8] {
+        enabled: bool,
+    };
+    UpscalingSetParam = AudioSetParam [AudioInquiredType::Upscaling as u8] {
+        enabled: bool,
+    };
+    UpscalingNtfyParam = AudioNtfyParam [AudioInquiredType::Upscaling as u8] {
+        enabled: bool,
+    };
+    SystemGetCapability = 0xF0 {
+        inquired_type: SystemInquiredType,
+    };
+    type SystemRetCapability = 0xF1;
+    SystemGetParam = 0xF6 {
+        inquired_type: SystemInquiredType,
+    };
+    type SystemRetParam = 0xF7;
+    type SystemSetParam = 0xF8;
+    type SystemNtfyParam = 0xF9;
+    SystemGetExtParam = 0xFA {
+        inquired_type: SystemInquiredType,
+    };
+    type SystemRetExtParam = 0xFB;
+    type SystemSetExtParam = 0xFC;
+    type SystemNtfyExtParam = 0xFD;
+    SmartTalkingRetParam = SystemRetParam [SystemInquiredType::SmartTalkingMode as u8] {
+        enabled: OnOffSetting,
+        preview_mode: OnOffSetting,
+    };
+    SmartTalkingSetParam = SystemSetParam [SystemInquiredType::SmartTalkingMode as u8] {
+        enabled: OnOffSetting,
+        preview_mode: OnOffSetting,
+    };
+    SmartTalkingNtfyParam = SystemNtfyParam [SystemInquiredType::SmartTalkingMode as u8] {
+        enabled: OnOffSetting,
+        preview_mode: OnOffSetting,
+    };
+    SmartTalkingNtfyStatus = SystemNtfyParam [SystemInquiredType::SmartTalkingModeStatus as u8] {
+        talking: bool,
+    };
+    SmartTalkingRetExtParam = SystemRetExtParam [SystemInquiredType::SmartTalkingMode as u8]
+        (SmartTalkingConfig);
+    SmartTalkingSetExtParam = SystemSetExtParam [SystemInquiredType::SmartTalkingMode as u8]
+        (SmartTalkingConfig);
+    SmartTalkingNtfyExtParam = SystemNtfyExtParam [SystemInquiredType::SmartTalkingMode as u8]
+        (SmartTalkingConfig);
+    ControlByWearingRetParam = SystemRetParam [SystemInquiredType::ControlByWearing as u8] {
+        enabled: OnOffSetting,
+    };
+    ControlByWearingSetParam = SystemSetParam [SystemInquiredType::ControlByWearing as u8] {
+        enabled: OnOffSetting,
+    };
+    ControlByWearingNtfyParam = SystemNtfyParam [SystemInquiredType::ControlByWearing as u8] {
+        enabled: OnOffSetting,
+    };
+    WearingRetStatus = SystemRetParam [SystemInquiredType::WearingStatus as u8] {
+        wearing: bool,
+    };
+    WearingNtfyStatus = SystemNtfyParam [SystemInquiredType::WearingStatus as u8] {
+        wearing: bool,
+    };
+    AssignableRetCapability = SystemRetCapability [SystemInquiredType::AssignableSettings as u8] {
+        keys: Vec<AssignableKeyInfo>,
+    };
+    AssignableRetParam = SystemRetParam [SystemInquiredType::AssignableSettings as u8] {
+        assignments: Vec<KeyAssignment>,
+    };
+    AssignableSetParam = SystemSetParam [SystemInquiredType::AssignableSettings as u8] {
+        assignments: Vec<KeyAssignment>,
+    };
+    AssignableNtfyParam = SystemNtfyParam [SystemInquiredType::AssignableSettings as u8] {
+        assignments: Vec<KeyAssignment>,
+    };
+    AutoPowerOffRetParam = SystemRetParam [SystemInquiredType::AutoPowerOff as u8, 0x01]
+        (AutoPowerOff);
+    AutoPowerOffSetParam = SystemSetParam [SystemInquiredType::AutoPowerOff as u8, 0x01]
+        (AutoPowerOff);
+    AutoPowerOffNtfyParam = SystemNtfyParam [SystemInquiredType::AutoPowerOff as u8, 0x01]
+        (AutoPowerOff);
+    AutoNcAsmRetParam = SystemRetParam [SystemInquiredType::AutoNcAsm as u8] {
+        enabled: OnOffSetting,
+    };
+    AutoNcAsmSetParam = SystemSetParam [SystemInquiredType::AutoNcAsm as u8] {
+        enabled: OnOffSetting,
+    };
+    AutoNcAsmNtfyParam = SystemNtfyParam [SystemInquiredType::AutoNcAsm as u8] {
+        enabled: OnOffSetting,
+    };
+    AutoNcAsmNtfyStatus = SystemNtfyParam [SystemInquiredType::AutoNcAsmStatus as u8] {
+        activity: DetectedActivity,
+    };
+}
+
+/// What came in one frame
+#[derive(Debug, Default)]
+pub struct FramePackets {
+    pub packets: Vec<MDRPacket>,
+    /// Bytes after the last packet that don't make up another one
+    pub leftover: usize,
 }
 
 impl MDRPacket {
-    // TODO: make this a result
-    pub fn from_frame(frame: Frame) -> Vec<MDRPacket> {
-        if frame.content.is_empty() || frame.data_type != FrameDataType::DataMdr {
-            return vec![];
+    /// Every packet in the frame, they can come back to back. Only an error if not even the first
+    /// one parses, what follows good packets is counted in `leftover` instead
+    pub fn from_frame(frame: Frame, dialect: MdrDialect) -> Result<FramePackets, PacketError> {
+        if !matches!(
+            frame.data_type,
+            FrameDataType::DataMdr | FrameDataType::DataMdrNo2
+        ) {
+            return Ok(FramePackets::default());
         }
 
-        let packet_type = MDRPacketType::try_from(frame.content[0]).unwrap_or(MDRPacketType::Test);
-        let payload = &frame.content;
-
-        match Self::parse_packet(packet_type, payload) {
-            Ok((packet, _size)) => vec![packet],
-            Err(e) => {
-                println!("Error parsing packet: {}", e);
-                vec![]
+        let mut packets = vec![];
+        let mut content = frame.content.as_slice();
+        while !content.is_empty() {
+            match Self::decode(content, frame.data_type, dialect) {
+                Ok((packet, size)) => {
+                    packets.push(packet);
+                    content = content.get(size..).unwrap_or_default();
+                }
+                Err(_) if !packets.is_empty() => break,
+                Err(e) => return Err(e),
             }
         }
+        Ok(FramePackets {
+            packets,
+            leftover: content.len(),
+        })
     }
 
-    fn parse_packet(
-        packet_type: MDRPacketType,
-        payload: &[u8],
+    /// One packet off the start of `content`, the opcode is looked up in `dialect` first
+    pub fn decode(
+        content: &[u8],
+        data_type: FrameDataType,
+        dialect: MdrDialect,
     ) -> Result<(MDRPacket, usize), PacketError> {
-        match packet_type {
-            MDRPacketType::ConnectRetProtocolInfo => {
-                if payload.len() < 3 {
-                    return Err(PacketError::BufferTooShort);
-                }
-                let protocol_version = u16::from_be_bytes([payload[1], payload[2]]);
-                Ok((MDRPacket::ConnectRetProtocolInfo { protocol_version }, 3))
-            }
-            MDRPacketType::ConnectRetDeviceInfo => {
-                let (info, size) = ConnectRetDeviceInfo::from_bytes(payload)?;
-                Ok((MDRPacket::ConnectRetDeviceInfo(info), size))
-            }
-            MDRPacketType::CommonNtfyBatteryLevel => {
-                let (info, size) = CommonRetBatteryLevel::from_bytes(payload)?;
-                Ok((MDRPacket::CommonNtfyBatteryLevel(info), size))
-            }
-            MDRPacketType::ConnectedDeviecesRet => {
-                if payload.len() < 3 {
-                    return Err(PacketError::BufferTooShort);
-                }
+        let [opcode, rest @ ..] = content else {
+            return Err(PacketError::BufferTooShort);
+        };
+        let unknown = |payload: Vec<u8>| MDRPacket::Unknown {
+            opcode: *opcode,
+            payload,
+        };
+        let Some(packet_type) = dialect.packet_type(Opcode {
+            data_type,
+            opcode: *opcode,
+        }) else {
+            return Ok((unknown(rest.to_vec()), content.len()));
+        };
 
-                let connected_count = payload[1];
-                let paired_count = payload[2];
-                let mut devices = Vec::new();
-
-                let mut index = 3;
-                for _ in 0..paired_count {
-                    if index + 17 > payload.len() {
-                        return Err(PacketError::BufferTooShort);
-                    }
-                    let mac_address = String::from_utf8(payload[index..index + 17].to_vec())?;
-                    index += 17;
-
-                    println!("{} {}", connected_count, mac_address);
-
-                    if index + 4 > payload.len() {
-                        return Err(PacketError::BufferTooShort);
-                    }
-                    let flags = u32::from_be_bytes([
-                        payload[index],
-                        payload[index + 1],
-                        payload[index + 2],
-                        payload[index + 3],
-                    ]);
-                    index += 4;
-
-                    if index + 1 > payload.len() {
-                        return Err(PacketError::BufferTooShort);
-                    }
-                    let name_len = payload[index] as usize;
-                    index += 1;
-                    
-                    if index + name_len > payload.len() {
-                        println!("[short] 4");
-                        return Err(PacketError::BufferTooShort);
-                    }
-                    let name = String::from_utf8(payload[index..index + name_len].to_vec())?;
-                    index += name_len;
-
-                    println!("{:.?}", devices);
-
-                    devices.push(ConnectedDevice {
-                        mac_address,
-                        flags,
-                        name,
-                    });
-                }
+        let mut renumbered = content.to_vec();
+        renumbered[0] = packet_type.into();
+        match Self::parse_packet(&renumbered)? {
+            // keep what was actually on the wire
+            (MDRPacket::Unknown { payload, .. }, size) => Ok((unknown(payload), size)),
+            parsed => Ok(parsed),
+        }
+    }
 
-                if index + 3 <= payload.len() {
-                    index += 3;
-                }
+    /// The channel and bytes to send it as. `Unknown` goes as is on `DataMdr`
+    pub fn encode(&self, dialect: MdrDialect) -> Result<(FrameDataType, Vec<u8>), PacketError> {
+        let mut bytes = self.to_bytes();
+        if let MDRPacket::Unknown { .. } = self {
+            return Ok((FrameDataType::DataMdr, bytes));
+        }
 
-                Ok((
-                    MDRPacket::ConnectedDeviecesRet {
-                        connected_count,
-                        paired_count,
-                        devices,
-                    },
-                    index,
-                ))
-            }
-            MDRPacketType::MultipointActiveDeviceSet => {
-                if payload.len() < 19 {
-                    return Err(PacketError::BufferTooShort);
-                }
-                let flag1 = payload[1];
-                let mac_address = String::from_utf8(payload[2..19].to_vec())?;
-                Ok((
-                    MDRPacket::MultipointActiveDeviceSet { flag1, mac_address },
-                    19,
-                ))
+        let packet_type = MDRPacketType::try_from(bytes[0]).expect("described packets have a type");
+        let opcode = dialect
+            .opcode(packet_type)
+            .ok_or(PacketError::NotInDialect {
+                packet_type,
+                dialect,
+            })?;
+        bytes[0] = opcode.opcode;
+        Ok((opcode.data_type, bytes))
+    }
+
+    /// Whether the headphone answers this packet with another one (as opposed to just an ack)
+    pub fn expects_reply(&self) -> bool {
+        matches!(
+            self,
+            MDRPacket::ConnectGetProtocolInfo
+                | MDRPacket::ConnectGetDeviceInfo { .. }
+                | MDRPacket::ConnectGetSupportFunction
+                | MDRPacket::CommonGetBatteryLevel { .. }
+                | MDRPacket::CommonGetUpscalingEffect
+                | MDRPacket::CommonGetAudioCodec
+                | MDRPacket::ConnectedDeviecesGet { .. }
+                | MDRPacket::VoiceGuidanceGetParam
+                | MDRPacket::EqEbbGetCapability { .. }
+                | MDRPacket::EqEbbGetParam { .. }
+                | MDRPacket::NcAsmGetParam { .. }
+                | MDRPacket::AudioGetParam { .. }
+                | MDRPacket::SystemGetCapability { .. }
+                | MDRPacket::SystemGetParam { .. }
+                | MDRPacket::SystemGetExtParam { .. }
+        )
+    }
+
+    pub fn is_reply_to(&self, request: &MDRPacket) -> bool {
+        match (request, self) {
+            (MDRPacket::ConnectGetProtocolInfo, MDRPacket::ConnectRetProtocolInfo { .. }) => true,
+            (MDRPacket::ConnectGetSupportFunction, MDRPacket::ConnectRetSupportFunction(_)) => true,
+            (
+                MDRPacket::ConnectGetDeviceInfo { inquired_type },
+                MDRPacket::ConnectRetDeviceInfo(info),
+            ) => info.inquired_type() == *inquired_type,
+            (
+                MDRPacket::CommonGetBatteryLevel { inquired_type },
+                MDRPacket::CommonRetBatteryLevel(info),
+            ) => info.inquired_type() == *inquired_type,
+            (MDRPacket::CommonGetUpscalingEffect, MDRPacket::CommonRetUpscalingEffect(_)) => true,
+            (MDRPacket::CommonGetAudioCodec, MDRPacket::CommonRetAudioCodec { .. }) => true,
+            (MDRPacket::ConnectedDeviecesGet { .. }, MDRPacket::ConnectedDeviecesRet { .. }) => {
+                true
             }
-            MDRPacketType::MultipointPinningSet => {
-                if payload.len() < 1 {
-                    return Err(PacketError::BufferTooShort);
+            (MDRPacket::VoiceGuidanceGetParam, MDRPacket::VoiceGuidanceRetParam { .. }) => true,
+            (
+                MDRPacket::EqEbbGetCapability { inquired_type },
+                MDRPacket::EqEbbRetCapability(capability),
+            ) => match inquired_type {
+                // the headphone decides whether it is customizable or not
+                EqEbbInquiredType::Ebb => capability.inquired_type() == EqEbbInquiredType::Ebb,
+                _ => capability.inquired_type() != EqEbbInquiredType::Ebb,
+            },
+            (MDRPacket::EqEbbGetParam { inquired_type }, MDRPacket::EqEbbRetParam(param)) => {
+                match inquired_type {
+                    EqEbbInquiredType::Ebb => param.inquired_type() == EqEbbInquiredType::Ebb,
+                    _ => param.inquired_type() != EqEbbInquiredType::Ebb,
                 }
-                Ok((
-                    MDRPacket::MultipointPinningSet {
-                        payload: payload[1..].to_vec(),
-                    },
-                    payload.len(),
-                ))
             }
-            MDRPacketType::VolumeChangedNotify => {
-                if payload.len() < 3 {
-                    return Err(PacketError::BufferTooShort);
-                }
-                Ok((MDRPacket::VolumeChangedNotify { volume: payload[2] }, 3))
+            (MDRPacket::NcAsmGetParam { inquired_type }, MDRPacket::NcAsmRetParam(param)) => {
+                param.inquired_type == *inquired_type
             }
-            _ => Ok((
-                MDRPacket::Unknown {
-                    payload: payload.to_vec(),
+            (
+                MDRPacket::AudioGetParam {
+                    inquired_type: AudioInquiredType::ConnectionMode,
+                },
+                MDRPacket::ConnectionModeRetParam { .. },
+            ) => true,
+            (
+                MDRPacket::AudioGetParam {
+                    inquired_type: AudioInquiredType::Upscaling,
+                },
+                MDRPacket::UpscalingRetParam { .. },
+            ) => true,
+            (
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::SmartTalkingMode,
+                },
+                MDRPacket::SmartTalkingRetParam { .. },
+            ) => true,
+            (
+                MDRPacket::SystemGetExtParam {
+                    inquired_type: SystemInquiredType::SmartTalkingMode,
+                },
+                MDRPacket::SmartTalkingRetExtParam(_),
+            ) => true,
+            (
+                MDRPacket::SystemGetCapability {
+                    inquired_type: SystemInquiredType::AssignableSettings,
                 },
-                payload.len(),
-            )),
+                MDRPacket::AssignableRetCapability { .. },
+            ) => true,
+            (
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::AssignableSettings,
+                },
+                MDRPacket::AssignableRetParam { .. },
+            ) => true,
+            (
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::ControlByWearing,
+                },
+                MDRPacket::ControlByWearingRetParam { .. },
+            ) => true,
+            (
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::WearingStatus,
+                },
+                MDRPacket::WearingRetStatus { .. },
+            ) => true,
+            (
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::AutoPowerOff,
+                },
+                MDRPacket::AutoPowerOffRetParam(_),
+            ) => true,
+            (
+                MDRPacket::SystemGetParam {
+                    inquired_type: SystemInquiredType::AutoNcAsm,
+                },
+                MDRPacket::AutoNcAsmRetParam { .. },
+            ) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::wire::Examples;
+
+    impl Examples for SupportedFunctions {
+        fn examples() -> Vec<Self> {
+            Vec::<FunctionType>::examples()
+                .into_iter()
+                .map(|functions| functions.into_iter().collect())
+                .collect()
         }
     }
 
-    pub fn from_frame_stream(mut frame_rx: Receiver<Frame>) -> Receiver<MDRPacket> {
-        let (tx, rx) = tokio::sync::mpsc::channel(512);
-        tokio::spawn(async move {
-            while let Some(frame) = frame_rx.recv().await {
-                let packets = MDRPacket::from_frame(frame);
+    impl Examples for ConnectedDeviceFlags {
+        fn examples() -> Vec<Self> {
+            u32::examples().into_iter().map(Self::from).collect()
+        }
+    }
 
-                for packet in packets {
-                    tx.send(packet).await.unwrap();
-                }
-            }
-            println!("Done (mdr) frame_rx.is_closed:{}", frame_rx.is_closed());
-        });
-
-        rx
-    }
-
-    // we cant do this unless we have full table of mdr packet because size is unknown
-    // fn from_byte_stream(bytes_rx: Receiver<u8>) -> Receiver<MDRPacket> {
-    //     let (tx, rx) = tokio::sync::mpsc::channel(512);
-    //     tokio::spawn(async move {
-    //         let mut buffer: Vec<u8> = vec![];
-    //         let mut escape_next = false;
-    //         while let Some(byte) = bytes_rx.recv().await {
-    //             // print!("{:02x} ", byte);
-    //             match byte {
-    //             };
-    //         }
-    //         println!("Done (mdr) bytes_rx.is_closed:{}", bytes_rx.is_closed());
-    //     });
-
-    //     rx
-    // }
-
-    pub fn to_bytes(&self) -> Option<Vec<u8>> {
-        match self {
-            MDRPacket::ConnectGetProtocolInfo => {
-                Some(vec![MDRPacketType::ConnectGetProtocolInfo.into(), 0x00])
-            }
-            MDRPacket::ConnectGetCapabilityInfo => {
-                Some(vec![MDRPacketType::ConnectGetCapabilityInfo.into(), 0x00])
-            }
-            MDRPacket::ConnectGetDeviceInfo { inquired_type } => Some(vec![
-                MDRPacketType::ConnectGetDeviceInfo.into(),
-                (*inquired_type).into(),
-            ]),
-            MDRPacket::ConnectGetSupportFunction => {
-                Some(vec![MDRPacketType::ConnectGetSupportFunction.into(), 0x00])
-            }
-            MDRPacket::ConnectedDeviecesGet { b1 } => {
-                Some(vec![MDRPacketType::ConnectedDeviecesGet.into(), *b1])
-            }
-            MDRPacket::MultipointPinningSet { payload } => {
-                let mut bytes = vec![MDRPacketType::MultipointPinningSet.into()];
-                bytes.extend_from_slice(payload);
-                Some(bytes)
+    impl Examples for ConnectedDevice {
+        fn examples() -> Vec<Self> {
+            let names = String::examples();
+            ConnectedDeviceFlags::examples()
+                .into_iter()
+                .zip(MacAddress::examples().into_iter().cycle())
+                .zip(names.into_iter().cycle())
+                .map(|((flags, mac_address), name)| ConnectedDevice {
+                    mac_address,
+                    flags,
+                    name,
+                })
+                .collect()
+        }
+    }
+
+    impl Examples for ConnectRetDeviceInfo {
+        fn examples() -> Vec<Self> {
+            vec![
+                ConnectRetDeviceInfo::ModelName("WH-1000XM5".to_owned()),
+                ConnectRetDeviceInfo::FwVersion(String::new()),
+                ConnectRetDeviceInfo::SeriesAndColorInfo(ModelSeries::Premium, ModelColor::Silver),
+                ConnectRetDeviceInfo::InstructionGuide(vec![0x3c, 0x3d, 0x3e]),
+            ]
+        }
+    }
+
+    impl Examples for CommonRetBatteryLevel {
+        fn examples() -> Vec<Self> {
+            vec![
+                CommonRetBatteryLevel::Battery {
+                    level: 70,
+                    is_charging: false,
+                },
+                CommonRetBatteryLevel::LeftRightBattery {
+                    left_level: 60,
+                    left_charging: true,
+                    right_level: 62,
+                    right_charging: false,
+                },
+                CommonRetBatteryLevel::CradleBattery {
+                    level: 100,
+                    is_charging: true,
+                },
+            ]
+        }
+    }
+
+    impl Examples for NcAsmParam {
+        fn examples() -> Vec<Self> {
+            let only = |inquired_type, mode, ambient_level, voice_passthrough| NcAsmParam {
+                inquired_type,
+                mode,
+                ambient_level,
+                voice_passthrough,
+            };
+            let nc_only = NcAsmInquiredType::NoiseCancelling;
+            let asm_only = NcAsmInquiredType::AmbientSoundMode;
+            vec![
+                NcAsmParam::new(NcAsmMode::Off, 5, false),
+                NcAsmParam::new(NcAsmMode::NoiseCancelling, 0, false),
+                NcAsmParam::new(NcAsmMode::WindNoiseReduction, 0, false),
+                NcAsmParam::new(NcAsmMode::AmbientSound, MAX_AMBIENT_LEVEL, true),
+                // nothing about ambient sound in there
+                only(nc_only, NcAsmMode::Off, 0, false),
+                only(nc_only, NcAsmMode::NoiseCancelling, 0, false),
+                // and nothing about noise cancelling
+                only(asm_only, NcAsmMode::AmbientSound, 10, true),
+            ]
+        }
+    }
+
+    impl Examples for EqPresetInfo {
+        fn examples() -> Vec<Self> {
+            vec![
+                EqPresetInfo {
+                    preset: EqPreset::Bright,
+                    name: "Bright".to_owned(),
+                },
+                EqPresetInfo {
+                    preset: EqPreset::Unknown(0x3c),
+                    name: String::new(),
+                },
+            ]
+        }
+    }
+
+    impl Examples for EqEbbCapability {
+        fn examples() -> Vec<Self> {
+            vec![
+                EqEbbCapability::Eq(EqCapability {
+                    customizable: true,
+                    band_count: 5,
+                    level_steps: 21,
+                    presets: EqPresetInfo::examples(),
+                }),
+                EqEbbCapability::Eq(EqCapability {
+                    customizable: false,
+                    band_count: 0,
+                    level_steps: 0,
+                    presets: vec![],
+                }),
+                EqEbbCapability::Ebb(EbbCapability {
+                    min_level: -10,
+                    max_level: 10,
+                }),
+            ]
+        }
+    }
+
+    impl Examples for EqEbbParam {
+        fn examples() -> Vec<Self> {
+            vec![
+                EqEbbParam::Eq(EqParam {
+                    preset: EqPreset::Manual,
+                    levels: vec![-10, -5, 0, 5, 10],
+                }),
+                EqEbbParam::Eq(EqParam {
+                    preset: EqPreset::Off,
+                    levels: vec![],
+                }),
+                EqEbbParam::Ebb(-3),
+            ]
+        }
+    }
+
+    impl Examples for UpscalingIndicator {
+        fn examples() -> Vec<Self> {
+            vec![
+                UpscalingIndicator {
+                    upscaling_type: UpscalingType::DseeExtreme,
+                    active: true,
+                },
+                UpscalingIndicator {
+                    upscaling_type: UpscalingType::Unknown(0x3d),
+                    active: false,
+                },
+            ]
+        }
+    }
+
+    impl Examples for AssignableKeyInfo {
+        fn examples() -> Vec<Self> {
+            let presets = Vec::<AssignablePreset>::examples();
+            AssignableKey::examples()
+                .into_iter()
+                .zip(presets.into_iter().cycle())
+                .map(|(key, presets)| AssignableKeyInfo { key, presets })
+                .collect()
+        }
+    }
+
+    impl Examples for KeyAssignment {
+        fn examples() -> Vec<Self> {
+            let keys = AssignableKey::examples();
+            AssignablePreset::examples()
+                .into_iter()
+                .zip(keys.into_iter().cycle())
+                .map(|(preset, key)| KeyAssignment { key, preset })
+                .collect()
+        }
+    }
+
+    impl Examples for AutoPowerOff {
+        fn examples() -> Vec<Self> {
+            Self::CODES.iter().map(|(setting, _)| *setting).collect()
+        }
+    }
+
+    impl Examples for SmartTalkingConfig {
+        fn examples() -> Vec<Self> {
+            let timeouts = SmartTalkingTimeout::examples();
+            SmartTalkingSensitivity::examples()
+                .into_iter()
+                .zip(timeouts.into_iter().cycle())
+                .zip([false, true].into_iter().cycle())
+                .map(
+                    |((sensitivity, timeout), voice_passthrough)| SmartTalkingConfig {
+                        sensitivity,
+                        voice_passthrough,
+                        timeout,
+                    },
+                )
+                .collect()
+        }
+    }
+
+    #[test]
+    fn every_packet_round_trips() {
+        let packets = MDRPacket::examples();
+        for packet in &packets {
+            let bytes = packet.to_bytes();
+            let (parsed, size) = MDRPacket::parse_packet(&bytes)
+                .unwrap_or_else(|e| panic!("{packet:?} doesn't parse: {e}"));
+            assert_eq!(size, bytes.len(), "{packet:?}");
+            assert_eq!(format!("{parsed:?}"), format!("{packet:?}"));
+            assert_eq!(parsed.to_bytes(), bytes);
+        }
+
+        // nothing got left out
+        let opcodes: BTreeSet<u8> = packets.iter().map(|p| p.to_bytes()[0]).collect();
+        for opcode in 0..=u8::MAX {
+            if let Ok(packet_type) = MDRPacketType::try_from(opcode) {
+                assert!(opcodes.contains(&opcode), "no {packet_type:?}");
             }
-            MDRPacket::MultipointActiveDeviceSet { flag1, mac_address } => {
-                let mut bytes = vec![MDRPacketType::MultipointActiveDeviceSet.into(), *flag1];
-                bytes.extend(mac_address.as_bytes());
-                Some(bytes)
+        }
+    }
+
+    #[test]
+    fn every_packet_goes_through_each_dialect() {
+        for dialect in [MdrDialect::V1, MdrDialect::V2] {
+            for packet in MDRPacket::examples() {
+                let (data_type, bytes) = match packet.encode(dialect) {
+                    Ok(encoded) => encoded,
+                    Err(PacketError::NotInDialect { .. }) => continue,
+                    Err(e) => panic!("{packet:?}: {e}"),
+                };
+                let (decoded, size) = MDRPacket::decode(&bytes, data_type, dialect).unwrap();
+                assert_eq!(size, bytes.len());
+                assert_eq!(format!("{decoded:?}"), format!("{packet:?}"));
             }
-            _ => None,
         }
+
+        let guidance = MDRPacket::VoiceGuidanceSetParam { enabled: true };
+        let (data_type, bytes) = guidance.encode(MdrDialect::V2).unwrap();
+        assert_eq!(data_type, FrameDataType::DataMdrNo2);
+        let (decoded, _) =
+            MDRPacket::decode(&bytes, FrameDataType::DataMdr, MdrDialect::V2).unwrap();
+        assert!(matches!(decoded, MDRPacket::Unknown { opcode: 0x48, .. }));
+
+        let result = MDRPacket::CommonSetPowerOff.encode(MdrDialect::V2);
+        assert!(matches!(result, Err(PacketError::NotInDialect { .. })));
+    }
+
+    fn frame(packets: &[MDRPacket], extra: &[u8]) -> Frame {
+        let mut content = vec![];
+        for packet in packets {
+            let (_, bytes) = packet.encode(MdrDialect::default()).unwrap();
+            content.extend(bytes);
+        }
+        content.extend_from_slice(extra);
+        Frame {
+            data_type: FrameDataType::DataMdr,
+            sequence_number: 0,
+            content,
+        }
+    }
+
+    fn devices() -> MDRPacket {
+        MDRPacket::ConnectedDeviecesRet {
+            connected_count: 1,
+            devices: ConnectedDevice::examples(),
+        }
+    }
+
+    fn from_frame(frame: Frame) -> (Vec<MDRPacket>, usize) {
+        let FramePackets { packets, leftover } =
+            MDRPacket::from_frame(frame, MdrDialect::default()).unwrap();
+        (packets, leftover)
+    }
+
+    fn debug(packets: &[MDRPacket]) -> String {
+        format!("{packets:?}")
+    }
+
+    #[test]
+    fn packets_come_back_to_back() {
+        let sent = [
+            MDRPacket::VolumeChangedNotify { volume: 0x3c },
+            devices(),
+            MDRPacket::NcAsmNtfyParam(NcAsmParam::new(NcAsmMode::AmbientSound, 20, true)),
+            MDRPacket::WearingNtfyStatus { wearing: true },
+        ];
+        let (packets, leftover) = from_frame(frame(&sent, &[]));
+        assert_eq!(debug(&packets), debug(&sent));
+        assert_eq!(leftover, 0);
+    }
+
+    #[test]
+    fn devices_in_the_middle_leave_the_next_packet_alone() {
+        let sent = [devices(), MDRPacket::VolumeChangedNotify { volume: 0x3c }];
+        let (packets, leftover) = from_frame(frame(&sent, &[]));
+        assert_eq!(debug(&packets), debug(&sent));
+        assert_eq!(leftover, 0);
+    }
+
+    #[test]
+    fn leftover_bytes_keep_the_packets_before_them() {
+        let volume = MDRPacket::VolumeChangedNotify { volume: 1 };
+        let (_, nc_asm) = MDRPacket::NcAsmNtfyParam(NcAsmParam::new(NcAsmMode::Off, 0, false))
+            .encode(MdrDialect::default())
+            .unwrap();
+
+        // an nc/asm packet cut short after good ones
+        let sent = [volume, devices()];
+        let (packets, leftover) = from_frame(frame(&sent, &nc_asm[..2]));
+        assert_eq!(debug(&packets), debug(&sent));
+        assert_eq!(leftover, 2);
+
+        // and on its own, it's just that
+        let result = MDRPacket::from_frame(frame(&[], &nc_asm[..2]), MdrDialect::default());
+        assert!(matches!(result, Err(PacketError::BufferTooShort)));
+    }
+
+    #[test]
+    fn unknown_takes_the_rest() {
+        let dialect = MdrDialect::default();
+        let opcode = (0..=u8::MAX)
+            .find(|opcode| {
+                let opcode = Opcode {
+                    data_type: FrameDataType::DataMdr,
+                    opcode: *opcode,
+                };
+                dialect.packet_type(opcode).is_none()
+            })
+            .unwrap();
+        let volume = [MDRPacket::VolumeChangedNotify { volume: 1 }];
+
+        let (packets, _) = from_frame(frame(&volume, &[opcode, 0x3c, 0x3d]));
+        assert!(matches!(
+            packets.as_slice(),
+            [
+                MDRPacket::VolumeChangedNotify { volume: 1 },
+                MDRPacket::Unknown { opcode: o, payload },
+            ] if *o == opcode && payload == &[0x3c, 0x3d]
+        ));
+
+        // a known opcode that isn't followed by anything described keeps what was on the wire
+        let (_, system) = MDRPacket::WearingNtfyStatus { wearing: false }
+            .encode(dialect)
+            .unwrap();
+        let (packets, _) = from_frame(frame(&volume, &[system[0], 0xee, 0x3e]));
+        assert!(matches!(
+            packets.as_slice(),
+            [
+                MDRPacket::VolumeChangedNotify { .. },
+                MDRPacket::Unknown { opcode, payload },
+            ] if *opcode == system[0] && payload == &[0xee, 0x3e]
+        ));
+
+        // other channels aren't packets
+        let mut control = frame(&volume, &[]);
+        control.data_type = FrameDataType::Ack;
+        assert!(from_frame(control).0.is_empty());
     }
 }
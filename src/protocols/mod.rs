@@ -1,4 +1,11 @@
+#[macro_use]
+pub mod wire;
+pub mod adaptive;
+pub mod battery;
+pub mod dialect;
 pub mod frame;
+pub mod link;
 pub mod properties;
 pub mod mdr;
-pub mod connection;
\ No newline at end of file
+pub mod connection;
+pub mod wearing;
\ No newline at end of file
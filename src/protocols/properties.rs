@@ -1,18 +1,707 @@
-use crate::{platforms::traits::DeviceCommunication, protocols::mdr::MDRPacket};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::protocols::dialect::MdrDialect;
+use crate::protocols::mdr::{
+    AssignableKeyInfo, AudioCodec, AutoPowerOff, CommonRetBatteryLevel, ConnectRetDeviceInfo,
+    ConnectedDevice, ConnectionMode, DetectedActivity, EbbCapability, EqCapability,
+    EqEbbCapability, EqEbbParam, EqParam, FunctionType, KeyAssignment, MDRPacket, ModelColor,
+    ModelSeries, NcAsmParam, SmartTalkingConfig, SupportedFunctions, UpscalingIndicator,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatteryLevel {
+    pub level: u8,
+    pub is_charging: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeftRightBatteryLevel {
+    pub left: BatteryLevel,
+    pub right: BatteryLevel,
+}
+
+/// Which part of [`HeadphoneProperties`] an update touched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HeadphoneProperty {
+    ProtocolVersion,
+    SupportedFunctions,
+    ModelName,
+    FwVersion,
+    SeriesAndColor,
+    Battery,
+    LeftRightBattery,
+    CradleBattery,
+    Volume,
+    Devices,
+    NcAsm,
+    EqCapability,
+    Eq,
+    ClearBassCapability,
+    ClearBass,
+    SpeakToChat,
+    SpeakToChatConfig,
+    Talking,
+    AutoNcAsm,
+    Activity,
+    Codec,
+    Upscaling,
+    UpscalingIndicator,
+    ConnectionMode,
+    AutoPowerOff,
+    ControlByWearing,
+    Wearing,
+    AssignableCapability,
+    Assignments,
+}
+
+// None means we haven't heard about it yet
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct HeadphoneProperties {
-    pub placeholder_text: String
+    pub protocol_version: Option<u16>,
+    pub supported_functions: Option<SupportedFunctions>,
+    pub model_name: Option<String>,
+    pub fw_version: Option<String>,
+    pub series: Option<ModelSeries>,
+    pub color: Option<ModelColor>,
+    pub battery: Option<BatteryLevel>,
+    pub left_right_battery: Option<LeftRightBatteryLevel>,
+    pub cradle_battery: Option<BatteryLevel>,
+    pub volume: Option<u8>,
+    pub connected_count: Option<u8>,
+    pub devices: Option<Vec<ConnectedDevice>>,
+    pub nc_asm: Option<NcAsmParam>,
+    pub eq_capability: Option<EqCapability>,
+    pub eq: Option<EqParam>,
+    pub clear_bass_capability: Option<EbbCapability>,
+    pub clear_bass: Option<i8>,
+    pub speak_to_chat: Option<bool>,
+    pub speak_to_chat_config: Option<SmartTalkingConfig>,
+    /// In a Speak-to-Chat session right now
+    pub talking: Option<bool>,
+    /// Adaptive Sound Control
+    pub auto_nc_asm: Option<bool>,
+    /// Only ever notified, stays `None` until it detects something
+    pub activity: Option<DetectedActivity>,
+    pub codec: Option<AudioCodec>,
+    /// DSEE
+    pub upscaling: Option<bool>,
+    pub upscaling_indicator: Option<UpscalingIndicator>,
+    pub connection_mode: Option<ConnectionMode>,
+    pub auto_power_off: Option<AutoPowerOff>,
+    /// Pause when taken off
+    pub control_by_wearing: Option<bool>,
+    /// On someone's head, see `wearing::wear_events` to get told when that changes
+    pub wearing: Option<bool>,
+    /// Which keys can be reassigned and to what
+    pub assignable_capability: Option<Vec<AssignableKeyInfo>>,
+    pub assignments: Option<Vec<KeyAssignment>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PropertiesChanged {
+    pub properties: HeadphoneProperties,
+    pub changed: Vec<HeadphoneProperty>,
+}
+
+fn set<T: PartialEq>(
+    field: &mut Option<T>,
+    value: T,
+    property: HeadphoneProperty,
+    changed: &mut Vec<HeadphoneProperty>,
+) {
+    if field.as_ref() != Some(&value) {
+        *field = Some(value);
+        changed.push(property);
+    }
 }
 
 impl HeadphoneProperties {
-    pub async fn new<D: DeviceCommunication>(communication: &mut D) -> Self {
-        Self {
-            placeholder_text: "".to_owned()
+    /// Which opcode table to use, v1 until the headphone says otherwise
+    pub fn dialect(&self) -> MdrDialect {
+        self.protocol_version
+            .map(MdrDialect::from_protocol_version)
+            .unwrap_or_default()
+    }
+
+    /// Whether the headphone has any of these (or nothing is needed).
+    /// True until it tells us what it has, so nothing is hidden from a headphone that never answers
+    pub fn supports_any(&self, functions: &[FunctionType]) -> bool {
+        match &self.supported_functions {
+            Some(supported) => functions.is_empty() || supported.contains_any(functions),
+            None => true,
+        }
+    }
+
+    /// Returns what actually changed, empty if the packet told us nothing new
+    pub fn update(&mut self, packet: &MDRPacket) -> Vec<HeadphoneProperty> {
+        let mut changed = vec![];
+
+        match packet {
+            MDRPacket::ConnectRetProtocolInfo { protocol_version } => set(
+                &mut self.protocol_version,
+                *protocol_version,
+                HeadphoneProperty::ProtocolVersion,
+                &mut changed,
+            ),
+            MDRPacket::ConnectRetSupportFunction(functions) => set(
+                &mut self.supported_functions,
+                functions.clone(),
+                HeadphoneProperty::SupportedFunctions,
+                &mut changed,
+            ),
+            MDRPacket::ConnectRetDeviceInfo(info) => match info {
+                ConnectRetDeviceInfo::ModelName(name) => set(
+                    &mut self.model_name,
+                    name.clone(),
+                    HeadphoneProperty::ModelName,
+                    &mut changed,
+                ),
+                ConnectRetDeviceInfo::FwVersion(version) => set(
+                    &mut self.fw_version,
+                    version.clone(),
+                    HeadphoneProperty::FwVersion,
+                    &mut changed,
+                ),
+                ConnectRetDeviceInfo::SeriesAndColorInfo(series, color) => {
+                    if self.series != Some(*series) || self.color != Some(*color) {
+                        self.series = Some(*series);
+                        self.color = Some(*color);
+                        changed.push(HeadphoneProperty::SeriesAndColor);
+                    }
+                }
+                ConnectRetDeviceInfo::InstructionGuide(_) => {}
+            },
+            MDRPacket::CommonRetBatteryLevel(battery)
+            | MDRPacket::CommonNtfyBatteryLevel(battery) => match *battery {
+                CommonRetBatteryLevel::Battery { level, is_charging } => set(
+                    &mut self.battery,
+                    BatteryLevel { level, is_charging },
+                    HeadphoneProperty::Battery,
+                    &mut changed,
+                ),
+                CommonRetBatteryLevel::LeftRightBattery {
+                    left_level,
+                    left_charging,
+                    right_level,
+                    right_charging,
+                } => set(
+                    &mut self.left_right_battery,
+                    LeftRightBatteryLevel {
+                        left: BatteryLevel {
+                            level: left_level,
+                            is_charging: left_charging,
+                        },
+                        right: BatteryLevel {
+                            level: right_level,
+                            is_charging: right_charging,
+                        },
+                    },
+                    HeadphoneProperty::LeftRightBattery,
+                    &mut changed,
+                ),
+                CommonRetBatteryLevel::CradleBattery { level, is_charging } => set(
+                    &mut self.cradle_battery,
+                    BatteryLevel { level, is_charging },
+                    HeadphoneProperty::CradleBattery,
+                    &mut changed,
+                ),
+            },
+            MDRPacket::VolumeChangedNotify { volume } => set(
+                &mut self.volume,
+                *volume,
+                HeadphoneProperty::Volume,
+                &mut changed,
+            ),
+            MDRPacket::ConnectedDeviecesRet {
+                connected_count,
+                devices,
+                ..
+            } if self.connected_count != Some(*connected_count)
+                || self.devices.as_ref() != Some(devices) =>
+            {
+                self.connected_count = Some(*connected_count);
+                self.devices = Some(devices.clone());
+                changed.push(HeadphoneProperty::Devices);
+            }
+            MDRPacket::NcAsmRetParam(param) | MDRPacket::NcAsmNtfyParam(param) => set(
+                &mut self.nc_asm,
+                *param,
+                HeadphoneProperty::NcAsm,
+                &mut changed,
+            ),
+            MDRPacket::EqEbbRetCapability(capability) => match capability {
+                EqEbbCapability::Eq(eq) => set(
+                    &mut self.eq_capability,
+                    eq.clone(),
+                    HeadphoneProperty::EqCapability,
+                    &mut changed,
+                ),
+                EqEbbCapability::Ebb(ebb) => set(
+                    &mut self.clear_bass_capability,
+                    *ebb,
+                    HeadphoneProperty::ClearBassCapability,
+                    &mut changed,
+                ),
+            },
+            MDRPacket::EqEbbRetParam(param) | MDRPacket::EqEbbNtfyParam(param) => match param {
+                EqEbbParam::Eq(eq) => set(
+                    &mut self.eq,
+                    eq.clone(),
+                    HeadphoneProperty::Eq,
+                    &mut changed,
+                ),
+                EqEbbParam::Ebb(level) => set(
+                    &mut self.clear_bass,
+                    *level,
+                    HeadphoneProperty::ClearBass,
+                    &mut changed,
+                ),
+            },
+            MDRPacket::SmartTalkingRetParam { enabled, .. }
+            | MDRPacket::SmartTalkingNtfyParam { enabled, .. } => set(
+                &mut self.speak_to_chat,
+                enabled.is_on(),
+                HeadphoneProperty::SpeakToChat,
+                &mut changed,
+            ),
+            MDRPacket::SmartTalkingRetExtParam(config)
+            | MDRPacket::SmartTalkingNtfyExtParam(config) => set(
+                &mut self.speak_to_chat_config,
+                *config,
+                HeadphoneProperty::SpeakToChatConfig,
+                &mut changed,
+            ),
+            MDRPacket::SmartTalkingNtfyStatus { talking } => set(
+                &mut self.talking,
+                *talking,
+                HeadphoneProperty::Talking,
+                &mut changed,
+            ),
+            MDRPacket::AutoNcAsmRetParam { enabled }
+            | MDRPacket::AutoNcAsmNtfyParam { enabled } => set(
+                &mut self.auto_nc_asm,
+                enabled.is_on(),
+                HeadphoneProperty::AutoNcAsm,
+                &mut changed,
+            ),
+            MDRPacket::AutoNcAsmNtfyStatus { activity } => set(
+                &mut self.activity,
+                *activity,
+                HeadphoneProperty::Activity,
+                &mut changed,
+            ),
+            MDRPacket::CommonRetAudioCodec { codec }
+            | MDRPacket::CommonNtfyAudioCodec { codec } => set(
+                &mut self.codec,
+                *codec,
+                HeadphoneProperty::Codec,
+                &mut changed,
+            ),
+            MDRPacket::UpscalingRetParam { enabled }
+            | MDRPacket::UpscalingNtfyParam { enabled } => set(
+                &mut self.upscaling,
+                *enabled,
+                HeadphoneProperty::Upscaling,
+                &mut changed,
+            ),
+            MDRPacket::CommonRetUpscalingEffect(indicator)
+            | MDRPacket::CommonNtfyUpscalingEffect(indicator) => set(
+                &mut self.upscaling_indicator,
+                *indicator,
+                HeadphoneProperty::UpscalingIndicator,
+                &mut changed,
+            ),
+            MDRPacket::ConnectionModeRetParam { mode }
+            | MDRPacket::ConnectionModeNtfyParam { mode } => set(
+                &mut self.connection_mode,
+                *mode,
+                HeadphoneProperty::ConnectionMode,
+                &mut changed,
+            ),
+            MDRPacket::AutoPowerOffRetParam(setting)
+            | MDRPacket::AutoPowerOffNtfyParam(setting) => set(
+                &mut self.auto_power_off,
+                *setting,
+                HeadphoneProperty::AutoPowerOff,
+                &mut changed,
+            ),
+            MDRPacket::ControlByWearingRetParam { enabled }
+            | MDRPacket::ControlByWearingNtfyParam { enabled } => set(
+                &mut self.control_by_wearing,
+                enabled.is_on(),
+                HeadphoneProperty::ControlByWearing,
+                &mut changed,
+            ),
+            MDRPacket::WearingRetStatus { wearing } | MDRPacket::WearingNtfyStatus { wearing } => {
+                set(
+                    &mut self.wearing,
+                    *wearing,
+                    HeadphoneProperty::Wearing,
+                    &mut changed,
+                )
+            }
+            MDRPacket::AssignableRetCapability { keys } => set(
+                &mut self.assignable_capability,
+                keys.clone(),
+                HeadphoneProperty::AssignableCapability,
+                &mut changed,
+            ),
+            MDRPacket::AssignableRetParam { assignments }
+            | MDRPacket::AssignableNtfyParam { assignments } => set(
+                &mut self.assignments,
+                assignments.clone(),
+                HeadphoneProperty::Assignments,
+                &mut changed,
+            ),
+            _ => {}
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::{
+        mdr::{
+            AssignableKey, AssignablePreset, EqPreset, NcAsmMode, OnOffSetting,
+            SmartTalkingSensitivity, SmartTalkingTimeout, UpscalingType,
+        },
+        wire::Examples,
+    };
+
+    type Check = fn(&HeadphoneProperties) -> bool;
+
+    /// Fed in order to the same properties: what each packet changes, and how it should look after
+    fn updates() -> Vec<(MDRPacket, HeadphoneProperty, Check)> {
+        let nc_asm = NcAsmParam::new(NcAsmMode::AmbientSound, 12, true);
+        let config = SmartTalkingConfig {
+            sensitivity: SmartTalkingSensitivity::High,
+            voice_passthrough: false,
+            timeout: SmartTalkingTimeout::Long,
+        };
+        let indicator = UpscalingIndicator {
+            upscaling_type: UpscalingType::DseeExtreme,
+            active: true,
+        };
+        let assignment = KeyAssignment {
+            key: AssignableKey::NcAmbButton,
+            preset: AssignablePreset::GoogleAssistant,
+        };
+        vec![
+            (
+                MDRPacket::ConnectRetProtocolInfo {
+                    protocol_version: 0x0200,
+                },
+                HeadphoneProperty::ProtocolVersion,
+                |p| p.protocol_version == Some(0x0200) && p.dialect() == MdrDialect::V2,
+            ),
+            (
+                MDRPacket::ConnectRetSupportFunction(
+                    [FunctionType::PowerOff, FunctionType::AutoNcAsm]
+                        .into_iter()
+                        .collect(),
+                ),
+                HeadphoneProperty::SupportedFunctions,
+                |p| {
+                    p.supports_any(&[FunctionType::AutoNcAsm])
+                        && !p.supports_any(&[FunctionType::Ebb])
+                },
+            ),
+            (
+                MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::ModelName("WH".to_owned())),
+                HeadphoneProperty::ModelName,
+                |p| p.model_name.as_deref() == Some("WH"),
+            ),
+            (
+                MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::FwVersion("1.0".to_owned())),
+                HeadphoneProperty::FwVersion,
+                |p| p.fw_version.as_deref() == Some("1.0"),
+            ),
+            (
+                MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::SeriesAndColorInfo(
+                    ModelSeries::Premium,
+                    ModelColor::Black,
+                )),
+                HeadphoneProperty::SeriesAndColor,
+                |p| p.series == Some(ModelSeries::Premium) && p.color == Some(ModelColor::Black),
+            ),
+            (
+                MDRPacket::CommonRetBatteryLevel(CommonRetBatteryLevel::Battery {
+                    level: 60,
+                    is_charging: true,
+                }),
+                HeadphoneProperty::Battery,
+                |p| p.battery.is_some_and(|b| b.level == 60 && b.is_charging),
+            ),
+            (
+                MDRPacket::CommonNtfyBatteryLevel(CommonRetBatteryLevel::LeftRightBattery {
+                    left_level: 50,
+                    left_charging: false,
+                    right_level: 40,
+                    right_charging: true,
+                }),
+                HeadphoneProperty::LeftRightBattery,
+                |p| {
+                    p.left_right_battery
+                        .is_some_and(|b| b.left.level == 50 && b.right.is_charging)
+                },
+            ),
+            (
+                MDRPacket::CommonRetBatteryLevel(CommonRetBatteryLevel::CradleBattery {
+                    level: 90,
+                    is_charging: false,
+                }),
+                HeadphoneProperty::CradleBattery,
+                |p| p.cradle_battery.is_some_and(|b| b.level == 90),
+            ),
+            (
+                MDRPacket::VolumeChangedNotify { volume: 7 },
+                HeadphoneProperty::Volume,
+                |p| p.volume == Some(7),
+            ),
+            (
+                MDRPacket::ConnectedDeviecesRet {
+                    connected_count: 2,
+                    devices: ConnectedDevice::examples(),
+                },
+                HeadphoneProperty::Devices,
+                |p| p.connected_count == Some(2) && p.devices == Some(ConnectedDevice::examples()),
+            ),
+            (
+                MDRPacket::NcAsmNtfyParam(nc_asm),
+                HeadphoneProperty::NcAsm,
+                |p| {
+                    p.nc_asm
+                        .is_some_and(|nc| nc.ambient_level == 12 && nc.voice_passthrough)
+                },
+            ),
+            (
+                MDRPacket::EqEbbRetParam(EqEbbParam::Eq(EqParam {
+                    preset: EqPreset::Bright,
+                    levels: vec![1, 2, 3, 4, 5],
+                })),
+                HeadphoneProperty::Eq,
+                |p| {
+                    p.eq.as_ref()
+                        .is_some_and(|eq| eq.preset == EqPreset::Bright)
+                },
+            ),
+            (
+                MDRPacket::EqEbbNtfyParam(EqEbbParam::Ebb(-3)),
+                HeadphoneProperty::ClearBass,
+                |p| p.clear_bass == Some(-3),
+            ),
+            (
+                MDRPacket::SmartTalkingRetParam {
+                    enabled: OnOffSetting::On,
+                    preview_mode: OnOffSetting::Off,
+                },
+                HeadphoneProperty::SpeakToChat,
+                |p| p.speak_to_chat == Some(true),
+            ),
+            (
+                MDRPacket::SmartTalkingNtfyExtParam(config),
+                HeadphoneProperty::SpeakToChatConfig,
+                |p| {
+                    p.speak_to_chat_config
+                        .is_some_and(|c| c.timeout == SmartTalkingTimeout::Long)
+                },
+            ),
+            (
+                MDRPacket::SmartTalkingNtfyStatus { talking: true },
+                HeadphoneProperty::Talking,
+                |p| p.talking == Some(true),
+            ),
+            (
+                MDRPacket::AutoNcAsmRetParam {
+                    enabled: OnOffSetting::Off,
+                },
+                HeadphoneProperty::AutoNcAsm,
+                |p| p.auto_nc_asm == Some(false),
+            ),
+            (
+                MDRPacket::AutoNcAsmNtfyStatus {
+                    activity: DetectedActivity::Running,
+                },
+                HeadphoneProperty::Activity,
+                |p| p.activity == Some(DetectedActivity::Running),
+            ),
+            (
+                MDRPacket::CommonNtfyAudioCodec {
+                    codec: AudioCodec::Aac,
+                },
+                HeadphoneProperty::Codec,
+                |p| p.codec == Some(AudioCodec::Aac),
+            ),
+            (
+                MDRPacket::UpscalingNtfyParam { enabled: true },
+                HeadphoneProperty::Upscaling,
+                |p| p.upscaling == Some(true),
+            ),
+            (
+                MDRPacket::CommonRetUpscalingEffect(indicator),
+                HeadphoneProperty::UpscalingIndicator,
+                |p| p.upscaling_indicator.is_some_and(|i| i.active),
+            ),
+            (
+                MDRPacket::ConnectionModeRetParam {
+                    mode: ConnectionMode::StableConnection,
+                },
+                HeadphoneProperty::ConnectionMode,
+                |p| p.connection_mode == Some(ConnectionMode::StableConnection),
+            ),
+            (
+                MDRPacket::AutoPowerOffNtfyParam(AutoPowerOff::After1Hour),
+                HeadphoneProperty::AutoPowerOff,
+                |p| p.auto_power_off == Some(AutoPowerOff::After1Hour),
+            ),
+            (
+                MDRPacket::ControlByWearingNtfyParam {
+                    enabled: OnOffSetting::On,
+                },
+                HeadphoneProperty::ControlByWearing,
+                |p| p.control_by_wearing == Some(true),
+            ),
+            (
+                MDRPacket::WearingRetStatus { wearing: false },
+                HeadphoneProperty::Wearing,
+                |p| p.wearing == Some(false),
+            ),
+            (
+                MDRPacket::AssignableRetCapability {
+                    keys: vec![AssignableKeyInfo {
+                        key: AssignableKey::NcAmbButton,
+                        presets: vec![AssignablePreset::GoogleAssistant],
+                    }],
+                },
+                HeadphoneProperty::AssignableCapability,
+                |p| {
+                    p.assignable_capability
+                        .as_ref()
+                        .is_some_and(|keys| keys.len() == 1)
+                },
+            ),
+            (
+                MDRPacket::AssignableNtfyParam {
+                    assignments: vec![assignment],
+                },
+                HeadphoneProperty::Assignments,
+                |p| {
+                    p.assignments
+                        .as_ref()
+                        .is_some_and(|a| a[0].preset == AssignablePreset::GoogleAssistant)
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn each_packet_changes_its_property() {
+        let mut properties = HeadphoneProperties::default();
+        for (packet, property, check) in updates() {
+            assert_eq!(properties.update(&packet), vec![property], "{packet:?}");
+            assert!(check(&properties), "{packet:?} gave {properties:#?}");
+
+            // the same again is nothing new
+            let before = properties.clone();
+            assert_eq!(properties.update(&packet), vec![], "{packet:?} again");
+            assert_eq!(properties, before);
+        }
+    }
+
+    #[test]
+    fn replies_and_notifications_share_a_property() {
+        let mut properties = HeadphoneProperties::default();
+        let param = NcAsmParam::new(NcAsmMode::NoiseCancelling, 0, false);
+
+        properties.update(&MDRPacket::NcAsmRetParam(param));
+        assert_eq!(properties.update(&MDRPacket::NcAsmNtfyParam(param)), vec![]);
+
+        let ambient = NcAsmParam::new(NcAsmMode::AmbientSound, 3, false);
+        assert_eq!(
+            properties.update(&MDRPacket::NcAsmNtfyParam(ambient)),
+            vec![HeadphoneProperty::NcAsm]
+        );
+        assert_eq!(properties.nc_asm, Some(ambient));
+    }
+
+    #[test]
+    fn half_a_change_is_a_change() {
+        let mut properties = HeadphoneProperties::default();
+        let series_and_color = |color| {
+            MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::SeriesAndColorInfo(
+                ModelSeries::Premium,
+                color,
+            ))
+        };
+        properties.update(&series_and_color(ModelColor::Black));
+        assert_eq!(
+            properties.update(&series_and_color(ModelColor::Silver)),
+            vec![HeadphoneProperty::SeriesAndColor]
+        );
+        assert_eq!(properties.series, Some(ModelSeries::Premium));
+        assert_eq!(properties.color, Some(ModelColor::Silver));
+
+        // same devices, one fewer connected
+        let devices = |connected_count| MDRPacket::ConnectedDeviecesRet {
+            connected_count,
+            devices: ConnectedDevice::examples(),
+        };
+        properties.update(&devices(2));
+        assert_eq!(
+            properties.update(&devices(1)),
+            vec![HeadphoneProperty::Devices]
+        );
+        assert_eq!(properties.connected_count, Some(1));
+    }
+
+    #[test]
+    fn other_packets_change_nothing() {
+        let mut properties = HeadphoneProperties::default();
+        let packets = [
+            MDRPacket::ConnectGetSupportFunction,
+            MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::InstructionGuide(vec![1])),
+            MDRPacket::NcAsmSetParam(NcAsmParam::new(NcAsmMode::Off, 0, false)),
+            MDRPacket::Unknown {
+                opcode: 0xee,
+                payload: vec![0x3c],
+            },
+        ];
+        for packet in packets {
+            assert_eq!(properties.update(&packet), vec![], "{packet:?}");
         }
+        assert_eq!(properties, HeadphoneProperties::default());
     }
 
-    pub fn update(&mut self, packet: MDRPacket) {
-        
+    #[test]
+    fn eq_and_clear_bass_capabilities_are_told_apart() {
+        let mut properties = HeadphoneProperties::default();
+
+        let ebb = EbbCapability {
+            min_level: -10,
+            max_level: 10,
+        };
+        let packet = MDRPacket::EqEbbRetCapability(EqEbbCapability::Ebb(ebb));
+        assert_eq!(
+            properties.update(&packet),
+            vec![HeadphoneProperty::ClearBassCapability]
+        );
+        assert_eq!(properties.clear_bass_capability, Some(ebb));
+        // nothing new the second time
+        assert_eq!(properties.update(&packet), vec![]);
+
+        let eq = EqCapability {
+            customizable: true,
+            band_count: 5,
+            level_steps: 20,
+            presets: vec![],
+        };
+        let packet = MDRPacket::EqEbbRetCapability(EqEbbCapability::Eq(eq));
+        assert_eq!(
+            properties.update(&packet),
+            vec![HeadphoneProperty::EqCapability]
+        );
     }
 }
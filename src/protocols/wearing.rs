@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::{
+    platforms::traits::DeviceCommunication,
+    protocols::{connection::HeadphoneConnection, properties::HeadphoneProperty},
+};
+
+// wear detection works whether or not Control-by-Wearing pauses anything,
+// the headphone just tells us when it goes on or comes off
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WearEvent {
+    PutOn,
+    TakenOff,
+}
+
+impl WearEvent {
+    pub fn from_wearing(wearing: bool) -> Self {
+        if wearing {
+            WearEvent::PutOn
+        } else {
+            WearEvent::TakenOff
+        }
+    }
+}
+
+/// Only changes, what it was when we connected isn't one
+pub fn wear_events<D>(connection: &HeadphoneConnection<D>) -> Receiver<WearEvent>
+where
+    D: DeviceCommunication,
+{
+    let (tx, rx) = channel(8);
+    let mut changes_rx = connection.properties_rx();
+    let mut wearing = connection.properties().wearing;
+
+    tokio::spawn(async move {
+        while let Some(change) = changes_rx.recv().await {
+            if !change.changed.contains(&HeadphoneProperty::Wearing) {
+                continue;
+            }
+            let was_known = wearing.is_some();
+            wearing = change.properties.wearing;
+            let Some(now) = wearing.filter(|_| was_known) else {
+                continue;
+            };
+            if tx.send(WearEvent::from_wearing(now)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
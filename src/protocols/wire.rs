@@ -0,0 +1,495 @@
+use crate::{platforms::MacAddress, protocols::mdr::PacketError};
+
+// how things are laid out inside an mdr packet
+// `mdr_packets!` in mdr.rs puts these together so every packet is only described once
+
+/// Something that can be read from and written to a packet
+pub trait MdrField: Sized {
+    /// Returns the value and how many bytes it took
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError>;
+    fn write(&self, bytes: &mut Vec<u8>);
+}
+
+/// Values that read back the same as they were written, `mdr_packets!` makes packets out of them
+#[cfg(test)]
+pub trait Examples: Sized {
+    fn examples() -> Vec<Self>;
+}
+
+/// Most a length or count byte can say
+pub const MAX_LENGTH: usize = u8::MAX as usize;
+
+/// Reads fields one after the other
+pub struct PacketReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PacketReader<'a> {
+    pub fn new(bytes: &'a [u8], position: usize) -> Self {
+        Self { bytes, position }
+    }
+
+    pub fn read<T: MdrField>(&mut self) -> Result<T, PacketError> {
+        let (value, size) = T::read(self.remaining())?;
+        self.position += size;
+        Ok(value)
+    }
+
+    pub fn skip(&mut self, count: usize) -> Result<(), PacketError> {
+        if self.remaining().len() < count {
+            return Err(PacketError::BufferTooShort);
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes.get(self.position..).unwrap_or_default()
+    }
+
+    /// How many bytes were read so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl MdrField for u8 {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let byte = bytes.first().ok_or(PacketError::BufferTooShort)?;
+        Ok((*byte, 1))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self);
+    }
+}
+
+impl MdrField for bool {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (byte, size) = u8::read(bytes)?;
+        Ok((byte != 0, size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self as u8);
+    }
+}
+
+// big endian like everything else in there
+impl MdrField for u16 {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (value, size) = <[u8; 2]>::read(bytes)?;
+        Ok((u16::from_be_bytes(value), size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_be_bytes());
+    }
+}
+
+impl MdrField for u32 {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (value, size) = <[u8; 4]>::read(bytes)?;
+        Ok((u32::from_be_bytes(value), size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_be_bytes());
+    }
+}
+
+impl<const N: usize> MdrField for [u8; N] {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let value = bytes
+            .get(..N)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(PacketError::BufferTooShort)?;
+        Ok((value, N))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(self);
+    }
+}
+
+/// A length byte then the string
+impl MdrField for String {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (raw, size) = Vec::<u8>::read(bytes)?;
+        Ok((String::from_utf8(raw)?, size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        debug_assert!(self.len() <= MAX_LENGTH, "{} bytes don't fit", self.len());
+        // cut on a char boundary so it still reads back
+        let mut len = self.len().min(MAX_LENGTH);
+        while !self.is_char_boundary(len) {
+            len -= 1;
+        }
+        bytes.push(len as u8);
+        bytes.extend(&self.as_bytes()[..len]);
+    }
+}
+
+/// A count byte then the items
+impl<T: MdrField> MdrField for Vec<T> {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let mut reader = PacketReader::new(bytes, 0);
+        let count: u8 = reader.read()?;
+        let items = (0..count)
+            .map(|_| reader.read())
+            .collect::<Result<_, _>>()?;
+        Ok((items, reader.position()))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        debug_assert!(self.len() <= MAX_LENGTH, "{} items don't fit", self.len());
+        let items = &self[..self.len().min(MAX_LENGTH)];
+        bytes.push(items.len() as u8);
+        for item in items {
+            item.write(bytes);
+        }
+    }
+}
+
+/// Only there if the packet goes on, so only makes sense last
+impl<T: MdrField> MdrField for Option<T> {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        if bytes.is_empty() {
+            return Ok((None, 0));
+        }
+        let (value, size) = T::read(bytes)?;
+        Ok((Some(value), size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        if let Some(value) = self {
+            value.write(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod examples {
+    use super::*;
+
+    impl Examples for u8 {
+        fn examples() -> Vec<Self> {
+            vec![0x00, 0x3c, 0xff]
+        }
+    }
+
+    impl Examples for bool {
+        fn examples() -> Vec<Self> {
+            vec![false, true]
+        }
+    }
+
+    impl Examples for u16 {
+        fn examples() -> Vec<Self> {
+            vec![0, 0x3d3e, u16::MAX]
+        }
+    }
+
+    impl Examples for u32 {
+        fn examples() -> Vec<Self> {
+            vec![0, 0x3c3d_3e3f, u32::MAX]
+        }
+    }
+
+    impl<const N: usize> Examples for [u8; N] {
+        fn examples() -> Vec<Self> {
+            vec![[0x00; N], [0x3e; N]]
+        }
+    }
+
+    impl Examples for String {
+        fn examples() -> Vec<Self> {
+            vec![
+                String::new(),
+                "WH-1000XM5".to_owned(),
+                "Pixel de Zoë 💀".to_owned(),
+                "x".repeat(MAX_LENGTH),
+            ]
+        }
+    }
+
+    impl<T: Examples> Examples for Vec<T> {
+        fn examples() -> Vec<Self> {
+            vec![vec![], T::examples().into_iter().take(MAX_LENGTH).collect()]
+        }
+    }
+
+    impl<T: Examples> Examples for Option<T> {
+        fn examples() -> Vec<Self> {
+            let mut examples = vec![None];
+            examples.extend(T::examples().into_iter().map(Some));
+            examples
+        }
+    }
+
+    impl Examples for MacAddress {
+        fn examples() -> Vec<Self> {
+            ["AA:BB:CC:DD:EE:FF", "00:3C:3D:3E:01:02"]
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect()
+        }
+    }
+}
+
+// the address is spelled out as AA:BB:CC:DD:EE:FF 💀
+const MAC_ADDRESS_STRING_LENGTH: usize = 17;
+
+impl MdrField for MacAddress {
+    fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let (raw, size) = <[u8; MAC_ADDRESS_STRING_LENGTH]>::read(bytes)?;
+        let s = String::from_utf8(raw.to_vec())?;
+        Ok((s.parse()?, size))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_string().as_bytes());
+    }
+}
+
+/// For `num_enum` enums that take a single byte
+macro_rules! mdr_enum_field {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $crate::protocols::wire::MdrField for $ty {
+                fn read(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+                    let byte = *bytes.first().ok_or(PacketError::BufferTooShort)?;
+                    let value = <$ty>::try_from(byte).map_err(|_| PacketError::InvalidPacketBody(byte))?;
+                    Ok((value, 1))
+                }
+
+                fn write(&self, bytes: &mut Vec<u8>) {
+                    bytes.push((*self).into());
+                }
+            }
+
+            #[cfg(test)]
+            impl $crate::protocols::wire::Examples for $ty {
+                /// Every byte that means something
+                fn examples() -> Vec<Self> {
+                    (0..=u8::MAX).filter_map(|byte| <$ty>::try_from(byte).ok()).collect()
+                }
+            }
+        )*
+    };
+}
+
+/// Generates `MDRPacketType`, `MDRPacket`, and the parser and serializer for every packet.
+///
+/// ```ignore
+/// // unit, opcode then bytes that have to be there
+/// ConnectGetProtocolInfo = 0x00 [0x00];
+/// // fields are read and written in order, see `MdrField` for how
+/// ConnectGetDeviceInfo = 0x04 { inquired_type: DeviceInfoInquiredType };
+/// // a type that lays itself out
+/// ConnectRetDeviceInfo = 0x05 (ConnectRetDeviceInfo);
+/// // bytes after the fields are written as is and skipped when reading
+/// EqEbbGetCapability = 0x50 { inquired_type: EqEbbInquiredType } [0x00];
+/// // same opcode as another packet, the bytes after it tell them apart
+/// MultipointDeviceAction = MultipointActiveDeviceSet [0x02] { ... };
+/// // only an opcode, for when every packet on it is told apart like that
+/// type SystemRetParam = 0xF7;
+/// ```
+///
+/// Packets are tried in order when parsing, anything that doesn't match ends up as `Unknown`
+macro_rules! mdr_packets {
+    (@munch $types:tt $packets:tt) => {
+        mdr_packets!(@emit $types $packets);
+    };
+    (@munch [$($types:tt)*] $packets:tt type $name:ident = $opcode:literal; $($rest:tt)*) => {
+        mdr_packets!(@munch [$($types)* $name = $opcode,] $packets $($rest)*);
+    };
+    // a new opcode
+    (@munch [$($types:tt)*] $packets:tt $(#[$meta:meta])* $name:ident = $opcode:literal $($rest:tt)*) => {
+        mdr_packets!(@shape [$($types)* $name = $opcode,] $packets [$(#[$meta])*] $name $name $($rest)*);
+    };
+    // borrowing another packet's opcode
+    (@munch $types:tt $packets:tt $(#[$meta:meta])* $name:ident = $ty:ident $($rest:tt)*) => {
+        mdr_packets!(@shape $types $packets [$(#[$meta])*] $name $ty $($rest)*);
+    };
+
+    (@shape $types:tt [$($packets:tt)*] $meta:tt $name:ident $ty:ident
+        $([$($header:expr),* $(,)?])? ; $($rest:tt)*
+    ) => {
+        mdr_packets!(@munch $types [$($packets)* {
+            $meta $name $ty [$($($header),*)?] unit [] []
+        }] $($rest)*);
+    };
+    (@shape $types:tt [$($packets:tt)*] $meta:tt $name:ident $ty:ident
+        $([$($header:expr),* $(,)?])? { $($fields:tt)* } $([$($trailer:expr),* $(,)?])? ; $($rest:tt)*
+    ) => {
+        mdr_packets!(@munch $types [$($packets)* {
+            $meta $name $ty [$($($header),*)?] fields [{ $($fields)* }] [$($($trailer),*)?]
+        }] $($rest)*);
+    };
+    (@shape $types:tt [$($packets:tt)*] $meta:tt $name:ident $ty:ident
+        $([$($header:expr),* $(,)?])? ( $inner:ty ) ; $($rest:tt)*
+    ) => {
+        mdr_packets!(@munch $types [$($packets)* {
+            $meta $name $ty [$($($header),*)?] inner [($inner)] []
+        }] $($rest)*);
+    };
+
+    (@pattern $value:ident $name:ident unit []) => {
+        MDRPacket::$name
+    };
+    (@pattern $value:ident $name:ident fields [{ $($(#[$field_meta:meta])* $field:ident : $ty:ty),* $(,)? }]) => {
+        MDRPacket::$name { $($field),* }
+    };
+    (@pattern $value:ident $name:ident inner [($ty:ty)]) => {
+        MDRPacket::$name($value)
+    };
+
+    (@read $reader:ident $name:ident unit []) => {
+        MDRPacket::$name
+    };
+    (@read $reader:ident $name:ident fields [{ $($(#[$field_meta:meta])* $field:ident : $ty:ty),* $(,)? }]) => {
+        MDRPacket::$name { $($field: $reader.read()?),* }
+    };
+    (@read $reader:ident $name:ident inner [($ty:ty)]) => {
+        MDRPacket::$name($reader.read()?)
+    };
+
+    (@write $bytes:ident $value:ident unit []) => {};
+    (@write $bytes:ident $value:ident fields [{ $($(#[$field_meta:meta])* $field:ident : $ty:ty),* $(,)? }]) => {
+        $($crate::protocols::wire::MdrField::write($field, &mut $bytes);)*
+    };
+    (@write $bytes:ident $value:ident inner [($ty:ty)]) => {
+        $crate::protocols::wire::MdrField::write($value, &mut $bytes);
+    };
+
+    (@examples $name:ident unit []) => {
+        vec![MDRPacket::$name]
+    };
+    // every example of every field shows up at least once
+    (@examples $name:ident fields [{ $($(#[$field_meta:meta])* $field:ident : $ty:ty),* $(,)? }]) => {{
+        $(let $field = <$ty as $crate::protocols::wire::Examples>::examples();)*
+        let count = [$($field.len()),*].into_iter().max().unwrap_or(1);
+        (0..count)
+            .map(|i| MDRPacket::$name { $($field: $field[i % $field.len()].clone()),* })
+            .collect::<Vec<_>>()
+    }};
+    (@examples $name:ident inner [($ty:ty)]) => {
+        <$ty as $crate::protocols::wire::Examples>::examples()
+            .into_iter()
+            .map(MDRPacket::$name)
+            .collect::<Vec<_>>()
+    };
+
+    (@emit [$($type_name:ident = $opcode:literal,)*] [$({
+        [$(#[$meta:meta])*] $name:ident $ty:ident [$($header:expr),*] $kind:ident [$($body:tt)*] [$($trailer:expr),*]
+    })*]) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+        #[repr(u8)]
+        pub enum MDRPacketType {
+            $($type_name = $opcode,)*
+        }
+
+        #[derive(Debug, Clone)]
+        pub enum MDRPacket {
+            $($(#[$meta])* $name $($body)*,)*
+            /// Anything not described yet, with everything after the opcode since its size is unknown
+            Unknown {
+                opcode: u8,
+                payload: Vec<u8>,
+            },
+        }
+
+        impl MDRPacket {
+            fn parse_packet(payload: &[u8]) -> Result<(MDRPacket, usize), PacketError> {
+                $({
+                    let header: &[u8] = &[MDRPacketType::$ty.into(), $($header),*];
+                    if payload.starts_with(header) {
+                        let mut reader = $crate::protocols::wire::PacketReader::new(payload, header.len());
+                        let packet = mdr_packets!(@read reader $name $kind [$($body)*]);
+                        let trailer: &[u8] = &[$($trailer),*];
+                        reader.skip(trailer.len())?;
+                        return Ok((packet, reader.position()));
+                    }
+                })*
+                let [opcode, rest @ ..] = payload else {
+                    return Err(PacketError::BufferTooShort);
+                };
+                Ok((
+                    MDRPacket::Unknown {
+                        opcode: *opcode,
+                        payload: rest.to_vec(),
+                    },
+                    payload.len(),
+                ))
+            }
+
+            pub fn to_bytes(&self) -> Vec<u8> {
+                match self {
+                    $(mdr_packets!(@pattern value $name $kind [$($body)*]) => {
+                        let mut bytes: Vec<u8> = vec![MDRPacketType::$ty.into(), $($header),*];
+                        mdr_packets!(@write bytes value $kind [$($body)*]);
+                        bytes.extend_from_slice(&[$($trailer),*]);
+                        bytes
+                    })*
+                    MDRPacket::Unknown { opcode, payload } => {
+                        let mut bytes = vec![*opcode];
+                        bytes.extend_from_slice(payload);
+                        bytes
+                    }
+                }
+            }
+
+            /// Every packet there is, made out of the `Examples` of its fields
+            #[cfg(test)]
+            fn examples() -> Vec<MDRPacket> {
+                let mut packets = vec![];
+                $(packets.extend(mdr_packets!(@examples $name $kind [$($body)*]));)*
+                packets
+            }
+        }
+    };
+
+    ($($packets:tt)*) => {
+        mdr_packets!(@munch [] [] $($packets)*);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written<T: MdrField>(value: &T) -> Vec<u8> {
+        let mut bytes = vec![];
+        value.write(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn lengths_fit_in_a_byte() {
+        for example in String::examples() {
+            let bytes = written(&example);
+            assert_eq!(bytes[0] as usize, example.len());
+            assert_eq!(String::read(&bytes).unwrap(), (example, bytes.len()));
+        }
+
+        let items = vec![0x3d_u8; MAX_LENGTH];
+        let bytes = written(&items);
+        assert_eq!(bytes.len(), MAX_LENGTH + 1);
+        assert_eq!(Vec::<u8>::read(&bytes).unwrap(), (items, bytes.len()));
+    }
+
+    #[test]
+    #[should_panic(expected = "256 bytes don't fit")]
+    fn too_long_string_is_a_bug() {
+        written(&"x".repeat(MAX_LENGTH + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "256 items don't fit")]
+    fn too_many_items_is_a_bug() {
+        written(&vec![0_u8; MAX_LENGTH + 1]);
+    }
+}
@@ -1,4 +1,10 @@
-use crate::ui::{components::code_block::CodeBlock, state::use_app_state};
+use crate::{
+    protocols::{
+        connection::HeadphoneAppCommand,
+        mdr::{NcAsmMode, NcAsmParam, MAX_AMBIENT_LEVEL},
+    },
+    ui::{components::code_block::CodeBlock, state::use_app_state},
+};
 use freya::prelude::*;
 
 // wtf did i just wrote
@@ -7,6 +13,16 @@ pub fn app() -> Element {
 
     let is_initialized = use_memo(move || (app_state.read().connection).is_some());
 
+    // buttons for what the headphone doesn't have are hidden
+    let noise_cancelling =
+        HeadphoneAppCommand::SetNcAsm(NcAsmParam::new(NcAsmMode::NoiseCancelling, 0, false));
+    let ambient_sound = HeadphoneAppCommand::SetNcAsm(NcAsmParam::new(
+        NcAsmMode::AmbientSound,
+        MAX_AMBIENT_LEVEL,
+        false,
+    ));
+    let nc_asm_off = HeadphoneAppCommand::SetNcAsm(NcAsmParam::new(NcAsmMode::Off, 0, false));
+
     rsx!(
         ScrollView {
             height: "100%",
@@ -29,6 +45,48 @@ pub fn app() -> Element {
                         "Connected"
                     }
 
+                    if let Some(model_name) = app_state.read().properties.model_name.clone() {
+                        label {
+                            "{model_name}"
+                        }
+                    }
+
+                    if let Some(battery) = app_state.read().properties.battery {
+                        label {
+                            "{battery.level}%"
+                        }
+                    }
+
+                    if noise_cancelling.is_supported(&app_state.read().properties) {
+                        Button {
+                            onpress: move |_| coroutine.send(noise_cancelling),
+
+                            label {
+                                "Noise cancelling"
+                            }
+                        }
+                    }
+
+                    if ambient_sound.is_supported(&app_state.read().properties) {
+                        Button {
+                            onpress: move |_| coroutine.send(ambient_sound),
+
+                            label {
+                                "Ambient sound"
+                            }
+                        }
+                    }
+
+                    if nc_asm_off.is_supported(&app_state.read().properties) {
+                        Button {
+                            onpress: move |_| coroutine.send(nc_asm_off),
+
+                            label {
+                                "Off"
+                            }
+                        }
+                    }
+
                     // Button {
                     //     onpress: move |_| service.send(ServiceMessage::SendAck),
 
@@ -4,13 +4,19 @@ use futures::StreamExt;
 
 use crate::{
     constant::SONY_SOME_SERVICE_UUID,
-    platforms::{traits::DeviceCommunication, windows::WindowsDeviceCommunication, MacAddress},
-    protocols::connection::{HeadphoneAppCommand, HeadphoneConnection},
+    platforms::{self, traits::DeviceCommunication, MacAddress, PlatformDeviceCommunication},
+    protocols::{
+        adaptive::{default_profiles_path, AdaptiveSound},
+        battery::{BatteryEvent, BatteryMonitor, BatteryMonitorConfig},
+        connection::{HeadphoneAppCommand, HeadphoneConnection},
+        properties::HeadphoneProperties,
+    },
 };
 
 #[derive(Debug)]
 pub struct AppState<D: DeviceCommunication> {
     pub connection: Option<HeadphoneConnection<D>>,
+    pub properties: HeadphoneProperties,
     pub log: Vec<Log>,
 }
 
@@ -18,6 +24,7 @@ impl<D: DeviceCommunication> AppState<D> {
     fn new() -> Self {
         Self {
             connection: None,
+            properties: HeadphoneProperties::default(),
             log: vec![],
         }
     }
@@ -35,10 +42,10 @@ pub struct Log {
 }
 
 pub fn use_app_state() -> (
-    Signal<AppState<WindowsDeviceCommunication>>,
+    Signal<AppState<PlatformDeviceCommunication>>,
     Coroutine<HeadphoneAppCommand>,
 ) {
-    let mut app_state = use_signal(|| AppState::<WindowsDeviceCommunication>::new());
+    let mut app_state = use_signal(|| AppState::<PlatformDeviceCommunication>::new());
     let mut add_log = move |m: String| {
         println!("[log] {m}");
         app_state.write().log.push(Log {
@@ -50,22 +57,43 @@ pub fn use_app_state() -> (
     // actor model as its finest,
     // this pretty much look like elm pattern tho
     let c: Coroutine<HeadphoneAppCommand> = use_coroutine(move |mut command_rx| async move {
-        let communication = WindowsDeviceCommunication::new(SONY_SOME_SERVICE_UUID)
-            .await
-            .unwrap();
+        let communication = platforms::connect(SONY_SOME_SERVICE_UUID).await.unwrap();
 
         app_state.write().connect(communication).await;
         add_log("Initialized".into());
 
+        let connection = app_state.peek().connection.clone().unwrap();
+        let c = connection.clone();
         tokio::spawn(async move {
-            command_rx;
-
+            while let Some(command) = command_rx.next().await {
+                if let Err(e) = c.send(command).await {
+                    println!("[log] {command:.?} failed: {e}");
+                }
+            }
         });
 
-        let mut rx = app_state.peek().connection.as_ref().unwrap().properties_rx();
-        while let Some(value) = rx.recv().await {
-            // *app_state.write() = value;
-            add_log(format!("{value:.?}"));
+        let mut rx = connection.properties_rx();
+        let battery = BatteryMonitor::start(connection.clone(), BatteryMonitorConfig::from_env());
+        let mut battery_rx = battery.subscribe();
+        let _adaptive = AdaptiveSound::start(connection.clone(), default_profiles_path());
+        let c = connection.clone();
+        tokio::spawn(async move { c.refresh().await });
+
+        loop {
+            tokio::select! {
+                Some(value) = rx.recv() => {
+                    add_log(format!("{:.?} changed", value.changed));
+                    app_state.write().properties = value.properties;
+                }
+                Ok(BatteryEvent::Low { level, .. }) = battery_rx.recv() => {
+                    add_log(format!("Battery low: {level}%"));
+                }
+                disconnect = connection.disconnected() => {
+                    add_log(format!("Disconnected ({disconnect:?})"));
+                    break;
+                }
+                else => break,
+            }
         }
     });
 
@@ -0,0 +1,225 @@
+#![cfg(unix)]
+
+// the daemon over a real socket, with emulators standing in for the headphone
+
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixListener, UnixStream,
+    },
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep, timeout, Instant},
+};
+use xm5_thing::{
+    daemon::{
+        keep_connected,
+        protocol::{DaemonRequest, DaemonResponse},
+        serve,
+    },
+    platforms::{
+        emulator::{EmulatedDeviceCommunication, EmulatedHeadphone},
+        traits::DeviceCommunication,
+    },
+    protocols::{
+        battery::BatteryMonitorConfig, connection::HeadphoneAppCommand, mdr::MDRPacket,
+        properties::HeadphoneProperty,
+    },
+};
+
+struct TestDaemon {
+    path: PathBuf,
+    /// Only finishes if something went wrong
+    connected: JoinHandle<Result<()>>,
+    serving: JoinHandle<Result<()>>,
+}
+
+impl TestDaemon {
+    /// Every connect takes the next emulator, there's no headphone once they run out
+    fn start(name: &str, emulators: Vec<EmulatedDeviceCommunication>) -> Self {
+        let path = std::env::temp_dir().join(format!("xm5-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let (sessions_tx, sessions_rx) = watch::channel(None);
+        let emulators = Arc::new(Mutex::new(VecDeque::from(emulators)));
+        let connect = move || {
+            let next = emulators.lock().unwrap().pop_front();
+            async move { next.ok_or_else(|| anyhow!("No headphone")) }
+        };
+        let config = BatteryMonitorConfig {
+            history_path: None,
+            ..Default::default()
+        };
+
+        Self {
+            path,
+            connected: tokio::spawn(keep_connected(connect, config, sessions_tx)),
+            serving: tokio::spawn(serve(listener, sessions_rx)),
+        }
+    }
+
+    async fn client(&self) -> Client {
+        let (reader, writer) = UnixStream::connect(&self.path).await.unwrap().into_split();
+        Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+}
+
+impl Drop for TestDaemon {
+    fn drop(&mut self) {
+        self.connected.abort();
+        self.serving.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn send(&mut self, request: DaemonRequest) {
+        let mut line = serde_json::to_vec(&request).unwrap();
+        line.push(b'\n');
+        self.writer.write_all(&line).await.unwrap();
+    }
+
+    async fn next(&mut self, within: Duration) -> Option<DaemonResponse> {
+        let line = timeout(within, self.lines.next_line()).await.ok()?;
+        Some(serde_json::from_str(&line.unwrap()?).unwrap())
+    }
+
+    /// Everything until nothing comes for a bit
+    async fn drain(&mut self) -> Vec<DaemonResponse> {
+        let mut responses = vec![];
+        while let Some(response) = self.next(Duration::from_millis(300)).await {
+            responses.push(response);
+        }
+        responses
+    }
+
+    /// Waits for the properties to look like `ready`, the daemon connects and refreshes on its own
+    async fn wait_for_properties(&mut self, ready: impl Fn(&DaemonResponse) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            self.send(DaemonRequest::Properties).await;
+            let response = self.next(Duration::from_secs(1)).await.unwrap();
+            if ready(&response) {
+                return;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Properties never got there");
+    }
+}
+
+fn model_name_is(name: &str) -> impl Fn(&DaemonResponse) -> bool + '_ {
+    move |response| {
+        matches!(
+            response,
+            DaemonResponse::Properties { properties }
+                if properties.model_name.as_deref() == Some(name)
+        )
+    }
+}
+
+fn count(responses: &[DaemonResponse], f: impl Fn(&DaemonResponse) -> bool) -> usize {
+    responses.iter().filter(|response| f(response)).count()
+}
+
+#[tokio::test]
+async fn subscribing_twice_sends_everything_once() {
+    let emulator = EmulatedDeviceCommunication::new(EmulatedHeadphone::default());
+    let daemon = TestDaemon::start("subscribe", vec![emulator.clone()]);
+    let mut client = daemon.client().await;
+    client
+        .wait_for_properties(model_name_is("WH-1000XM5"))
+        .await;
+
+    client.send(DaemonRequest::Subscribe).await;
+    client.send(DaemonRequest::Subscribe).await;
+    let responses = client.drain().await;
+    let snapshots = count(&responses, |r| {
+        matches!(r, DaemonResponse::Properties { .. })
+    });
+    assert_eq!(snapshots, 1);
+
+    emulator.notify(MDRPacket::VolumeChangedNotify { volume: 3 });
+    let responses = client.drain().await;
+    let volume_changes = count(
+        &responses,
+        |r| matches!(r, DaemonResponse::Changed { changed, .. } if changed.contains(&HeadphoneProperty::Volume)),
+    );
+    assert_eq!(volume_changes, 1);
+}
+
+#[tokio::test]
+async fn reconnects_after_losing_the_headphone() {
+    let first = EmulatedDeviceCommunication::new(EmulatedHeadphone::default());
+    let second = EmulatedDeviceCommunication::new(EmulatedHeadphone {
+        model_name: "Second".to_owned(),
+        ..Default::default()
+    });
+    let daemon = TestDaemon::start("reconnect", vec![first.clone(), second]);
+    let mut subscriber = daemon.client().await;
+    subscriber.send(DaemonRequest::Subscribe).await;
+    let mut client = daemon.client().await;
+    client
+        .wait_for_properties(model_name_is("WH-1000XM5"))
+        .await;
+
+    // out of range, as far as the daemon can tell
+    first.close();
+    client
+        .wait_for_properties(|response| matches!(response, DaemonResponse::Error { .. }))
+        .await;
+
+    // same socket, same clients, new headphone
+    client.wait_for_properties(model_name_is("Second")).await;
+    let responses = subscriber.drain().await;
+    let snapshots = count(&responses, |r| {
+        matches!(r, DaemonResponse::Properties { .. })
+    });
+    assert_eq!(snapshots, 2, "one per session");
+    assert!(!daemon.connected.is_finished());
+}
+
+#[tokio::test]
+async fn powered_off_headphone_is_waited_for() {
+    let first = EmulatedDeviceCommunication::new(EmulatedHeadphone::default());
+    let second = EmulatedDeviceCommunication::new(EmulatedHeadphone {
+        model_name: "Second".to_owned(),
+        ..Default::default()
+    });
+    let daemon = TestDaemon::start("power-off", vec![first, second]);
+    let mut client = daemon.client().await;
+    client
+        .wait_for_properties(model_name_is("WH-1000XM5"))
+        .await;
+
+    // on purpose, which isn't a reason to stop serving
+    client
+        .send(DaemonRequest::Command {
+            command: HeadphoneAppCommand::PowerOff,
+        })
+        .await;
+    let response = client.next(Duration::from_secs(1)).await;
+    assert!(matches!(response, Some(DaemonResponse::Ok)));
+
+    // turned back on
+    client.wait_for_properties(model_name_is("Second")).await;
+    assert!(!daemon.connected.is_finished());
+}
@@ -0,0 +1,303 @@
+// the whole stack against the emulator: link, connection, properties
+
+use std::time::Duration;
+
+use tokio::{sync::broadcast, time::timeout};
+use xm5_thing::{
+    platforms::{
+        emulator::{EmulatedDeviceCommunication, EmulatedHeadphone, EmulatorFaults},
+        traits::DeviceCommunication,
+    },
+    protocols::{
+        battery::{BatteryMonitor, BatteryMonitorConfig},
+        connection::{Disconnect, HeadphoneAppCommand, HeadphoneConnection, RequestError},
+        link::LinkError,
+        mdr::{
+            BatteryInquiredType, CommonRetBatteryLevel, ConnectRetDeviceInfo,
+            DeviceInfoInquiredType, FunctionType, MDRPacket, NcAsmMode, NcAsmParam,
+        },
+    },
+};
+
+type Connection = HeadphoneConnection<EmulatedDeviceCommunication>;
+
+async fn connect(headphone: EmulatedHeadphone) -> (EmulatedDeviceCommunication, Connection) {
+    let emulator = EmulatedDeviceCommunication::new(headphone);
+    let connection = HeadphoneConnection::new(emulator.clone()).await;
+    (emulator, connection)
+}
+
+async fn refreshed(headphone: EmulatedHeadphone) -> (EmulatedDeviceCommunication, Connection) {
+    let (emulator, connection) = connect(headphone).await;
+    // commands check the supported functions
+    connection.refresh().await;
+    (emulator, connection)
+}
+
+/// Everything that comes in until nothing has for `quiet`
+async fn collect(rx: &mut broadcast::Receiver<MDRPacket>, quiet: Duration) -> Vec<MDRPacket> {
+    let mut packets = vec![];
+    while let Ok(Ok(packet)) = timeout(quiet, rx.recv()).await {
+        packets.push(packet);
+    }
+    packets
+}
+
+fn model_name_query() -> MDRPacket {
+    MDRPacket::ConnectGetDeviceInfo {
+        inquired_type: DeviceInfoInquiredType::ModelName,
+    }
+}
+
+#[tokio::test]
+async fn request_gets_its_reply() {
+    let (_, connection) = connect(EmulatedHeadphone::default()).await;
+
+    let reply = connection.request(model_name_query()).await.unwrap();
+    assert!(matches!(
+        reply,
+        MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::ModelName(name)) if name == "WH-1000XM5"
+    ));
+
+    let reply = connection
+        .request(MDRPacket::CommonGetBatteryLevel {
+            inquired_type: BatteryInquiredType::Battery,
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        reply,
+        MDRPacket::CommonRetBatteryLevel(CommonRetBatteryLevel::Battery {
+            level: 70,
+            is_charging: false,
+        })
+    ));
+}
+
+#[tokio::test]
+async fn refresh_fills_the_properties() {
+    let headphone = EmulatedHeadphone {
+        fw_version: "1.2.3".to_owned(),
+        battery: (42, true),
+        ..Default::default()
+    };
+    let (_, connection) = connect(headphone).await;
+
+    connection.refresh().await;
+
+    let properties = connection.properties();
+    assert_eq!(properties.model_name.as_deref(), Some("WH-1000XM5"));
+    assert_eq!(properties.fw_version.as_deref(), Some("1.2.3"));
+    assert_eq!(
+        properties.battery.map(|b| (b.level, b.is_charging)),
+        Some((42, true))
+    );
+    assert_eq!(
+        properties.nc_asm.map(|nc| nc.mode),
+        Some(NcAsmMode::NoiseCancelling)
+    );
+}
+
+#[tokio::test]
+async fn lost_ack_is_resent_once_and_handled_once() {
+    let (emulator, connection) = connect(EmulatedHeadphone::default()).await;
+    let mut packets = connection.subscribe();
+
+    emulator.set_faults(EmulatorFaults {
+        drop_acks: 1,
+        ..Default::default()
+    });
+    let param = NcAsmParam::new(NcAsmMode::AmbientSound, 10, true);
+    connection
+        .send_packet(MDRPacket::NcAsmSetParam(param))
+        .await
+        .unwrap();
+
+    // past the retransmission, which the emulator acks but doesn't act on again
+    let notifications = collect(&mut packets, Duration::from_millis(800)).await;
+    assert!(matches!(
+        notifications.as_slice(),
+        [MDRPacket::NcAsmNtfyParam(notified)] if *notified == param
+    ));
+    assert_eq!(emulator.headphone().nc_asm, param);
+}
+
+#[tokio::test]
+async fn corrupted_ack_is_resent() {
+    let (emulator, connection) = connect(EmulatedHeadphone::default()).await;
+
+    // the ack is the first thing it sends
+    emulator.set_faults(EmulatorFaults {
+        bad_checksums: 1,
+        ..Default::default()
+    });
+    let reply = connection.request(model_name_query()).await.unwrap();
+    assert!(matches!(
+        reply,
+        MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::ModelName(_))
+    ));
+}
+
+#[tokio::test]
+async fn corrupted_reply_times_out() {
+    let (emulator, connection) = connect(EmulatedHeadphone::default()).await;
+
+    // the ack and then the reply
+    emulator.set_faults(EmulatorFaults {
+        bad_checksums: 2,
+        ..Default::default()
+    });
+    let result = connection
+        .request_with_timeout(model_name_query(), Duration::from_millis(300))
+        .await;
+    assert!(matches!(result, Err(RequestError::Timeout)));
+
+    // only that one was lost
+    assert!(connection.request(model_name_query()).await.is_ok());
+}
+
+#[tokio::test]
+async fn slow_reply_times_out() {
+    let (emulator, connection) = connect(EmulatedHeadphone::default()).await;
+
+    emulator.set_faults(EmulatorFaults {
+        reply_delay: Duration::from_millis(400),
+        ..Default::default()
+    });
+    let result = connection
+        .request_with_timeout(model_name_query(), Duration::from_millis(100))
+        .await;
+    assert!(matches!(result, Err(RequestError::Timeout)));
+
+    // the late reply doesn't get mistaken for the answer to something else
+    emulator.set_faults(EmulatorFaults::default());
+    let reply = connection
+        .request(MDRPacket::CommonGetBatteryLevel {
+            inquired_type: BatteryInquiredType::Battery,
+        })
+        .await
+        .unwrap();
+    assert!(matches!(reply, MDRPacket::CommonRetBatteryLevel(_)));
+}
+
+#[tokio::test]
+async fn next_request_works_after_every_ack_was_lost() {
+    let (emulator, connection) = connect(EmulatedHeadphone::default()).await;
+
+    // the first send and all three retries, the emulator still takes the first one
+    emulator.set_faults(EmulatorFaults {
+        drop_acks: 4,
+        ..Default::default()
+    });
+    let param = NcAsmParam::new(NcAsmMode::AmbientSound, 5, false);
+    let result = connection
+        .send_packet(MDRPacket::NcAsmSetParam(param))
+        .await;
+    assert!(matches!(
+        result,
+        Err(RequestError::Link(LinkError::NoAck { attempts: 4 }))
+    ));
+    assert_eq!(emulator.headphone().nc_asm, param);
+
+    // used to go out with the number the emulator just took and get dropped as a retransmission
+    let reply = connection.request(model_name_query()).await.unwrap();
+    assert!(matches!(
+        reply,
+        MDRPacket::ConnectRetDeviceInfo(ConnectRetDeviceInfo::ModelName(_))
+    ));
+}
+
+#[tokio::test]
+async fn close_fails_pending_requests_right_away() {
+    let (emulator, connection) = connect(EmulatedHeadphone::default()).await;
+
+    emulator.set_faults(EmulatorFaults {
+        reply_delay: Duration::from_secs(1),
+        ..Default::default()
+    });
+    let request = tokio::spawn({
+        let connection = connection.clone();
+        async move { connection.request(model_name_query()).await }
+    });
+    // acked and waiting on the reply
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    connection.close();
+
+    // well before the request timeout
+    let result = timeout(Duration::from_millis(500), request)
+        .await
+        .expect("still waiting after close")
+        .unwrap();
+    assert!(matches!(result, Err(RequestError::Link(LinkError::Closed))));
+
+    // and so does anything after
+    let result = connection.request(model_name_query()).await;
+    assert!(matches!(result, Err(RequestError::Link(LinkError::Closed))));
+}
+
+#[tokio::test]
+async fn power_off_is_an_expected_disconnect() {
+    let (_, connection) = refreshed(EmulatedHeadphone::default()).await;
+
+    connection
+        .send(HeadphoneAppCommand::PowerOff)
+        .await
+        .unwrap();
+    let disconnect = timeout(Duration::from_secs(1), connection.disconnected()).await;
+    assert_eq!(disconnect, Ok(Disconnect::Expected));
+}
+
+#[tokio::test]
+async fn unacked_power_off_is_an_error() {
+    let (emulator, connection) = refreshed(EmulatedHeadphone::default()).await;
+
+    // the first send and all three retries
+    emulator.set_faults(EmulatorFaults {
+        lose_frames: 4,
+        ..Default::default()
+    });
+    let result = connection.send(HeadphoneAppCommand::PowerOff).await;
+    assert!(matches!(
+        result,
+        Err(RequestError::Link(LinkError::NoAck { attempts: 4 }))
+    ));
+
+    // still connected, and losing it now isn't on us
+    let disconnect = timeout(Duration::from_millis(200), connection.disconnected()).await;
+    assert!(disconnect.is_err());
+    emulator.close();
+    assert_eq!(connection.disconnected().await, Disconnect::Lost);
+}
+
+#[tokio::test]
+async fn battery_monitor_polls_the_cradle() {
+    let mut headphone = EmulatedHeadphone {
+        cradle_battery: Some((80, true)),
+        ..Default::default()
+    };
+    headphone
+        .supported_functions
+        .0
+        .insert(FunctionType::CradleBatteryLevel);
+    let (emulator, connection) = refreshed(headphone).await;
+    let cradle = |connection: &Connection| connection.properties().cradle_battery.map(|b| b.level);
+    assert_eq!(cradle(&connection), Some(80));
+
+    // the cradle doesn't say when it changes
+    emulator.update(|headphone| headphone.cradle_battery = Some((55, false)));
+    let _monitor = BatteryMonitor::start(
+        connection.clone(),
+        BatteryMonitorConfig {
+            poll_interval: Duration::from_millis(50),
+            history_path: None,
+            ..Default::default()
+        },
+    );
+    timeout(Duration::from_secs(1), async {
+        while cradle(&connection) != Some(55) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("cradle battery never polled");
+}
@@ -0,0 +1,341 @@
+// one feature at a time, through commands and properties like the tools use them
+
+use std::time::Duration;
+
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{sleep, timeout},
+};
+use xm5_thing::{
+    platforms::emulator::{EmulatedDeviceCommunication, EmulatedHeadphone},
+    protocols::{
+        adaptive::{ActivityProfile, AdaptiveProfiles, AdaptiveSound},
+        connection::{HeadphoneAppCommand, HeadphoneConnection, RequestError},
+        mdr::{
+            AssignableKey, AssignablePreset, AudioCodec, ConnectionMode, DetectedActivity,
+            EqPreset, FunctionType, KeyAssignment, MDRPacket, NcAsmInquiredType, NcAsmMode,
+            NcAsmParam, SmartTalkingConfig, SmartTalkingSensitivity, SmartTalkingTimeout,
+        },
+        properties::HeadphoneProperties,
+        wearing::{wear_events, WearEvent},
+    },
+};
+
+type Connection = HeadphoneConnection<EmulatedDeviceCommunication>;
+
+async fn refreshed(headphone: EmulatedHeadphone) -> (EmulatedDeviceCommunication, Connection) {
+    let emulator = EmulatedDeviceCommunication::new(headphone);
+    let connection = HeadphoneConnection::new(emulator.clone()).await;
+    connection.refresh().await;
+    (emulator, connection)
+}
+
+/// Notifications can come in after the ack, so commands don't show up right away
+async fn wait_for(connection: &Connection, done: impl Fn(&HeadphoneProperties) -> bool) {
+    let waited = timeout(Duration::from_secs(1), async {
+        while !done(&connection.properties()) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    if waited.is_err() {
+        panic!("Never got there: {:#?}", connection.properties());
+    }
+}
+
+async fn next_event(events: &mut Receiver<WearEvent>) -> Option<WearEvent> {
+    timeout(Duration::from_millis(300), events.recv())
+        .await
+        .ok()
+        .flatten()
+}
+
+fn without(function: FunctionType) -> EmulatedHeadphone {
+    let mut headphone = EmulatedHeadphone::default();
+    headphone.supported_functions.0.remove(&function);
+    headphone
+}
+
+#[tokio::test]
+async fn speak_to_chat() {
+    let (emulator, connection) = refreshed(EmulatedHeadphone::default()).await;
+    let properties = connection.properties();
+    assert_eq!(properties.speak_to_chat, Some(false));
+    assert_eq!(
+        properties.speak_to_chat_config,
+        Some(emulator.headphone().speak_to_chat_config)
+    );
+
+    connection
+        .send(HeadphoneAppCommand::EnableSpeakToChat(true))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.speak_to_chat == Some(true)).await;
+    assert!(emulator.headphone().speak_to_chat);
+
+    let config = SmartTalkingConfig {
+        sensitivity: SmartTalkingSensitivity::High,
+        voice_passthrough: false,
+        timeout: SmartTalkingTimeout::Off,
+    };
+    connection
+        .send(HeadphoneAppCommand::SetSpeakToChat(config))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.speak_to_chat_config == Some(config)).await;
+    assert_eq!(emulator.headphone().speak_to_chat_config, config);
+
+    // sessions are only ever notified
+    assert_eq!(connection.properties().talking, None);
+    emulator.notify(MDRPacket::SmartTalkingNtfyStatus { talking: true });
+    wait_for(&connection, |p| p.talking == Some(true)).await;
+    emulator.notify(MDRPacket::SmartTalkingNtfyStatus { talking: false });
+    wait_for(&connection, |p| p.talking == Some(false)).await;
+
+    let (_, connection) = refreshed(without(FunctionType::SmartTalkingMode)).await;
+    assert_eq!(connection.properties().speak_to_chat, None);
+    let result = connection
+        .send(HeadphoneAppCommand::EnableSpeakToChat(false))
+        .await;
+    assert_eq!(result, Err(RequestError::Unsupported));
+}
+
+#[tokio::test]
+async fn nc_asm_is_set_the_way_the_headphone_does_it() {
+    let halves = [
+        (FunctionType::NoiseCancelling, NcAsmMode::NoiseCancelling),
+        (FunctionType::AmbientSoundMode, NcAsmMode::AmbientSound),
+    ];
+    for (function, mode) in halves {
+        let mut headphone = without(FunctionType::NoiseCancellingAndAmbientSoundMode);
+        headphone.supported_functions.0.insert(function);
+        let (emulator, connection) = refreshed(headphone).await;
+
+        // `new` always goes for both
+        let param = NcAsmParam::new(mode, 5, false);
+        connection
+            .send(HeadphoneAppCommand::SetNcAsm(param))
+            .await
+            .unwrap();
+        wait_for(&connection, |p| p.nc_asm.map(|nc| nc.mode) == Some(mode)).await;
+        let expected = match function {
+            FunctionType::NoiseCancelling => NcAsmInquiredType::NoiseCancelling,
+            _ => NcAsmInquiredType::AmbientSoundMode,
+        };
+        assert_eq!(emulator.headphone().nc_asm.inquired_type, expected);
+    }
+}
+
+#[tokio::test]
+async fn eq_presets_are_the_ones_it_lists() {
+    let (emulator, connection) = refreshed(EmulatedHeadphone::default()).await;
+
+    // not on the list
+    let result = connection
+        .send(HeadphoneAppCommand::SetEqPreset(EqPreset::Rock))
+        .await;
+    assert_eq!(result, Err(RequestError::UnavailableOption));
+    assert_eq!(emulator.headphone().eq.preset, EqPreset::Off);
+
+    connection
+        .send(HeadphoneAppCommand::SetEqPreset(EqPreset::Bright))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| {
+        p.eq.as_ref().map(|eq| eq.preset) == Some(EqPreset::Bright)
+    })
+    .await;
+    assert_eq!(emulator.headphone().eq.preset, EqPreset::Bright);
+}
+
+#[tokio::test]
+async fn adaptive_sound_applies_each_activity() {
+    let (emulator, connection) = refreshed(EmulatedHeadphone::default()).await;
+    let path = std::env::temp_dir().join(format!("xm5-{}-adaptive.json", std::process::id()));
+    let mut profiles = AdaptiveProfiles::default();
+    let walking = ActivityProfile::new(NcAsmMode::AmbientSound, 7, true);
+    profiles.set(DetectedActivity::Walking, walking);
+    profiles.save(&path).unwrap();
+    let _adaptive = AdaptiveSound::start(connection.clone(), path.clone());
+    let detected = |activity| emulator.notify(MDRPacket::AutoNcAsmNtfyStatus { activity });
+
+    connection
+        .send(HeadphoneAppCommand::EnableAutoNcAsm(true))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.auto_nc_asm == Some(true)).await;
+    detected(DetectedActivity::Walking);
+    wait_for(&connection, |p| {
+        p.nc_asm.map(|nc| nc.ambient_level) == Some(7)
+    })
+    .await;
+    let nc_asm = emulator.headphone().nc_asm;
+    assert_eq!(
+        ActivityProfile::new(nc_asm.mode, nc_asm.ambient_level, nc_asm.voice_passthrough),
+        walking
+    );
+
+    // edited while running
+    let running = ActivityProfile::new(NcAsmMode::AmbientSound, 3, false);
+    profiles.set(DetectedActivity::Running, running);
+    profiles.save(&path).unwrap();
+    detected(DetectedActivity::Running);
+    wait_for(&connection, |p| {
+        p.nc_asm.map(|nc| nc.ambient_level) == Some(3)
+    })
+    .await;
+    assert_eq!(emulator.headphone().nc_asm.ambient_level, 3);
+
+    // off means the activity is left alone
+    connection
+        .send(HeadphoneAppCommand::EnableAutoNcAsm(false))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.auto_nc_asm == Some(false)).await;
+    detected(DetectedActivity::Staying);
+    wait_for(&connection, |p| {
+        p.activity == Some(DetectedActivity::Staying)
+    })
+    .await;
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(emulator.headphone().nc_asm.mode, NcAsmMode::AmbientSound);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn dsee_follows_the_codec() {
+    let (emulator, connection) = refreshed(EmulatedHeadphone::default()).await;
+    let properties = connection.properties();
+    assert_eq!(properties.codec, Some(AudioCodec::Ldac));
+    assert_eq!(properties.upscaling, Some(false));
+    assert_eq!(
+        properties.connection_mode,
+        Some(ConnectionMode::SoundQuality)
+    );
+    let indicator = |connection: &Connection| {
+        connection
+            .properties()
+            .upscaling_indicator
+            .map(|indicator| indicator.active)
+    };
+    assert_eq!(indicator(&connection), Some(false));
+
+    // nothing to upscale on ldac
+    connection
+        .send(HeadphoneAppCommand::EnableUpscaling(true))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.upscaling == Some(true)).await;
+    assert!(emulator.headphone().upscaling);
+    assert_eq!(indicator(&connection), Some(false));
+
+    // stable drops to aac, which does get upscaled
+    connection
+        .send(HeadphoneAppCommand::SetConnectionMode(
+            ConnectionMode::StableConnection,
+        ))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| {
+        p.upscaling_indicator
+            .is_some_and(|indicator| indicator.active)
+    })
+    .await;
+    let properties = connection.properties();
+    assert_eq!(
+        properties.connection_mode,
+        Some(ConnectionMode::StableConnection)
+    );
+    assert_eq!(properties.codec, Some(AudioCodec::Aac));
+    assert_eq!(emulator.headphone().codec, AudioCodec::Aac);
+
+    connection
+        .send(HeadphoneAppCommand::SetConnectionMode(
+            ConnectionMode::SoundQuality,
+        ))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.codec == Some(AudioCodec::Ldac)).await;
+    wait_for(&connection, |p| {
+        p.upscaling_indicator
+            .is_some_and(|indicator| !indicator.active)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn wear_events_are_only_changes() {
+    let emulator = EmulatedDeviceCommunication::new(EmulatedHeadphone::default());
+    let connection = HeadphoneConnection::new(emulator.clone()).await;
+    let mut events = wear_events(&connection);
+
+    // being on when we connect isn't putting it on
+    connection.refresh().await;
+    assert_eq!(connection.properties().wearing, Some(true));
+    assert_eq!(next_event(&mut events).await, None);
+
+    let wearing = |wearing| emulator.notify(MDRPacket::WearingNtfyStatus { wearing });
+    wearing(false);
+    assert_eq!(next_event(&mut events).await, Some(WearEvent::TakenOff));
+    wearing(false);
+    assert_eq!(next_event(&mut events).await, None);
+    wearing(true);
+    assert_eq!(next_event(&mut events).await, Some(WearEvent::PutOn));
+
+    // the events don't depend on it
+    connection
+        .send(HeadphoneAppCommand::EnableControlByWearing(false))
+        .await
+        .unwrap();
+    wait_for(&connection, |p| p.control_by_wearing == Some(false)).await;
+    assert!(!emulator.headphone().control_by_wearing);
+    wearing(false);
+    assert_eq!(next_event(&mut events).await, Some(WearEvent::TakenOff));
+}
+
+#[tokio::test]
+async fn assignments_stick_to_the_offered_presets() {
+    let (emulator, connection) = refreshed(EmulatedHeadphone::default()).await;
+    let before = emulator.headphone().assignments;
+    assert_eq!(connection.properties().assignments, Some(before.clone()));
+
+    // the touch panel only does playback
+    let result = connection
+        .send(HeadphoneAppCommand::SetAssignment(
+            AssignableKey::TouchPanel,
+            AssignablePreset::GoogleAssistant,
+        ))
+        .await;
+    assert_eq!(result, Err(RequestError::UnavailableOption));
+    assert_eq!(emulator.headphone().assignments, before);
+
+    connection
+        .send(HeadphoneAppCommand::SetAssignment(
+            AssignableKey::NcAmbButton,
+            AssignablePreset::GoogleAssistant,
+        ))
+        .await
+        .unwrap();
+    let expected = vec![
+        KeyAssignment {
+            key: AssignableKey::NcAmbButton,
+            preset: AssignablePreset::GoogleAssistant,
+        },
+        KeyAssignment {
+            key: AssignableKey::TouchPanel,
+            preset: AssignablePreset::PlaybackControl,
+        },
+    ];
+    wait_for(&connection, |p| p.assignments.as_ref() == Some(&expected)).await;
+    assert_eq!(emulator.headphone().assignments, expected);
+
+    let (_, connection) = refreshed(without(FunctionType::AssignableSettings)).await;
+    let result = connection
+        .send(HeadphoneAppCommand::SetAssignment(
+            AssignableKey::NcAmbButton,
+            AssignablePreset::NoFunction,
+        ))
+        .await;
+    assert_eq!(result, Err(RequestError::Unsupported));
+}